@@ -0,0 +1,72 @@
+use aws_credential_types::Credentials;
+use std::collections::HashMap;
+use tracing::info;
+
+use crate::state::Env;
+
+// Named credential sets for models that live in other AWS accounts.
+// MODEL_CREDENTIALS="meta.llama3-70b-instruct-v1:0=llama,..." maps a model ID to a set;
+// set NAME reads AWS_ACCESS_KEY_ID_<NAME>, AWS_SECRET_ACCESS_KEY_<NAME> and the
+// optional AWS_SESSION_TOKEN_<NAME>. Unmapped models use the default credentials
+pub struct CredentialSets {
+    sets: HashMap<String, Credentials>,
+    models: HashMap<String, String>,
+}
+
+impl CredentialSets {
+    pub fn from_env(env: &Env) -> Result<Option<Self>, String> {
+        let Ok(raw) = env.var("MODEL_CREDENTIALS") else {
+            return Ok(None);
+        };
+
+        let mut sets = HashMap::new();
+        let mut models = HashMap::new();
+        for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (model_id, name) = entry
+                .rsplit_once('=')
+                .ok_or_else(|| format!("MODEL_CREDENTIALS entry '{}' must be model=set", entry))?;
+            let name = name.trim().to_uppercase();
+            if !sets.contains_key(&name) {
+                sets.insert(name.clone(), credential_set(env, &name)?);
+            }
+            models.insert(model_id.trim().to_string(), name);
+        }
+
+        info!(
+            "🔑 {} credential set(s) for {} model(s) from MODEL_CREDENTIALS",
+            sets.len(),
+            models.len()
+        );
+        Ok(Some(Self { sets, models }))
+    }
+
+    // Credentials for `model_id` when it is mapped to a named set
+    pub fn for_model(&self, model_id: &str) -> Option<Credentials> {
+        let name = self.models.get(model_id)?;
+        self.sets.get(name).cloned()
+    }
+}
+
+fn credential_set(env: &Env, name: &str) -> Result<Credentials, String> {
+    let var = |prefix: &str| env.var(&format!("{}_{}", prefix, name)).ok();
+    let access_key = var("AWS_ACCESS_KEY_ID").ok_or_else(|| {
+        format!(
+            "AWS_ACCESS_KEY_ID_{} must be set for MODEL_CREDENTIALS",
+            name
+        )
+    })?;
+    let secret_key = var("AWS_SECRET_ACCESS_KEY").ok_or_else(|| {
+        format!(
+            "AWS_SECRET_ACCESS_KEY_{} must be set for MODEL_CREDENTIALS",
+            name
+        )
+    })?;
+
+    Ok(Credentials::new(
+        access_key,
+        secret_key,
+        var("AWS_SESSION_TOKEN"),
+        None,
+        "model-credential-set",
+    ))
+}
@@ -0,0 +1,144 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use crate::{
+    state::AppState,
+    transform::{
+        bedrock_chunk_to_openai, bedrock_to_openai, cohere_chunk_to_openai, cohere_to_openai,
+        converse_chunk_to_openai, converse_to_openai, openai_to_bedrock, openai_to_cohere,
+        openai_to_converse, openai_to_titan, titan_chunk_to_openai, titan_to_openai, ModelFamily,
+        OpenAIRequest, OpenAIResponse, OpenAIStreamResponse, StreamState,
+    },
+};
+
+// Request/response conversion between the OpenAI schema and one Bedrock model family.
+// A new family implements this and registers itself in ADAPTERS; the handlers only
+// ever go through `ModelFamily::adapter`
+pub trait ModelAdapter: Send + Sync {
+    // OpenAI chat request -> Bedrock request body
+    fn to_bedrock(&self, req: &OpenAIRequest, model_id: &str, state: &AppState) -> Value;
+
+    // Non-streaming Bedrock response -> OpenAI chat completion, reported as `model`.
+    // Named for the direction of conversion, not a constructor
+    #[allow(clippy::wrong_self_convention)]
+    fn from_bedrock(&self, resp: &Value, model: &str, state: &AppState) -> OpenAIResponse;
+
+    // One decoded stream event -> the chunks to send for it (often none)
+    fn chunk_to_openai(
+        &self,
+        chunk: &Value,
+        stream_state: &mut StreamState,
+    ) -> Vec<OpenAIStreamResponse>;
+}
+
+// Anthropic Messages API via InvokeModel
+pub struct AnthropicAdapter;
+
+impl ModelAdapter for AnthropicAdapter {
+    fn to_bedrock(&self, req: &OpenAIRequest, model_id: &str, state: &AppState) -> Value {
+        openai_to_bedrock(req, model_id, state)
+    }
+
+    fn from_bedrock(&self, resp: &Value, model: &str, state: &AppState) -> OpenAIResponse {
+        bedrock_to_openai(resp, model, state)
+    }
+
+    fn chunk_to_openai(
+        &self,
+        chunk: &Value,
+        stream_state: &mut StreamState,
+    ) -> Vec<OpenAIStreamResponse> {
+        bedrock_chunk_to_openai(chunk, stream_state)
+            .into_iter()
+            .collect()
+    }
+}
+
+// Amazon Titan Text via InvokeModel
+pub struct TitanAdapter;
+
+impl ModelAdapter for TitanAdapter {
+    fn to_bedrock(&self, req: &OpenAIRequest, _model_id: &str, state: &AppState) -> Value {
+        openai_to_titan(req, state)
+    }
+
+    fn from_bedrock(&self, resp: &Value, model: &str, state: &AppState) -> OpenAIResponse {
+        titan_to_openai(resp, model, state)
+    }
+
+    fn chunk_to_openai(
+        &self,
+        chunk: &Value,
+        stream_state: &mut StreamState,
+    ) -> Vec<OpenAIStreamResponse> {
+        titan_chunk_to_openai(chunk, stream_state)
+            .into_iter()
+            .collect()
+    }
+}
+
+// Cohere Command text models via InvokeModel
+pub struct CohereAdapter;
+
+impl ModelAdapter for CohereAdapter {
+    fn to_bedrock(&self, req: &OpenAIRequest, _model_id: &str, state: &AppState) -> Value {
+        openai_to_cohere(req, state)
+    }
+
+    fn from_bedrock(&self, resp: &Value, model: &str, state: &AppState) -> OpenAIResponse {
+        cohere_to_openai(resp, model, state)
+    }
+
+    fn chunk_to_openai(
+        &self,
+        chunk: &Value,
+        stream_state: &mut StreamState,
+    ) -> Vec<OpenAIStreamResponse> {
+        cohere_chunk_to_openai(chunk, stream_state)
+            .into_iter()
+            .collect()
+    }
+}
+
+// Any model through the Converse API (USE_CONVERSE_API=true)
+pub struct ConverseAdapter;
+
+impl ModelAdapter for ConverseAdapter {
+    fn to_bedrock(&self, req: &OpenAIRequest, _model_id: &str, state: &AppState) -> Value {
+        openai_to_converse(req, state)
+    }
+
+    fn from_bedrock(&self, resp: &Value, model: &str, state: &AppState) -> OpenAIResponse {
+        converse_to_openai(resp, model, state)
+    }
+
+    fn chunk_to_openai(
+        &self,
+        chunk: &Value,
+        stream_state: &mut StreamState,
+    ) -> Vec<OpenAIStreamResponse> {
+        converse_chunk_to_openai(chunk, stream_state)
+            .into_iter()
+            .collect()
+    }
+}
+
+static ADAPTERS: LazyLock<HashMap<ModelFamily, Box<dyn ModelAdapter>>> = LazyLock::new(|| {
+    let mut adapters: HashMap<ModelFamily, Box<dyn ModelAdapter>> = HashMap::new();
+    adapters.insert(ModelFamily::Anthropic, Box::new(AnthropicAdapter));
+    adapters.insert(ModelFamily::Titan, Box::new(TitanAdapter));
+    adapters.insert(ModelFamily::Cohere, Box::new(CohereAdapter));
+    adapters.insert(ModelFamily::Converse, Box::new(ConverseAdapter));
+    adapters
+});
+
+impl ModelFamily {
+    // Conversion logic for this family
+    pub fn adapter(self) -> &'static dyn ModelAdapter {
+        ADAPTERS
+            .get(&self)
+            .map(|adapter| adapter.as_ref())
+            .unwrap_or_else(|| panic!("no ModelAdapter registered for {:?}", self))
+    }
+}
@@ -0,0 +1,232 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use http::{HeaderMap, StatusCode};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::{
+    errors::openai_error,
+    state::{AppState, Env, SharedState},
+};
+
+// Check `Authorization: Bearer <ADMIN_TOKEN>`. Returns the rejection to send, if any:
+// 404 while ADMIN_TOKEN is unset so the admin surface isn't advertised, 401 on mismatch
+fn reject_unauthorized(state: &AppState, headers: &HeaderMap) -> Option<Response> {
+    let Some(expected) = &state.admin_token else {
+        return Some(openai_error(
+            StatusCode::NOT_FOUND,
+            "Not found",
+            "invalid_request_error",
+            "not_found",
+        ));
+    };
+
+    let provided = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("");
+    if !constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        warn!("🔒 Rejected admin request with missing or invalid token");
+        return Some(openai_error(
+            StatusCode::UNAUTHORIZED,
+            "Invalid admin token",
+            "authentication_error",
+            "invalid_admin_token",
+        ));
+    }
+    None
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// POST /admin/reload: re-read .env and the environment, build a fresh AppState and
+// swap it in. Requests already running finish on the state they started with
+pub async fn reload_handler(State(shared): State<SharedState>, headers: HeaderMap) -> Response {
+    if let Some(rejection) = reject_unauthorized(&shared.load(), &headers) {
+        return rejection;
+    }
+
+    match reload(&shared, Path::new(".env")) {
+        Ok(next) => Json(json!({
+            "reloaded": true,
+            "inference_profile": next.inference_profile,
+            "default_model_name": next.default_model_name,
+        }))
+        .into_response(),
+        Err(message) => openai_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Config reload failed: {}", message),
+            "api_error",
+            "reload_failed",
+        ),
+    }
+}
+
+// Rebuild only the env-derived config; runtime parts (audit writer, breaker, stats, ...)
+// come from the current state. `.env` in the working directory is parsed into a map that
+// overrides the environment without modifying it, so a variable removed from `.env`
+// keeps the value it was loaded with at startup until restart
+fn reload(shared: &SharedState, env_file: &Path) -> Result<Arc<AppState>, String> {
+    let next = dotenv_vars(env_file)
+        .and_then(|vars| AppState::from_vars(&Env::new(vars), shared.load().runtime()))
+        .inspect_err(|message| {
+            error!(
+                "❌ Config reload failed, keeping current config: {}",
+                message
+            )
+        })?;
+
+    info!(
+        "🔄 Config reloaded (inference profile: {})",
+        next.inference_profile
+    );
+    let next = Arc::new(next);
+    shared.store(next.clone());
+    Ok(next)
+}
+
+// Variables set in `env_file`; none when the file doesn't exist
+fn dotenv_vars(env_file: &Path) -> Result<HashMap<String, String>, String> {
+    match dotenvy::from_path_iter(env_file) {
+        Ok(iter) => iter
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("{}: {}", env_file.display(), e)),
+        Err(e) if e.not_found() => Ok(HashMap::new()),
+        Err(e) => Err(format!("{}: {}", env_file.display(), e)),
+    }
+}
+
+// GET /admin/stats: request counters since startup (see stats.rs)
+pub async fn stats_handler(State(shared): State<SharedState>, headers: HeaderMap) -> Response {
+    let current = shared.load();
+    if let Some(rejection) = reject_unauthorized(&current, &headers) {
+        return rejection;
+    }
+
+    Json(current.request_stats.snapshot()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        handlers::openai_chat_completions_handler,
+        state::{test_state, TEST_ENV_LOCK},
+    };
+    use axum::{extract::Path, routing::post, Router};
+    use serde_json::Value;
+
+    // Anthropic InvokeModel reply naming the model the request was sent to
+    async fn echo_model(Path(model_id): Path<String>) -> Json<Value> {
+        Json(json!({
+            "id": "msg_mock",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "text", "text": model_id}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 3, "output_tokens": 1},
+        }))
+    }
+
+    async fn answering_model(shared: &SharedState) -> String {
+        let request = serde_json::from_value(json!({
+            "messages": [{"role": "user", "content": "Which model?"}]
+        }))
+        .unwrap();
+        let response =
+            openai_chat_completions_handler(State(shared.load()), HeaderMap::new(), Json(request))
+                .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        body["choices"][0]["message"]["content"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn reloaded_inference_profile_takes_effect_on_next_request() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let router = Router::new().route("/model/{id}/invoke", post(echo_model));
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let mut state = test_state();
+        state.bedrock_endpoint = endpoint.clone();
+        let original = state.inference_profile.clone();
+        let shared = SharedState::new(Arc::new(state));
+        assert_eq!(answering_model(&shared).await, original);
+
+        let before = shared.load();
+        let env_file = std::env::temp_dir().join(format!("env-{}", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &env_file,
+            format!(
+                "INFERENCE_PROFILE=anthropic.claude-reloaded-v1:0\nBEDROCK_ENDPOINT_URL={}\n",
+                endpoint
+            ),
+        )
+        .unwrap();
+        {
+            let _env = TEST_ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            reload(&shared, &env_file).unwrap();
+            // `.env` overrides only the new config, never the process environment
+            assert_eq!(std::env::var("INFERENCE_PROFILE").unwrap(), original);
+            assert!(std::env::var("BEDROCK_ENDPOINT_URL").is_err());
+        }
+        std::fs::remove_file(&env_file).ok();
+
+        assert_eq!(
+            answering_model(&shared).await,
+            "anthropic.claude-reloaded-v1:0"
+        );
+        // Runtime parts are carried over rather than rebuilt
+        let after = shared.load();
+        assert!(Arc::ptr_eq(&before.request_stats, &after.request_stats));
+        assert!(Arc::ptr_eq(
+            &before.assumed_credentials,
+            &after.assumed_credentials
+        ));
+    }
+
+    #[test]
+    fn invalid_config_fails_reload_and_keeps_current_state() {
+        let state = Arc::new(test_state());
+        let shared = SharedState::new(state.clone());
+        let env_file = std::env::temp_dir().join(format!("env-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&env_file, "INFERENCE_TAGS=team\n").unwrap();
+
+        let error = {
+            let _env = TEST_ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            reload(&shared, &env_file).err().unwrap()
+        };
+        std::fs::remove_file(&env_file).ok();
+        assert!(error.starts_with("INFERENCE_TAGS"), "{}", error);
+        assert!(Arc::ptr_eq(&shared.load(), &state));
+    }
+
+    #[test]
+    fn missing_env_file_reloads_from_the_environment() {
+        let shared = SharedState::new(Arc::new(test_state()));
+        let env_file = std::env::temp_dir().join(format!("env-{}", uuid::Uuid::new_v4()));
+
+        let _env = TEST_ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let next = reload(&shared, &env_file).unwrap();
+        assert_eq!(
+            next.inference_profile,
+            "anthropic.claude-3-haiku-20240307-v1:0"
+        );
+    }
+}
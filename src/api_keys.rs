@@ -0,0 +1,350 @@
+use axum::http::HeaderMap;
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use tracing::info;
+
+use crate::{
+    admin::constant_time_eq,
+    state::Env,
+    transform::{OpenAIRequest, DEFAULT_TEMPERATURE},
+};
+
+// Temperature Anthropic models use when an /invoke body leaves it out
+const ANTHROPIC_DEFAULT_TEMPERATURE: f32 = 1.0;
+
+// Client API keys. API_KEYS="team-a:sk-...,team-b:sk-..." lists name:secret pairs; while set,
+// the Bedrock routes require `Authorization: Bearer <secret>`. Each key NAME may carry
+// API_KEY_MAX_TOKENS_<NAME> and API_KEY_MAX_TEMPERATURE_<NAME> caps for chat requests
+// (NAME upper-cased, `-` as `_`), enforced per API_KEY_LIMITS_MODE=reject|clamp
+pub struct ApiKeys {
+    keys: Vec<(String, Arc<ApiKey>)>,
+    // Lower over-limit values instead of rejecting the request
+    pub clamp: bool,
+}
+
+pub struct ApiKey {
+    pub name: String,
+    pub max_tokens_cap: Option<u32>,
+    pub max_temperature: Option<f32>,
+}
+
+impl ApiKeys {
+    pub fn from_env(env: &Env) -> Result<Option<Self>, String> {
+        let Ok(raw) = env.var("API_KEYS") else {
+            return Ok(None);
+        };
+
+        let mut keys = Vec::new();
+        for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, secret) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("API_KEYS entry '{}' must be name:secret", entry))?;
+            let name = name.trim().to_string();
+            let key = ApiKey {
+                max_tokens_cap: limit_var(env, "API_KEY_MAX_TOKENS", &name)?,
+                max_temperature: limit_var(env, "API_KEY_MAX_TEMPERATURE", &name)?,
+                name,
+            };
+            keys.push((secret.trim().to_string(), Arc::new(key)));
+        }
+
+        let clamp = match env.var("API_KEY_LIMITS_MODE").as_deref() {
+            Ok("clamp") => true,
+            Ok("reject") | Err(_) => false,
+            Ok(other) => {
+                return Err(format!(
+                    "API_KEY_LIMITS_MODE must be reject or clamp, got '{}'",
+                    other
+                ))
+            }
+        };
+        info!(
+            "🔑 {} API key(s) from API_KEYS (limits: {})",
+            keys.len(),
+            if clamp { "clamp" } else { "reject" }
+        );
+        Ok(Some(Self::new(keys, clamp)))
+    }
+
+    // Keys as (secret, key) pairs
+    pub fn new(keys: Vec<(String, Arc<ApiKey>)>, clamp: bool) -> Self {
+        Self { keys, clamp }
+    }
+
+    // Key whose secret matches the `Authorization: Bearer` header, if any
+    pub fn identify(&self, headers: &HeaderMap) -> Option<Arc<ApiKey>> {
+        let provided = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))?;
+        self.keys
+            .iter()
+            .find(|(secret, _)| constant_time_eq(provided.as_bytes(), secret.as_bytes()))
+            .map(|(_, key)| key.clone())
+    }
+}
+
+impl ApiKey {
+    // Apply this key's caps to a chat request. Values the client didn't send are lowered
+    // to the cap silently; explicit values over it are clamped or, unless `clamp`, rejected.
+    // Err carries a client-facing message for a 400 response
+    pub fn enforce_limits(
+        &self,
+        req: &mut OpenAIRequest,
+        default_max_tokens: u32,
+        clamp: bool,
+    ) -> Result<(), String> {
+        self.limit_max_tokens(&mut req.max_tokens, Some(default_max_tokens), clamp)?;
+        self.limit_temperature(&mut req.temperature, DEFAULT_TEMPERATURE, clamp)
+    }
+
+    // The same caps on an Anthropic-native /invoke body. Its `max_tokens` is required, so
+    // there is no default to lower, and an unset `temperature` means Anthropic's default of 1
+    pub fn enforce_native_limits(
+        &self,
+        payload: &mut Map<String, Value>,
+        clamp: bool,
+    ) -> Result<(), String> {
+        let mut max_tokens = payload
+            .get("max_tokens")
+            .and_then(Value::as_u64)
+            .map(|v| v.min(u32::MAX as u64) as u32);
+        let sent = max_tokens;
+        self.limit_max_tokens(&mut max_tokens, None, clamp)?;
+        if max_tokens != sent {
+            payload.insert("max_tokens".to_string(), json!(max_tokens));
+        }
+
+        let mut temperature = payload
+            .get("temperature")
+            .and_then(Value::as_f64)
+            .map(|t| t as f32);
+        let sent = temperature;
+        self.limit_temperature(&mut temperature, ANTHROPIC_DEFAULT_TEMPERATURE, clamp)?;
+        if temperature != sent {
+            payload.insert("temperature".to_string(), json!(temperature));
+        }
+        Ok(())
+    }
+
+    // Extended thinking sends temperature 1 and raises max_tokens to `budget` + max_tokens
+    // when it isn't already above the budget (see `openai_to_bedrock`). A temperature cap
+    // below 1, or a token cap at or below the budget, leaves no valid request to clamp to,
+    // so both modes reject it; otherwise clamp mode lowers max_tokens to the cap
+    pub fn enforce_thinking_limits(
+        &self,
+        req: &mut OpenAIRequest,
+        budget: u32,
+        default_max_tokens: u32,
+        clamp: bool,
+    ) -> Result<(), String> {
+        if self.max_temperature.is_some_and(|max| max < 1.0) {
+            return Err(format!(
+                "'reasoning_effort' needs temperature 1, above the limit of {} for this API key",
+                self.max_temperature.unwrap_or_default()
+            ));
+        }
+        let Some(cap) = self.max_tokens_cap else {
+            return Ok(());
+        };
+
+        let max_tokens = req.max_tokens.unwrap_or(default_max_tokens);
+        let sent = if max_tokens <= budget {
+            budget.saturating_add(max_tokens)
+        } else {
+            max_tokens
+        };
+        if sent <= cap {
+            return Ok(());
+        }
+        if !clamp || cap <= budget {
+            return Err(format!(
+                "'reasoning_effort' raises max_tokens to {} (thinking budget {}), exceeding the limit of {} for this API key",
+                sent, budget, cap
+            ));
+        }
+        info!(
+            "🔒 Clamping max_tokens {} (with thinking budget {}) to {} for API key {}",
+            sent, budget, cap, self.name
+        );
+        req.max_tokens = Some(cap);
+        Ok(())
+    }
+
+    // `default` is what is sent when the client leaves max_tokens unset, if anything
+    fn limit_max_tokens(
+        &self,
+        max_tokens: &mut Option<u32>,
+        default: Option<u32>,
+        clamp: bool,
+    ) -> Result<(), String> {
+        let Some(cap) = self.max_tokens_cap else {
+            return Ok(());
+        };
+        match *max_tokens {
+            Some(requested) if requested > cap && !clamp => Err(format!(
+                "'max_tokens' {} exceeds the limit of {} for this API key",
+                requested, cap
+            )),
+            Some(requested) if requested > cap => {
+                info!(
+                    "🔒 Clamping max_tokens {} to {} for API key {}",
+                    requested, cap, self.name
+                );
+                *max_tokens = Some(cap);
+                Ok(())
+            }
+            None if default.is_some_and(|default| default > cap) => {
+                *max_tokens = Some(cap);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // `default` is the temperature the model uses when the client leaves it unset
+    fn limit_temperature(
+        &self,
+        temperature: &mut Option<f32>,
+        default: f32,
+        clamp: bool,
+    ) -> Result<(), String> {
+        let Some(max) = self.max_temperature else {
+            return Ok(());
+        };
+        match *temperature {
+            Some(requested) if requested > max && !clamp => Err(format!(
+                "'temperature' {} exceeds the limit of {} for this API key",
+                requested, max
+            )),
+            Some(requested) if requested > max => {
+                info!(
+                    "🔒 Clamping temperature {} to {} for API key {}",
+                    requested, max, self.name
+                );
+                *temperature = Some(max);
+                Ok(())
+            }
+            None if default > max => {
+                *temperature = Some(max);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn limit_var<T: std::str::FromStr>(
+    env: &Env,
+    prefix: &str,
+    name: &str,
+) -> Result<Option<T>, String> {
+    let var = format!("{}_{}", prefix, name.to_uppercase().replace('-', "_"));
+    env.var(&var)
+        .ok()
+        .map(|v| {
+            v.parse()
+                .map_err(|_| format!("{} must be a number, got '{}'", var, v))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(max_tokens_cap: Option<u32>, max_temperature: Option<f32>) -> ApiKey {
+        ApiKey {
+            name: "team-a".to_string(),
+            max_tokens_cap,
+            max_temperature,
+        }
+    }
+
+    fn chat_request(body: Value) -> OpenAIRequest {
+        let mut body = body;
+        body["messages"] = json!([{"role": "user", "content": "hi"}]);
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn reject_mode_refuses_values_over_the_caps() {
+        let key = key(Some(1024), Some(0.5));
+        let mut req = chat_request(json!({"max_tokens": 2048}));
+        let error = key.enforce_limits(&mut req, 512, false).unwrap_err();
+        assert!(error.contains("'max_tokens' 2048 exceeds the limit of 1024"));
+
+        let mut req = chat_request(json!({"temperature": 0.9}));
+        let error = key.enforce_limits(&mut req, 512, false).unwrap_err();
+        assert!(error.contains("'temperature' 0.9 exceeds the limit of 0.5"));
+
+        // Unset values above the cap are lowered silently in both modes
+        let mut req = chat_request(json!({}));
+        key.enforce_limits(&mut req, 4096, false).unwrap();
+        assert_eq!(req.max_tokens, Some(1024));
+        assert_eq!(req.temperature, Some(0.5));
+    }
+
+    #[test]
+    fn clamp_mode_lowers_values_to_the_caps() {
+        let key = key(Some(1024), Some(0.5));
+        let mut req = chat_request(json!({"max_tokens": 2048, "temperature": 0.9}));
+        key.enforce_limits(&mut req, 512, true).unwrap();
+        assert_eq!(req.max_tokens, Some(1024));
+        assert_eq!(req.temperature, Some(0.5));
+
+        let mut req = chat_request(json!({"max_tokens": 100, "temperature": 0.2}));
+        key.enforce_limits(&mut req, 512, true).unwrap();
+        assert_eq!(req.max_tokens, Some(100));
+        assert_eq!(req.temperature, Some(0.2));
+    }
+
+    #[test]
+    fn native_bodies_get_the_same_caps() {
+        let key = key(Some(1024), Some(0.5));
+        let body = json!({"max_tokens": 100000, "messages": []});
+        let Value::Object(mut payload) = body else {
+            unreachable!()
+        };
+        let error = key.enforce_native_limits(&mut payload, false).unwrap_err();
+        assert!(error.contains("'max_tokens' 100000 exceeds the limit of 1024"));
+
+        key.enforce_native_limits(&mut payload, true).unwrap();
+        assert_eq!(payload["max_tokens"], 1024);
+        // Anthropic's default temperature of 1 is over the cap, so the cap is sent
+        assert_eq!(payload["temperature"], 0.5);
+    }
+
+    #[test]
+    fn thinking_budget_counts_toward_the_max_tokens_cap() {
+        let key = key(Some(3000), None);
+        // max_tokens at or below the budget is sent as budget + max_tokens = 3072
+        let mut req = chat_request(json!({"max_tokens": 1024}));
+        let error = key
+            .enforce_thinking_limits(&mut req, 2048, 512, false)
+            .unwrap_err();
+        assert!(error.contains("raises max_tokens to 3072"), "{}", error);
+
+        key.enforce_thinking_limits(&mut req, 2048, 512, true)
+            .unwrap();
+        assert_eq!(req.max_tokens, Some(3000));
+
+        // A cap at or below the budget can't be clamped to a valid request
+        let mut req = chat_request(json!({"max_tokens": 1024}));
+        assert!(key
+            .enforce_thinking_limits(&mut req, 4096, 512, true)
+            .is_err());
+    }
+
+    #[test]
+    fn thinking_needs_a_temperature_cap_of_at_least_one() {
+        let mut req = chat_request(json!({}));
+        let error = key(None, Some(0.5))
+            .enforce_thinking_limits(&mut req, 1024, 512, true)
+            .unwrap_err();
+        assert!(error.contains("needs temperature 1"));
+        assert!(key(None, Some(1.0))
+            .enforce_thinking_limits(&mut req, 1024, 512, false)
+            .is_ok());
+    }
+}
@@ -0,0 +1,90 @@
+use serde_json::{json, Value};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tracing::{error, info};
+
+// Appends one JSON line per chat completion to AUDIT_LOG_PATH.
+// Writes happen on a background task so the response path never waits on disk.
+pub struct AuditLogger {
+    sender: mpsc::UnboundedSender<String>,
+    redact_fields: Vec<String>,
+}
+
+impl AuditLogger {
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("AUDIT_LOG_PATH").ok()?;
+        let redact_fields = std::env::var("AUDIT_REDACT_FIELDS")
+            .map(|v| {
+                v.split(',')
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self::new(path, redact_fields))
+    }
+
+    pub fn new(path: String, redact_fields: Vec<String>) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+
+        info!("📝 Audit log enabled: {}", path);
+        tokio::spawn(async move {
+            let mut file = match tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+            {
+                Ok(f) => f,
+                Err(e) => {
+                    error!("❌ Failed to open audit log {}: {}", path, e);
+                    return;
+                }
+            };
+
+            while let Some(line) = receiver.recv().await {
+                if let Err(e) = file.write_all(line.as_bytes()).await {
+                    error!("❌ Failed to write audit log: {}", e);
+                }
+            }
+        });
+
+        Self {
+            sender,
+            redact_fields,
+        }
+    }
+
+    pub fn record(&self, request_id: &str, model: &str, request: Value, response: Value) {
+        let mut entry = json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "request_id": request_id,
+            "model": model,
+            "request": request,
+            "response": response,
+        });
+        redact(&mut entry, &self.redact_fields);
+
+        let _ = self.sender.send(format!("{}\n", entry));
+    }
+}
+
+// Replace the value of any object key listed in `fields`, at any depth
+fn redact(value: &mut Value, fields: &[String]) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if fields.iter().any(|f| f == key) {
+                    *v = Value::String("[REDACTED]".to_string());
+                } else {
+                    redact(v, fields);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                redact(item, fields);
+            }
+        }
+        _ => {}
+    }
+}
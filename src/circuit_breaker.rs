@@ -0,0 +1,108 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+// Opens after `threshold` consecutive upstream failures within `window`,
+// rejects requests for `cooldown`, then lets a single probe through (half-open)
+pub struct CircuitBreaker {
+    threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    inner: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    first_failure_at: Option<Instant>,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            cooldown,
+            inner: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        let threshold: u32 = std::env::var("CIRCUIT_BREAKER_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|t| *t > 0)?;
+        let window_secs = std::env::var("CIRCUIT_BREAKER_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+        let cooldown_secs = std::env::var("CIRCUIT_BREAKER_COOLDOWN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+
+        Some(Self::new(
+            threshold,
+            Duration::from_secs(window_secs),
+            Duration::from_secs(cooldown_secs),
+        ))
+    }
+
+    // Returns false when the circuit is open and the request should be rejected
+    pub fn allow_request(&self) -> bool {
+        let mut state = self.inner.lock().unwrap();
+
+        let Some(opened_at) = state.opened_at else {
+            return true;
+        };
+
+        if opened_at.elapsed() < self.cooldown {
+            return false;
+        }
+
+        // Half-open: let one probe request test recovery. Restarting the cooldown
+        // holds back other requests until the probe reports back (or times out)
+        state.opened_at = Some(Instant::now());
+        true
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.inner.lock().unwrap();
+        if state.opened_at.is_some() {
+            info!("🔌 Circuit breaker closed, upstream recovered");
+        }
+        *state = BreakerState::default();
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.inner.lock().unwrap();
+        let now = Instant::now();
+
+        if state.opened_at.is_some() {
+            // Failed probe re-opens the circuit for another cooldown
+            state.opened_at = Some(now);
+            warn!("🔌 Circuit breaker probe failed, staying open");
+            return;
+        }
+
+        match state.first_failure_at {
+            Some(first) if now.duration_since(first) <= self.window => {
+                state.consecutive_failures += 1;
+            }
+            _ => {
+                state.first_failure_at = Some(now);
+                state.consecutive_failures = 1;
+            }
+        }
+
+        if state.consecutive_failures >= self.threshold {
+            warn!(
+                "🔌 Circuit breaker opened after {} consecutive upstream failures",
+                state.consecutive_failures
+            );
+            state.opened_at = Some(now);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use http::StatusCode;
+use serde_json::{json, Value};
+
+// OpenAI-style error body: {"error": {"message", "type", "code"}}
+pub fn openai_error_body(message: &str, error_type: &str, code: &str) -> Value {
+    json!({
+        "error": {
+            "message": message,
+            "type": error_type,
+            "code": code,
+        }
+    })
+}
+
+pub fn openai_error(status: StatusCode, message: &str, error_type: &str, code: &str) -> Response {
+    (status, Json(openai_error_body(message, error_type, code))).into_response()
+}
+
+// Map well-known Bedrock error responses to OpenAI errors.
+// `error_type` is the `x-amzn-ErrorType` response header when present.
+// Returns None for errors that should be forwarded unchanged.
+pub fn map_bedrock_error(
+    status: StatusCode,
+    error_type: Option<&str>,
+    body: &str,
+) -> Option<Value> {
+    let is_access_denied = status == StatusCode::FORBIDDEN
+        && (error_type.is_some_and(|t| t.starts_with("AccessDeniedException"))
+            || body.contains("AccessDeniedException"));
+
+    if !is_access_denied {
+        return None;
+    }
+
+    let mut error = openai_error_body(
+        "Access denied by AWS Bedrock. Check that the IAM principal has \
+         bedrock:InvokeModel and bedrock:InvokeModelWithResponseStream permissions \
+         for this model or inference profile, and that model access has been \
+         granted in the Bedrock console for this region.",
+        "permission_error",
+        "access_denied",
+    );
+    error["error"]["bedrock_error"] =
+        serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.to_string()));
+
+    Some(error)
+}
+
+// Anthropic overloaded_error, however Bedrock surfaces it (529, or a 500/503 body)
+pub fn is_overloaded(status: StatusCode, error_type: Option<&str>, body: &str) -> bool {
+    status.as_u16() == 529
+        || (status.is_server_error()
+            && (error_type.is_some_and(|t| t.to_ascii_lowercase().contains("overloaded"))
+                || body.to_ascii_lowercase().contains("overloaded")))
+}
+
+// 429 body for an overloaded upstream (REMAP_OVERLOADED_TO_429), keeping the Bedrock error
+pub fn overloaded_error_body(body: &str) -> Value {
+    let mut error = openai_error_body(
+        "The model is currently overloaded. Please retry after a short delay.",
+        "rate_limit_error",
+        "model_overloaded",
+    );
+    error["error"]["bedrock_error"] =
+        serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.to_string()));
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Body Bedrock returned for InvokeModel without model access
+    const ACCESS_DENIED: &str =
+        r#"{"message":"You don't have access to the model with the specified model ID."}"#;
+
+    #[test]
+    fn maps_access_denied_to_a_permission_error() {
+        let error = map_bedrock_error(
+            StatusCode::FORBIDDEN,
+            Some("AccessDeniedException:http://internal.amazon.com/coral/com.amazon.bedrock/"),
+            ACCESS_DENIED,
+        )
+        .unwrap();
+        assert_eq!(error["error"]["type"], "permission_error");
+        assert_eq!(error["error"]["code"], "access_denied");
+        assert!(error["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("bedrock:InvokeModel"));
+        assert_eq!(
+            error["error"]["bedrock_error"]["message"],
+            "You don't have access to the model with the specified model ID."
+        );
+
+        // Without the header the exception name in the body is enough; non-JSON is kept as text
+        let body = "AccessDeniedException: not authorized";
+        let error = map_bedrock_error(StatusCode::FORBIDDEN, None, body).unwrap();
+        assert_eq!(error["error"]["bedrock_error"], body);
+    }
+
+    #[test]
+    fn forwards_other_errors_unchanged() {
+        assert!(map_bedrock_error(StatusCode::FORBIDDEN, None, ACCESS_DENIED).is_none());
+        assert!(map_bedrock_error(
+            StatusCode::BAD_REQUEST,
+            Some("AccessDeniedException"),
+            ACCESS_DENIED
+        )
+        .is_none());
+        assert!(map_bedrock_error(
+            StatusCode::FORBIDDEN,
+            Some("UnrecognizedClientException"),
+            r#"{"message":"The security token included in the request is invalid."}"#
+        )
+        .is_none());
+    }
+}
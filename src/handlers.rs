@@ -1,104 +1,471 @@
 use axum::{
-    extract::State,
+    body::HttpBody,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, State,
+    },
+    middleware::Next,
     response::{
-        sse::{Event, KeepAlive, Sse},
-        IntoResponse,
+        sse::{Event, Sse},
+        IntoResponse, Response,
     },
     Json,
 };
 use base64::Engine;
 use futures_util::{Stream, StreamExt};
-use http::Request;
+use http::{HeaderMap, HeaderValue, Request};
 use serde_json::{json, Value};
-use std::{pin::Pin, sync::Arc};
-use tracing::{debug, error, info};
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{debug, error, info, warn, Instrument};
 
 use crate::{
+    api_keys::ApiKey,
+    errors::{
+        is_overloaded, map_bedrock_error, openai_error, openai_error_body, overloaded_error_body,
+    },
+    logging::truncate_body,
+    recording,
+    sentences::SentenceBuffer,
+    sessions::SessionModel,
     signing::sign_request,
-    state::AppState,
+    state::{parse_tags, profile_region_mismatch, AppState},
     transform::{
-        bedrock_chunk_to_openai, bedrock_to_openai, openai_to_bedrock, transform_payload,
-        OpenAIRequest,
+        bedrock_chunk_to_openai, content_to_text, context_window_room, count_tool_turns,
+        estimate_missing_usage, forces_tool_call, json_mode, normalize_roles, openai_to_bedrock,
+        parse_tool_choice, reject_audio_input, requests_logprobs, thinking_budget,
+        transform_payload, truncate_history, usage_chunk, validate_documents, validate_image_count,
+        validate_logprobs, validate_response_format, validate_stop_sequences, LegacyPayload,
+        ModelFamily, OpenAIMessage, OpenAIRequest, StreamOptions, StreamState,
+        CHAT_COMPLETION_CHUNK_OBJECT,
     },
 };
 
+// Start of the JSON payload inside each AWS event-stream message
+const FRAME_START: &[u8] = b"{\"bytes\":";
+
 // Helper function to extract JSON from AWS event stream chunks
-fn extract_json_from_bedrock_chunk(chunk_text: &str) -> Option<Value> {
+//
+// `buffer` holds the raw bytes received so far. Returns the decoded Bedrock events for
+// every complete `{"bytes": ...}` frame and the number of bytes consumed. A frame split
+// across reads is left unconsumed so the caller can retry once more bytes arrive.
+// Frames whose base64 or inner JSON fails to decode are skipped.
+//
+// Scanning is done on raw bytes, never on a lossily decoded string, so offsets always
+// line up with `buffer`. The structural bytes we look for (`{`, `}`, `"`, `\`) are ASCII
+// and never occur inside a multibyte UTF-8 sequence, and the decoded event is parsed
+// with `from_slice`, so non-ASCII model output (CJK, emoji) passes through intact.
+pub(crate) fn extract_json_from_bedrock_chunk(buffer: &[u8]) -> (Vec<Value>, usize) {
     // AWS sends binary event stream format. We need to find JSON within the binary data
     // Look for JSON objects that contain the "bytes" field
+    let mut events = Vec::new();
+    let mut consumed = 0;
 
-    // Try to find JSON patterns in the chunk
-    let mut start_idx = 0;
-    while let Some(json_start) = chunk_text[start_idx..].find('{') {
-        let actual_start = start_idx + json_start;
+    loop {
+        let Some(offset) = buffer[consumed..]
+            .windows(FRAME_START.len())
+            .position(|w| w == FRAME_START)
+        else {
+            // Nothing left to decode, but keep a tail that may be a split frame start
+            consumed = consumed.max(buffer.len().saturating_sub(FRAME_START.len() - 1));
+            break;
+        };
+        let start = consumed + offset;
 
-        // Try to find the end of this JSON object by counting braces
-        let mut brace_count = 0;
-        let mut in_string = false;
-        let mut escaped = false;
-        let mut json_end = None;
+        let Some(end) = json_object_end(&buffer[start..]).map(|len| start + len) else {
+            // Partial frame, wait for the rest
+            consumed = start;
+            break;
+        };
 
-        for (i, c) in chunk_text[actual_start..].char_indices() {
-            if escaped {
-                escaped = false;
-                continue;
-            }
+        let json_bytes = &buffer[start..end];
+        debug!("📋 Found JSON in chunk: {}", String::from_utf8_lossy(json_bytes));
 
-            match c {
-                '\\' if in_string => escaped = true,
-                '"' => in_string = !in_string,
-                '{' if !in_string => brace_count += 1,
-                '}' if !in_string => {
-                    brace_count -= 1;
-                    if brace_count == 0 {
-                        json_end = Some(actual_start + i + 1);
-                        break;
-                    }
+        if let Some(event) = decode_frame(json_bytes) {
+            events.push(event);
+        }
+        consumed = end;
+    }
+
+    (events, consumed)
+}
+
+// Length of the JSON object at the start of `bytes`, found by counting braces
+// outside of strings. None if the object is not complete yet.
+fn json_object_end(bytes: &[u8]) -> Option<usize> {
+    let mut brace_count = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, b) in bytes.iter().enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match b {
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            b'{' if !in_string => brace_count += 1,
+            b'}' if !in_string => {
+                brace_count -= 1;
+                if brace_count == 0 {
+                    return Some(i + 1);
                 }
-                _ => {}
             }
+            _ => {}
         }
+    }
 
-        if let Some(end) = json_end {
-            let json_str = &chunk_text[actual_start..end];
-            debug!("📋 Found JSON in chunk: {}", json_str);
+    None
+}
 
-            if let Ok(chunk_data) = serde_json::from_str::<Value>(json_str) {
-                if let Some(bytes_b64) = chunk_data.get("bytes").and_then(|b| b.as_str()) {
-                    // Decode base64 and parse JSON
-                    if let Ok(decoded_bytes) = base64::prelude::BASE64_STANDARD.decode(bytes_b64) {
-                        if let Ok(decoded_str) = String::from_utf8(decoded_bytes) {
-                            debug!("🔓 Decoded chunk: {}", decoded_str);
-                            if let Ok(bedrock_json) = serde_json::from_str(&decoded_str) {
-                                return Some(bedrock_json);
-                            }
-                        }
-                    }
-                }
+// Decode the base64 `bytes` field of a frame into the Bedrock JSON event
+fn decode_frame(json_bytes: &[u8]) -> Option<Value> {
+    let frame: Value = serde_json::from_slice(json_bytes).ok()?;
+    let bytes_b64 = frame.get("bytes")?.as_str()?;
+    let decoded_bytes = base64::prelude::BASE64_STANDARD.decode(bytes_b64).ok()?;
+    debug!("🔓 Decoded chunk: {}", String::from_utf8_lossy(&decoded_bytes));
+    serde_json::from_slice(&decoded_bytes).ok()
+}
+
+// Decode complete AWS event-stream messages from `buffer`, as used by converse-stream.
+// Unlike InvokeModel streams the payload is the event JSON itself and the event type
+// only lives in the `:event-type` (or `:exception-type`) header, so each event is
+// returned keyed by its type: {"contentBlockDelta": {...}}. Returns the events and the
+// number of bytes consumed; a trailing partial message is left for the next read.
+// A message failing its CRC is skipped; a bad prelude discards the buffer, as its
+// lengths can't be trusted to find the next message.
+pub(crate) fn decode_event_stream_messages(buffer: &[u8]) -> (Vec<Value>, usize) {
+    let mut events = Vec::new();
+    let mut consumed = 0;
+
+    // Prelude: total length, headers length, prelude CRC (all u32 big-endian)
+    while buffer.len() - consumed >= 12 {
+        let message = &buffer[consumed..];
+        let total_len = u32::from_be_bytes(message[0..4].try_into().unwrap()) as usize;
+        let headers_len = u32::from_be_bytes(message[4..8].try_into().unwrap()) as usize;
+        let prelude_crc = u32::from_be_bytes(message[8..12].try_into().unwrap());
+        if total_len < 16 + headers_len || crc32fast::hash(&message[..8]) != prelude_crc {
+            // Not a valid message; drop what we have rather than stall the stream
+            warn!("⚠️ Malformed event-stream message, discarding buffer");
+            return (events, buffer.len());
+        }
+        if message.len() < total_len {
+            break;
+        }
+
+        let message_crc = u32::from_be_bytes(message[total_len - 4..total_len].try_into().unwrap());
+        if crc32fast::hash(&message[..total_len - 4]) != message_crc {
+            warn!("⚠️ Event-stream message failed its CRC, skipping it");
+            consumed += total_len;
+            continue;
+        }
+
+        let headers = event_stream_headers(&message[12..12 + headers_len]);
+        let payload = &message[12 + headers_len..total_len - 4];
+        let event_type = headers
+            .get(":event-type")
+            .or_else(|| headers.get(":exception-type"));
+
+        match (event_type, serde_json::from_slice::<Value>(payload)) {
+            (Some(event_type), Ok(event)) => {
+                debug!("🔓 Decoded {} event: {}", event_type, event);
+                events.push(json!({ event_type.as_str(): event }));
             }
-            start_idx = end;
-        } else {
+            _ => debug!("📋 Skipping event-stream message: {:?}", headers),
+        }
+        consumed += total_len;
+    }
+
+    (events, consumed)
+}
+
+// String-valued event-stream headers; other header types are skipped
+fn event_stream_headers(mut bytes: &[u8]) -> std::collections::HashMap<String, String> {
+    let mut headers = std::collections::HashMap::new();
+
+    while let Some((&name_len, rest)) = bytes.split_first() {
+        let Some(name) = rest.get(..name_len as usize) else {
+            break;
+        };
+        let rest = &rest[name_len as usize..];
+        let Some((&value_type, rest)) = rest.split_first() else {
+            break;
+        };
+
+        // Fixed-size values by type (bool, byte, short, int, long, timestamp, uuid);
+        // bytes (6) and string (7) carry a u16 length prefix
+        let (len_prefix, len) = match value_type {
+            0 | 1 => (0, 0),
+            2 => (0, 1),
+            3 => (0, 2),
+            4 => (0, 4),
+            5 | 8 => (0, 8),
+            9 => (0, 16),
+            6 | 7 => match rest.get(..2) {
+                Some(len) => (2, u16::from_be_bytes([len[0], len[1]]) as usize),
+                None => break,
+            },
+            _ => break,
+        };
+        let Some(value) = rest.get(len_prefix..len_prefix + len) else {
             break;
+        };
+        let rest = &rest[len_prefix + len..];
+
+        if value_type == 7 {
+            headers.insert(
+                String::from_utf8_lossy(name).into_owned(),
+                String::from_utf8_lossy(value).into_owned(),
+            );
         }
+        bytes = rest;
     }
 
-    None
+    headers
+}
+
+// AWS error type (e.g. "AccessDeniedException") from the response headers
+pub(crate) fn bedrock_error_type(resp: &reqwest::Response) -> Option<String> {
+    resp.headers()
+        .get("x-amzn-errortype")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+// MODERATION_DENYLIST check on the user prompts; Err carries the client-facing message
+fn moderate(state: &AppState, messages: &[OpenAIMessage]) -> Result<(), String> {
+    match state.moderation.as_ref().and_then(|m| m.check(messages)) {
+        Some(_) => Err("Request blocked by content moderation".to_string()),
+        None => Ok(()),
+    }
+}
+
+// Upstream `Date` header as a unix timestamp, used for `created` when
+// CREATED_FROM_UPSTREAM_DATE is set. None falls back to local time
+fn upstream_created(state: &AppState, headers: &HeaderMap) -> Option<i64> {
+    if !state.created_from_upstream_date {
+        return None;
+    }
+    headers
+        .get(http::header::DATE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| chrono::DateTime::parse_from_rfc2822(v).ok())
+        .map(|date| date.timestamp())
+}
+
+// Queue an audit log line when AUDIT_LOG_PATH is configured
+fn audit(
+    state: &AppState,
+    request_id: &str,
+    model: &str,
+    openai_req: &OpenAIRequest,
+    response: Value,
+) {
+    if let Some(audit_log) = &state.audit_log {
+        let request = serde_json::to_value(openai_req).unwrap_or(Value::Null);
+        audit_log.record(request_id, model, request, response);
+    }
+}
+
+// Retry connect/DNS failures, which happen before any bytes are exchanged and are
+// therefore safe to retry even for streaming requests
+async fn execute_with_connect_retry(
+    state: &AppState,
+    mut req: reqwest::Request,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let mut attempt = 0;
+    loop {
+        let retry_req = req.try_clone();
+        match recording::execute(state, req).await {
+            Err(e) if e.is_connect() && attempt < state.connect_retry_attempts => {
+                let Some(next) = retry_req else {
+                    return Err(e);
+                };
+                attempt += 1;
+                warn!(
+                    "🔁 Connect error, retrying ({}/{}): {}",
+                    attempt, state.connect_retry_attempts, e
+                );
+                tokio::time::sleep(std::time::Duration::from_millis(100 << attempt.min(6))).await;
+                req = next;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Reject upstream-bound requests with 503 while the circuit breaker is open
+pub async fn circuit_breaker_middleware(
+    State(state): State<Arc<AppState>>,
+    req: axum::extract::Request,
+    next: Next,
+) -> Response {
+    if !state.upstream_available() {
+        error!("🔌 Circuit open, rejecting {} {}", req.method(), req.uri());
+        return openai_error(
+            reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            "Upstream temporarily unavailable (circuit breaker open)",
+            "api_error",
+            "circuit_open",
+        );
+    }
+
+    next.run(req).await
+}
+
+// With API_KEYS set, reject requests without a valid `Authorization: Bearer` key
+pub async fn api_key_middleware(
+    State(state): State<Arc<AppState>>,
+    req: axum::extract::Request,
+    next: Next,
+) -> Response {
+    if let Some(api_keys) = &state.api_keys {
+        if api_keys.identify(req.headers()).is_none() {
+            warn!(
+                "🔒 Rejected {} {} without a valid API key",
+                req.method(),
+                req.uri()
+            );
+            return openai_error(
+                reqwest::StatusCode::UNAUTHORIZED,
+                "Invalid or missing API key",
+                "authentication_error",
+                "invalid_api_key",
+            );
+        }
+    }
+
+    next.run(req).await
+}
+
+// Run each request in a span carrying the client IP, so every log line it emits can be
+// attributed. The IP is the TCP peer unless TRUST_FORWARDED_FOR takes it from X-Forwarded-For
+pub async fn client_ip_middleware(
+    State(state): State<Arc<AppState>>,
+    req: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip());
+    let forwarded = state
+        .trust_forwarded_for
+        .then(|| forwarded_client_ip(req.headers()))
+        .flatten();
+    let client_ip = match forwarded.or(peer) {
+        Some(ip) => ip.to_string(),
+        None => "unknown".to_string(),
+    };
+
+    let span = tracing::info_span!("request", client_ip = %client_ip);
+    next.run(req).instrument(span).await
+}
+
+// X-Forwarded-For lists "client, proxy1, proxy2", each proxy appending the address it
+// received the request from. Only the last entry comes from the trusted proxy in front of
+// this server; anything before it is client-supplied and could be forged
+fn forwarded_client_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .rfind(|entry| !entry.is_empty())?
+        .parse()
+        .ok()
+}
+
+// Count requests for /admin/stats. For streamed bodies the in-flight marker rides along
+// with the response body, so streams stay in flight until their last chunk is sent. Bodies
+// of known length are already complete and keep their Content-Length unwrapped
+pub async fn stats_middleware(
+    State(state): State<Arc<AppState>>,
+    req: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let in_flight = state.request_stats.begin();
+    let response = next.run(req).await;
+    state
+        .request_stats
+        .record_status(response.status().as_u16());
+
+    if response.body().size_hint().exact().is_some() {
+        return response;
+    }
+    response.map(|body| {
+        axum::body::Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _ = &in_flight;
+            chunk
+        }))
+    })
+}
+
+// Bedrock payload for a legacy /invoke or /invoke_stream body. OpenAI-style bodies get the
+// same checks as /v1/chat/completions and Anthropic-native ones the caller's API key caps,
+// so neither format gets past a limit set for the chat routes
+fn legacy_payload(
+    state: &AppState,
+    headers: &HeaderMap,
+    payload: Value,
+    streaming: bool,
+) -> Result<(Value, ChatAdjustments), ChatRejection> {
+    let mut ctx = RequestContext::from_headers(state, headers)
+        .map_err(|e| ChatRejection::bad_request(e, "invalid_header"))?;
+    // The legacy routes always call INFERENCE_PROFILE
+    ctx.model_id = state.inference_profile.clone();
+
+    let payload =
+        transform_payload(payload).map_err(|e| ChatRejection::bad_request(e, "invalid_value"))?;
+    match payload {
+        LegacyPayload::OpenAI(mut req) => {
+            let adjustments = validate_chat_request(state, &ctx, &mut req, streaming)?;
+            Ok((openai_to_bedrock(&req, &ctx.model_id, state), adjustments))
+        }
+        LegacyPayload::Native(mut payload) => {
+            if let (Some(key), Some(keys)) = (&ctx.api_key, &state.api_keys) {
+                key.enforce_native_limits(&mut payload, keys.clamp)
+                    .map_err(|e| ChatRejection::bad_request(e, "api_key_limit_exceeded"))?;
+            }
+            Ok((Value::Object(payload), ChatAdjustments::default()))
+        }
+    }
 }
 
 pub async fn invoke_handler(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(payload): Json<Value>,
-) -> impl IntoResponse {
-    let endpoint = format!(
-        "https://bedrock-runtime.{}.amazonaws.com/model/{}/invoke",
-        state.region, state.inference_profile
-    );
+) -> Response {
+    state.request_stats.record_model(&state.inference_profile);
+
+    let (transformed_payload, adjustments) = match legacy_payload(&state, &headers, payload, false)
+    {
+        Ok(transformed) => transformed,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let mut response = invoke_bedrock(&state, transformed_payload).await;
+    adjustments.add_headers(&mut response);
+    response
+}
 
-    let transformed_payload = transform_payload(payload);
+async fn invoke_bedrock(state: &AppState, transformed_payload: Value) -> Response {
+    let endpoint = state.model_url(&state.inference_profile, "invoke");
 
     debug!(
         "📊 Sending payload: {}",
-        serde_json::to_string_pretty(&transformed_payload).unwrap()
+        truncate_body(
+            &serde_json::to_string_pretty(&transformed_payload).unwrap(),
+            state.log_body_max_bytes
+        )
     );
 
     let body = serde_json::to_vec(&transformed_payload).unwrap();
@@ -110,7 +477,7 @@ pub async fn invoke_handler(
         .body(body)
         .unwrap();
 
-    let reqwest_req = match sign_request(http_req, &state, false) {
+    let reqwest_req = match sign_request(http_req, state, false) {
         Ok(r) => r,
         Err(e) => {
             return (
@@ -121,13 +488,19 @@ pub async fn invoke_handler(
         }
     };
 
-    match state.client.execute(reqwest_req).await {
+    let started = std::time::Instant::now();
+    match recording::execute(state, reqwest_req).await {
         Ok(resp) => {
             let status = resp.status();
+            state.record_upstream(!status.is_server_error());
+            state.record_latency(started.elapsed());
             match resp.text().await {
                 Ok(text) => {
                     info!("📨 Response status: {}", status);
-                    debug!("📨 Response body: {}", text);
+                    debug!(
+                        "📨 Response body: {}",
+                        truncate_body(&text, state.log_body_max_bytes)
+                    );
 
                     if status.is_success() {
                         if let Ok(json) = serde_json::from_str::<Value>(&text) {
@@ -146,27 +519,35 @@ pub async fn invoke_handler(
                     .into_response(),
             }
         }
-        Err(e) => (
-            reqwest::StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Request error: {}", e),
-        )
-            .into_response(),
+        Err(e) => {
+            state.record_upstream(false);
+            (
+                reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Request error: {}", e),
+            )
+                .into_response()
+        }
     }
 }
 
 pub async fn invoke_stream_handler(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(payload): Json<Value>,
-) -> Sse<Pin<Box<dyn Stream<Item = Result<Event, axum::Error>> + Send>>> {
-    let endpoint = format!(
-        "https://bedrock-runtime.{}.amazonaws.com/model/{}/invoke-with-response-stream",
-        state.region, state.inference_profile
-    );
+) -> Response {
+    let endpoint = state.model_url(&state.inference_profile, "invoke-with-response-stream");
+    state.request_stats.record_model(&state.inference_profile);
 
-    let transformed_payload = transform_payload(payload);
+    let (transformed_payload, adjustments) = match legacy_payload(&state, &headers, payload, true) {
+        Ok(transformed) => transformed,
+        Err(rejection) => return rejection.into_response(),
+    };
     debug!(
         "🌊 Streaming payload: {}",
-        serde_json::to_string_pretty(&transformed_payload).unwrap()
+        truncate_body(
+            &serde_json::to_string_pretty(&transformed_payload).unwrap(),
+            state.log_body_max_bytes
+        )
     );
 
     let body = serde_json::to_vec(&transformed_payload).unwrap();
@@ -185,282 +566,4465 @@ pub async fn invoke_stream_handler(
             // Convert error to string to ensure it's Send
             let error_msg = e.to_string();
             let err_stream: Pin<Box<dyn Stream<Item = Result<Event, axum::Error>> + Send>> =
-                Box::pin(futures_util::stream::once(async move {
-                    Ok(Event::default().data(format!("Signing error: {}", error_msg)))
-                }));
-            return Sse::new(err_stream);
+                Box::pin(futures_util::stream::iter([
+                    Ok(sse_event(&state, &format!("Signing error: {}", error_msg))),
+                    Ok(sse_event(&state, "[DONE]")),
+                ]));
+            return Sse::new(err_stream).into_response();
         }
     };
 
+    let keep_alive = state.sse_keep_alive();
     let raw_stream: Pin<Box<dyn Stream<Item = Result<Event, axum::Error>> + Send>> = Box::pin(
         async_stream::stream! {
-            match state.client.execute(reqwest_req).await {
+            let started = std::time::Instant::now();
+            match execute_with_connect_retry(&state, reqwest_req).await {
                 Ok(resp) => {
                     let status = resp.status();
+                    state.record_upstream(!status.is_server_error());
+                    state.record_latency(started.elapsed());
                     info!("🌊 Stream response status: {}", status);
 
                     if !status.is_success() {
                         if let Ok(text) = resp.text().await {
                             error!("❌ Stream error: {}", text);
-                            yield Ok(Event::default().data(format!("Error {}: {}", status, text)));
+                            yield Ok(sse_event(&state, &format!("Error {}: {}", status, text)));
                         }
+                        yield Ok(sse_event(&state, "[DONE]"));
                         return;
                     }
 
                     let mut stream = resp.bytes_stream();
+                    let mut stream_state = StreamState {
+                        id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
+                        model: state.default_model_name.clone(),
+                        clock: state.clock,
+                        ..StreamState::default()
+                    };
+                    let mut buffer: Vec<u8> = Vec::new();
 
                     while let Some(chunk_result) = stream.next().await {
                         match chunk_result {
                             Ok(bytes) => {
-                                let text = String::from_utf8_lossy(&bytes);
-                                debug!("📦 Raw chunk: {:?}", text);
-
-                                // Extract JSON from the event stream format
-                                if let Some(json_chunk) = extract_json_from_bedrock_chunk(&text) {
-                                    if let Some(openai_chunk) = bedrock_chunk_to_openai(&json_chunk) {
-                                        yield Ok(Event::default().data(
-                                            serde_json::to_string(&openai_chunk).unwrap()
+                                debug!("📦 Raw chunk: {:?}", String::from_utf8_lossy(&bytes));
+
+                                // Extract JSON from the event stream format, keeping any
+                                // partial frame buffered until the next read
+                                buffer.extend_from_slice(&bytes);
+                                let (events, consumed) = extract_json_from_bedrock_chunk(&buffer);
+                                buffer.drain(..consumed);
+
+                                for json_chunk in events {
+                                    if let Some(openai_chunk) = bedrock_chunk_to_openai(&json_chunk, &mut stream_state) {
+                                        yield Ok(sse_event(
+                                            &state,
+                                            &serde_json::to_string(&openai_chunk).unwrap(),
                                         ));
                                     }
                                 }
                             }
                             Err(e) => {
-                                yield Ok(Event::default().data(format!("Stream error: {}", e)));
+                                yield Ok(sse_event(&state, &format!("Stream error: {}", e)));
                                 break;
                             }
                         }
                     }
 
                     // Final [DONE] event for SSE clients
-                    yield Ok(Event::default().data("[DONE]"));
+                    yield Ok(sse_event(&state, "[DONE]"));
                 }
                 Err(e) => {
+                    state.record_upstream(false);
                     error!("❌ Request error: {}", e);
-                    yield Ok(Event::default().data(format!("Request error: {}", e)));
+                    yield Ok(sse_event(&state, &format!("Request error: {}", e)));
+                    yield Ok(sse_event(&state, "[DONE]"));
                 }
             }
         },
     );
 
-    Sse::new(raw_stream).keep_alive(KeepAlive::default())
+    let mut response = Sse::new(raw_stream).keep_alive(keep_alive).into_response();
+    adjustments.add_headers(&mut response);
+    response
 }
 
-pub async fn models_handler(State(_state): State<Arc<AppState>>) -> impl IntoResponse {
+pub async fn models_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     info!("📋 Models endpoint called");
 
     let models = serde_json::json!({
         "object": "list",
-        "data": [
-            {
-                "id": "claude-sonnet-4",
-                "object": "model",
-                "created": 1677610602,
-                "owned_by": "anthropic",
-                "permission": [],
-                "root": "claude-sonnet-4",
-                "parent": null
-            }
-        ]
+        "data": [model_object(&state.default_model_name)]
     });
 
     Json(models)
 }
 
-pub async fn openai_chat_completions_handler(
+pub async fn model_retrieve_handler(
     State(state): State<Arc<AppState>>,
-    Json(openai_req): Json<OpenAIRequest>,
-) -> impl IntoResponse {
-    info!(
-        "🤖 OpenAI chat completions request (stream={}, messages={})",
-        openai_req.stream.unwrap_or(false),
-        openai_req.messages.len()
-    );
-    debug!("📝 Request payload: {}", serde_json::to_string_pretty(&openai_req).unwrap_or_else(|_| "Failed to serialize".to_string()));
+    Path(id): Path<String>,
+) -> Response {
+    info!("📋 Model endpoint called for {}", id);
 
-    if openai_req.stream == Some(true) {
-        let stream_response =
-            openai_chat_completions_stream_handler(State(state), Json(openai_req)).await;
-        return stream_response.into_response();
+    if id != state.default_model_name {
+        return openai_error(
+            reqwest::StatusCode::NOT_FOUND,
+            &format!("The model '{}' does not exist", id),
+            "invalid_request_error",
+            "model_not_found",
+        );
     }
 
-    let model = openai_req.model.as_deref().unwrap_or("claude-sonnet-4");
-    let bedrock_payload = openai_to_bedrock(&openai_req);
-    debug!("🔄 Transformed to Bedrock payload: {}", serde_json::to_string_pretty(&bedrock_payload).unwrap_or_else(|_| "Failed to serialize".to_string()));
+    Json(model_object(&id)).into_response()
+}
 
-    let endpoint = format!(
-        "https://bedrock-runtime.{}.amazonaws.com/model/{}/invoke",
-        state.region, state.inference_profile
-    );
+// OpenAI model object listed by /v1/models
+fn model_object(id: &str) -> Value {
+    json!({
+        "id": id,
+        "object": "model",
+        "created": 1677610602,
+        "owned_by": "anthropic",
+        "permission": [],
+        "root": id,
+        "parent": null
+    })
+}
 
-    let body = serde_json::to_vec(&bedrock_payload).unwrap();
-    let http_req = Request::builder()
-        .method("POST")
-        .uri(&endpoint)
-        .header("Content-Type", "application/json")
-        .body(body)
-        .unwrap();
+// Per-request settings resolved from inbound headers
+#[derive(Clone)]
+pub struct RequestContext {
+    // Bedrock model ID / inference profile used for the endpoint
+    pub model_id: String,
+    // Deadline for Bedrock to respond (X-Request-Timeout-Ms or REQUEST_TIMEOUT_MS)
+    pub timeout: Option<Duration>,
+    // Inbound headers on the FORWARD_HEADERS allowlist, copied onto the Bedrock request
+    pub forward_headers: HeaderMap,
+    // X-Debug-Trace: true with ALLOW_DEBUG_TRACE=true: attach the Bedrock exchange to the response
+    pub debug_trace: bool,
+    // INFERENCE_TAGS overlaid with X-Bedrock-Request-Tags (the header wins per key)
+    pub request_tags: Vec<(String, String)>,
+    // Caller's key when API_KEYS is set (validated by api_key_middleware)
+    pub api_key: Option<Arc<ApiKey>>,
+}
 
-    let reqwest_req = match sign_request(http_req, &state, false) {
-        Ok(r) => r,
-        Err(e) => {
-            return (
-                reqwest::StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Signing error: {}", e),
-            )
-                .into_response()
+// 400 for a request header `RequestContext::from_headers` refused
+fn invalid_header(message: &str) -> Response {
+    openai_error(
+        reqwest::StatusCode::BAD_REQUEST,
+        message,
+        "invalid_request_error",
+        "invalid_header",
+    )
+}
+
+impl RequestContext {
+    // Err carries a client-facing message for a 400 response
+    pub fn from_headers(state: &AppState, headers: &HeaderMap) -> Result<Self, String> {
+        let model_id = match headers
+            .get("x-bedrock-model-id")
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(id) if state.allowed_model_ids.iter().any(|allowed| allowed == id) => {
+                info!("🎯 Using model override from X-Bedrock-Model-Id: {}", id);
+                id.to_string()
+            }
+            Some(id) => return Err(format!("Model ID '{}' is not in ALLOWED_MODEL_IDS", id)),
+            None => state.inference_profile.clone(),
+        };
+
+        if state.validate_profile_region {
+            if let Some(mismatch) = profile_region_mismatch(&model_id, &state.region) {
+                warn!("🌍 {}", mismatch);
+                return Err(mismatch);
+            }
         }
-    };
 
-    debug!("🌐 Making request to Bedrock endpoint: {}", endpoint);
-    match state.client.execute(reqwest_req).await {
-        Ok(resp) => {
-            let status = resp.status();
-            debug!("📡 Bedrock response status: {}", status);
-            match resp.text().await {
-                Ok(text) => {
-                    debug!("📨 Bedrock response body: {}", text);
-                    if status.is_success() {
-                        if let Ok(bedrock_response) = serde_json::from_str::<Value>(&text) {
-                            debug!("✅ Successfully parsed Bedrock response");
-                            let openai_response = bedrock_to_openai(&bedrock_response, model);
-                            debug!("🔄 Converted to OpenAI format: {}", serde_json::to_string_pretty(&openai_response).unwrap_or_else(|_| "Failed to serialize".to_string()));
-                            Json(openai_response).into_response()
-                        } else {
-                            error!("❌ Failed to parse Bedrock response as JSON: {}", text);
-                            (status, text).into_response()
-                        }
-                    } else {
-                        error!("❌ Bedrock API error {}: {}", status, text);
-                        (status, text).into_response()
-                    }
-                }
-                Err(e) => {
-                    error!("❌ Failed to read response body: {}", e);
-                    (
-                        reqwest::StatusCode::INTERNAL_SERVER_ERROR,
-                        format!("Failed to read response: {}", e),
-                    )
-                        .into_response()
+        let timeout = match headers.get("x-request-timeout-ms") {
+            Some(value) => {
+                let ms: u64 = value
+                    .to_str()
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|ms| *ms > 0)
+                    .ok_or("X-Request-Timeout-Ms must be a positive integer")?;
+                Some(Duration::from_millis(ms.min(state.max_request_timeout_ms)))
+            }
+            None => state.request_timeout_ms.map(Duration::from_millis),
+        };
+
+        let mut forward_headers = HeaderMap::new();
+        for name in &state.forward_headers {
+            for value in headers.get_all(name.as_str()) {
+                if let Ok(name) = http::HeaderName::from_bytes(name.as_bytes()) {
+                    forward_headers.append(name, value.clone());
                 }
             }
         }
-        Err(e) => {
-            error!("❌ HTTP request failed: {}", e);
-            (
-                reqwest::StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Request error: {}", e),
-            )
-                .into_response()
-        }
-    }
-}
 
-pub async fn openai_chat_completions_stream_handler(
-    State(state): State<Arc<AppState>>,
-    Json(openai_req): Json<OpenAIRequest>,
-) -> Sse<Pin<Box<dyn Stream<Item = Result<Event, axum::Error>> + Send>>> {
-    let model = openai_req
-        .model
-        .as_deref()
-        .unwrap_or("claude-sonnet-4")
-        .to_string();
-    let bedrock_payload = openai_to_bedrock(&openai_req);
+        let debug_trace = state.allow_debug_trace
+            && headers
+                .get("x-debug-trace")
+                .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"true"));
 
-    let endpoint = format!(
-        "https://bedrock-runtime.{}.amazonaws.com/model/{}/invoke-with-response-stream",
-        state.region, state.inference_profile
-    );
+        let mut request_tags = state.inference_tags.clone();
+        if let Some(value) = headers.get("x-bedrock-request-tags") {
+            let value = value
+                .to_str()
+                .map_err(|_| "X-Bedrock-Request-Tags must be ASCII key=value pairs".to_string())?;
+            for (key, tag_value) in parse_tags(value)? {
+                request_tags.retain(|(k, _)| *k != key);
+                request_tags.push((key, tag_value));
+            }
+        }
 
-    let body = serde_json::to_vec(&bedrock_payload).unwrap();
-    let http_req = Request::builder()
-        .method("POST")
-        .uri(&endpoint)
-        .header("Content-Type", "application/json")
-        .header("X-Amzn-Bedrock-Accept", "application/json")
-        .body(body)
-        .unwrap();
+        Ok(Self {
+            model_id,
+            timeout,
+            forward_headers,
+            debug_trace,
+            request_tags,
+            api_key: state
+                .api_keys
+                .as_ref()
+                .and_then(|keys| keys.identify(headers)),
+        })
+    }
 
-    let reqwest_req = match sign_request(http_req, &state, true) {
-        Ok(r) => r,
-        Err(e) => {
-            let error_msg = e.to_string();
-            let err_stream: Pin<Box<dyn Stream<Item = Result<Event, axum::Error>> + Send>> =
-                Box::pin(futures_util::stream::once(async move {
-                    Ok(Event::default().data(format!("Signing error: {}", error_msg)))
-                }));
-            return Sse::new(err_stream);
+    // Add a response's usage to the caller's API key totals in /admin/stats
+    pub fn record_tokens(&self, state: &AppState, prompt_tokens: i32, completion_tokens: i32) {
+        if let Some(key) = &self.api_key {
+            state
+                .request_stats
+                .record_tokens(&key.name, prompt_tokens, completion_tokens);
         }
-    };
+    }
 
-    let raw_stream: Pin<Box<dyn Stream<Item = Result<Event, axum::Error>> + Send>> = Box::pin(
-        async_stream::stream! {
-            match state.client.execute(reqwest_req).await {
-                Ok(resp) => {
-                    let status = resp.status();
-                    if !status.is_success() {
-                        let text = resp.text().await.unwrap_or_default();
-                        yield Ok(Event::default().data(format!("Error {}: {}", status, text)));
-                        yield Ok(Event::default().data("[DONE]"));
-                        return;
-                    }
+    // Apply the caller's API key caps (if any) to a chat request, including the max_tokens
+    // and temperature extended thinking adds on Anthropic models.
+    // Err carries a client-facing message for a 400 response
+    pub fn enforce_key_limits(
+        &self,
+        state: &AppState,
+        req: &mut OpenAIRequest,
+    ) -> Result<(), String> {
+        let (Some(key), Some(keys)) = (&self.api_key, &state.api_keys) else {
+            return Ok(());
+        };
+        key.enforce_limits(req, state.default_max_tokens, keys.clamp)?;
+        if ModelFamily::for_request(state, &self.model_id) != ModelFamily::Anthropic {
+            return Ok(());
+        }
+        match thinking_budget(req, &self.model_id, state) {
+            Some(budget) => {
+                key.enforce_thinking_limits(req, budget, state.default_max_tokens, keys.clamp)
+            }
+            None => Ok(()),
+        }
+    }
 
-                    let mut stream = resp.bytes_stream();
+    // The caller's API key cap on max_tokens, if any
+    fn max_tokens_cap(&self, state: &AppState) -> Option<u32> {
+        state.api_keys.as_ref()?;
+        self.api_key.as_ref()?.max_tokens_cap
+    }
+}
 
-                    let mut sent_first = false;
+// Request tags travel as Converse `requestMetadata`, inside the signed body.
+// InvokeModel has no per-request metadata field, so other families skip them
+fn attach_request_tags(payload: &mut Value, family: ModelFamily, ctx: &RequestContext) {
+    if ctx.request_tags.is_empty() {
+        return;
+    }
+    if family != ModelFamily::Converse {
+        debug!("🏷️ Request tags need the Converse API (USE_CONVERSE_API=true), not sending them");
+        return;
+    }
 
-                    while let Some(chunk) = stream.next().await {
-                        match chunk {
-                            Ok(bytes) => {
-                                let text = String::from_utf8_lossy(&bytes);
-                                debug!("📦 Raw chunk: {:?}", text);
+    let metadata: serde_json::Map<String, Value> = ctx
+        .request_tags
+        .iter()
+        .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+        .collect();
+    payload["requestMetadata"] = Value::Object(metadata);
+}
 
-                                if let Some(json_chunk) = extract_json_from_bedrock_chunk(&text) {
-                                    if let Some(openai_chunk) = bedrock_chunk_to_openai(&json_chunk) {
-                                        yield Ok(Event::default().data(serde_json::to_string(&openai_chunk).unwrap()));
-                                        sent_first = true;
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                yield Ok(Event::default().data(format!("Stream error: {}", e)));
-                                break;
-                            }
-                        }
-                    }
+// Await a Bedrock call under the per-request timeout, if any.
+// Err carries the timeout that expired.
+async fn with_request_timeout<F: Future>(
+    timeout: Option<Duration>,
+    call: F,
+) -> Result<F::Output, Duration> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, call)
+            .await
+            .map_err(|_| timeout),
+        None => Ok(call.await),
+    }
+}
 
-                    // Ensure at least one chunk
-                    if !sent_first {
-                        let dummy = json!({
-                            "id": format!("chatcmpl-{}", uuid::Uuid::new_v4()),
-                            "object": "chat.completion.chunk",
-                            "created": std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
-                            "model": model,
-                            "choices": [{
-                                "index": 0,
-                                "delta": {"content": ""},
-                                "finish_reason": null
-                            }]
-                        });
-                        yield Ok(Event::default().data(dummy.to_string()));
-                    }
+// Await part of a stream's setup under the STREAM_TTFB_TIMEOUT_MS deadline, if any.
+// None once the deadline has passed
+async fn before_first_byte<F: Future>(
+    deadline: Option<tokio::time::Instant>,
+    call: F,
+) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, call).await.ok(),
+        None => Some(call.await),
+    }
+}
 
-                    yield Ok(Event::default().data("[DONE]"));
-                }
-                Err(e) => {
-                    yield Ok(Event::default().data(format!("Request error: {}", e)));
-                    yield Ok(Event::default().data("[DONE]"));
-                }
-            }
-        },
+fn ttfb_timeout_error(state: &AppState) -> Value {
+    let message = format!(
+        "Bedrock sent no data within the {}ms stream first-byte timeout",
+        state.stream_ttfb_timeout_ms.unwrap_or_default()
     );
+    warn!("⏰ {}", message);
+    openai_error_body(&message, "timeout_error", "stream_ttfb_timeout")
+}
 
-    Sse::new(raw_stream).keep_alive(KeepAlive::default())
+fn request_timeout_message(timeout: Duration) -> String {
+    format!(
+        "Bedrock did not respond within the {}ms request timeout",
+        timeout.as_millis()
+    )
 }
 
-pub async fn catch_all_handler(
-    uri: axum::http::Uri,
-    method: axum::http::Method,
-) -> impl IntoResponse {
-    info!("🔍 Unhandled request: {} {}", method, uri);
-    axum::http::StatusCode::NOT_FOUND
+// Let the Accept header decide streaming where the body doesn't: `text/event-stream` streams
+// a request that left `stream` unset. With ACCEPT_JSON_OVERRIDES_STREAM=true, an Accept
+// listing `application/json` but not `text/event-stream` returns one JSON completion even
+// for `stream: true` (off by default, as OpenAI SDKs send that Accept on streamed calls too)
+fn negotiate_stream(state: &AppState, req: &mut OpenAIRequest, headers: &HeaderMap) {
+    let accepts = |media_type: &str| {
+        headers
+            .get_all(http::header::ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|range| {
+                let range = range.split(';').next().unwrap_or("").trim();
+                range.eq_ignore_ascii_case(media_type)
+            })
+    };
+
+    match req.stream {
+        None if accepts("text/event-stream") => {
+            info!("🔀 Accept: text/event-stream without 'stream', streaming the response");
+            req.stream = Some(true);
+        }
+        Some(true)
+            if state.accept_json_overrides_stream
+                && accepts("application/json")
+                && !accepts("text/event-stream") =>
+        {
+            info!("🔀 Accept: application/json with 'stream': true, returning JSON");
+            req.stream = Some(false);
+            req.stream_options = None;
+        }
+        _ => {}
+    }
+}
+
+// With SESSION_TTL_SECS set, a request carrying X-Session-Id that omits `model` or
+// X-Bedrock-Model-Id gets the one that session last used. A remembered model ID is put
+// back on the headers, so it is checked against ALLOWED_MODEL_IDS like a fresh override.
+// Returns the session ID to remember once the request's model has been accepted
+fn apply_session_model(
+    state: &AppState,
+    headers: &mut HeaderMap,
+    req: &mut OpenAIRequest,
+) -> Option<String> {
+    let sessions = state.sticky_sessions.as_ref()?;
+    let session_id = headers
+        .get("x-session-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())?
+        .to_string();
+
+    if let Some(remembered) = sessions.recall(&session_id) {
+        if req.model.is_none() && remembered.model.is_some() {
+            debug!(
+                "📌 Session {}: using model {:?}",
+                session_id, remembered.model
+            );
+            req.model = remembered.model;
+        }
+        if !headers.contains_key("x-bedrock-model-id") {
+            if let Some(value) = remembered
+                .model_id
+                .and_then(|id| HeaderValue::from_str(&id).ok())
+            {
+                debug!("📌 Session {}: using model ID {:?}", session_id, value);
+                headers.insert("x-bedrock-model-id", value);
+            }
+        }
+    }
+    Some(session_id)
+}
+
+fn remember_session_model(
+    state: &AppState,
+    session_id: &str,
+    headers: &HeaderMap,
+    req: &OpenAIRequest,
+) {
+    if let Some(sessions) = &state.sticky_sessions {
+        sessions.remember(
+            session_id,
+            SessionModel {
+                model: req.model.clone(),
+                model_id: headers
+                    .get("x-bedrock-model-id")
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string),
+            },
+        );
+    }
+}
+
+// A chat request refused by `validate_chat_request`, answered with an OpenAI error
+pub struct ChatRejection {
+    status: reqwest::StatusCode,
+    message: String,
+    code: &'static str,
+}
+
+impl ChatRejection {
+    fn new(status: reqwest::StatusCode, message: impl Into<String>, code: &'static str) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            code,
+        }
+    }
+
+    fn bad_request(message: impl Into<String>, code: &'static str) -> Self {
+        Self::new(reqwest::StatusCode::BAD_REQUEST, message, code)
+    }
+
+    fn body(&self) -> Value {
+        openai_error_body(&self.message, "invalid_request_error", self.code)
+    }
+
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body())).into_response()
+    }
+
+    fn batch_item(&self) -> Value {
+        batch_item_error(
+            self.status,
+            &self.message,
+            "invalid_request_error",
+            self.code,
+        )
+    }
+}
+
+// Changes `validate_chat_request` made to an accepted request, reported in response headers
+#[derive(Default)]
+pub struct ChatAdjustments {
+    // Messages dropped by TRUNCATE_CONTEXT_TOKENS
+    truncated_messages: usize,
+    // Tool-call round trips, when over MAX_TOOL_TURNS in MAX_TOOL_TURNS_MODE=warn
+    tool_turns_exceeded: Option<usize>,
+}
+
+impl ChatAdjustments {
+    fn add_headers(&self, response: &mut Response) {
+        if self.truncated_messages > 0 {
+            response.headers_mut().insert(
+                "x-truncated-messages",
+                HeaderValue::from(self.truncated_messages),
+            );
+        }
+        if let Some(tool_turns) = self.tool_turns_exceeded {
+            response
+                .headers_mut()
+                .insert("x-tool-turns-exceeded", HeaderValue::from(tool_turns));
+        }
+    }
+}
+
+// Checks shared by every chat transport (/v1/chat/completions, /v1/chat/batch items and
+// /v1/chat/ws), so a limit configured for one can't be bypassed through another.
+// `streaming` is whether the transport streams the reply. Normalizes roles, applies
+// TRUNCATE_CONTEXT_TOKENS and the API key caps to `req` in place
+fn validate_chat_request(
+    state: &AppState,
+    ctx: &RequestContext,
+    req: &mut OpenAIRequest,
+    streaming: bool,
+) -> Result<ChatAdjustments, ChatRejection> {
+    let mut adjustments = ChatAdjustments::default();
+
+    normalize_roles(&mut req.messages)
+        .map_err(|e| ChatRejection::bad_request(e, "invalid_role"))?;
+    moderate(state, &req.messages).map_err(|e| ChatRejection::bad_request(e, "content_filter"))?;
+
+    let tool_turns = count_tool_turns(&req.messages);
+    if state.max_tool_turns.is_some_and(|max| tool_turns > max) {
+        let message = format!(
+            "Conversation has {} tool-call round trips, exceeding MAX_TOOL_TURNS={}",
+            tool_turns,
+            state.max_tool_turns.unwrap_or_default()
+        );
+        warn!("🔁 {}", message);
+        if state.reject_tool_turns {
+            return Err(ChatRejection::bad_request(
+                message,
+                "tool_turn_limit_exceeded",
+            ));
+        }
+        adjustments.tool_turns_exceeded = Some(tool_turns);
+    }
+
+    if let Some(budget) = state.truncate_context_tokens {
+        adjustments.truncated_messages = truncate_history(&mut req.messages, budget);
+        if adjustments.truncated_messages > 0 {
+            info!(
+                "✂️ Truncated {} messages to fit context budget",
+                adjustments.truncated_messages
+            );
+        }
+    }
+
+    if req.stream_options.is_some() && !streaming {
+        return Err(ChatRejection::bad_request(
+            "The 'stream_options' parameter is only allowed when 'stream' is enabled.",
+            "invalid_value",
+        ));
+    }
+
+    // OpenAI advises setting temperature or top_p, not both; the request is sent unchanged
+    if req.temperature.is_some() && req.top_p.is_some() {
+        let message = "Both 'temperature' and 'top_p' are set; set only one of them.";
+        if state.strict_sampling_params {
+            return Err(ChatRejection::bad_request(message, "invalid_value"));
+        }
+        warn!(
+            "🎲 {} (temperature={:?}, top_p={:?})",
+            message, req.temperature, req.top_p
+        );
+    }
+
+    validate_response_format(req).map_err(|e| ChatRejection::bad_request(e, "invalid_value"))?;
+    validate_stop_sequences(req, state)
+        .map_err(|e| ChatRejection::bad_request(e, "invalid_value"))?;
+    validate_documents(req).map_err(|e| ChatRejection::bad_request(e, "invalid_value"))?;
+    validate_image_count(req, state.max_images_per_request)
+        .map_err(|e| ChatRejection::bad_request(e, "too_many_images"))?;
+    parse_tool_choice(req).map_err(|e| ChatRejection::bad_request(e, "invalid_value"))?;
+    validate_choice_count(req, streaming)
+        .map_err(|e| ChatRejection::bad_request(e, "invalid_value"))?;
+    ctx.enforce_key_limits(state, req)
+        .map_err(|e| ChatRejection::bad_request(e, "api_key_limit_exceeded"))?;
+    reject_audio_input(req, &ctx.model_id)
+        .map_err(|e| ChatRejection::bad_request(e, "unsupported_value"))?;
+    validate_logprobs(req, &ctx.model_id, state).map_err(|e| {
+        ChatRejection::new(
+            reqwest::StatusCode::NOT_IMPLEMENTED,
+            e,
+            "unsupported_parameter",
+        )
+    })?;
+
+    let family = ModelFamily::for_request(state, &ctx.model_id);
+    if requests_logprobs(req) && !family.returns_logprobs() {
+        info!(
+            "ℹ️ logprobs requested, but {:?} models on Bedrock don't return them; responding with null",
+            family
+        );
+    }
+
+    Ok(adjustments)
+}
+
+pub async fn openai_chat_completions_handler(
+    State(state): State<Arc<AppState>>,
+    mut headers: HeaderMap,
+    Json(mut openai_req): Json<OpenAIRequest>,
+) -> Response {
+    negotiate_stream(&state, &mut openai_req, &headers);
+    let session_id = apply_session_model(&state, &mut headers, &mut openai_req);
+    info!(
+        "🤖 OpenAI chat completions request (stream={}, messages={})",
+        openai_req.stream.unwrap_or(false),
+        openai_req.messages.len()
+    );
+    debug!("📝 Request payload: {}", truncate_body(&serde_json::to_string_pretty(&openai_req).unwrap_or_else(|_| "Failed to serialize".to_string()), state.log_body_max_bytes));
+
+    let ctx = match RequestContext::from_headers(&state, &headers) {
+        Ok(ctx) => ctx,
+        Err(e) => return invalid_header(&e),
+    };
+    state.request_stats.record_model(&ctx.model_id);
+
+    let streaming = openai_req.stream == Some(true);
+    let adjustments = match validate_chat_request(&state, &ctx, &mut openai_req, streaming) {
+        Ok(adjustments) => adjustments,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    // Only a request that passed every check may change the session's model
+    if let Some(session_id) = &session_id {
+        remember_session_model(&state, session_id, &headers, &openai_req);
+    }
+
+    let mut response = if openai_req.stream == Some(true) {
+        openai_chat_completions_stream_handler(state, openai_req, ctx)
+            .await
+            .into_response()
+    } else {
+        openai_chat_completions_json(state, openai_req, ctx).await
+    };
+
+    adjustments.add_headers(&mut response);
+    response
+}
+
+// Non-streaming chat completion. In JSON mode with JSON_RETRY_MAX_TOKENS set, a reply
+// cut off at max_tokens that isn't valid JSON is retried with double the max_tokens,
+// up to the cap; the last attempt is returned either way, and is the only one audited
+async fn openai_chat_completions_json(
+    state: Arc<AppState>,
+    mut openai_req: OpenAIRequest,
+    ctx: RequestContext,
+) -> Response {
+    loop {
+        let mut response =
+            openai_chat_completions_json_once(state.clone(), openai_req.clone(), ctx.clone()).await;
+
+        let retry = json_retry_max_tokens(&state, &ctx, &openai_req);
+        if let Some((sent, retry_max_tokens)) = retry.filter(|_| response.status().is_success()) {
+            let (parts, body) = response.into_parts();
+            let body = axum::body::to_bytes(body, usize::MAX)
+                .await
+                .unwrap_or_default();
+            // Extended thinking adds its budget to the retry, which the key's cap must allow
+            let mut retry_req = openai_req.clone();
+            retry_req.max_tokens = Some(retry_max_tokens);
+            if truncated_invalid_json(&body)
+                && ctx.enforce_key_limits(&state, &mut retry_req).is_ok()
+            {
+                warn!(
+                    "🔁 JSON-mode response truncated at max_tokens={} with invalid JSON, retrying with {}",
+                    sent, retry_max_tokens
+                );
+                openai_req = retry_req;
+                continue;
+            }
+            response = Response::from_parts(parts, axum::body::Body::from(body));
+        }
+
+        if let Some(pending) = response.extensions_mut().remove::<PendingAudit>() {
+            audit(
+                &state,
+                &pending.request_id,
+                &pending.model,
+                &openai_req,
+                pending.response,
+            );
+        }
+        return response;
+    }
+}
+
+// (max_tokens sent, max_tokens for a retry) under JSON_RETRY_MAX_TOKENS in JSON mode. The
+// retry doubles what was actually sent after the MODEL_CONTEXT_WINDOW clamp, capped by
+// both and by the caller's API key cap; None when retries are off or the value can't grow
+fn json_retry_max_tokens(
+    state: &AppState,
+    ctx: &RequestContext,
+    req: &OpenAIRequest,
+) -> Option<(u32, u32)> {
+    let cap = state.json_retry_max_tokens.filter(|_| json_mode(req))?;
+    let cap = cap.min(ctx.max_tokens_cap(state).unwrap_or(u32::MAX));
+    let room = context_window_room(req, state).unwrap_or(u32::MAX);
+    let sent = req.max_tokens.unwrap_or(state.default_max_tokens).min(room);
+    let retry = sent.saturating_mul(2).min(cap).min(room);
+    (retry > sent).then_some((sent, retry))
+}
+
+// Audit entry of a successful JSON completion, left on the response so that only the
+// attempt returned to the client is audited
+#[derive(Clone)]
+struct PendingAudit {
+    request_id: String,
+    model: String,
+    response: Value,
+}
+
+// finish_reason "length" and message content that doesn't parse as JSON
+fn truncated_invalid_json(body: &[u8]) -> bool {
+    let Ok(response) = serde_json::from_slice::<Value>(body) else {
+        return false;
+    };
+    let choice = &response["choices"][0];
+    if choice["finish_reason"] != "length" {
+        return false;
+    }
+    let content = content_to_text(&choice["message"].get("content").cloned());
+    serde_json::from_str::<Value>(&content).is_err()
+}
+
+async fn openai_chat_completions_json_once(
+    state: Arc<AppState>,
+    openai_req: OpenAIRequest,
+    ctx: RequestContext,
+) -> Response {
+    let model = openai_req
+        .model
+        .as_deref()
+        .unwrap_or(&state.default_model_name);
+    let family = ModelFamily::for_request(&state, &ctx.model_id);
+    let mut bedrock_payload = family
+        .adapter()
+        .to_bedrock(&openai_req, &ctx.model_id, &state);
+    attach_request_tags(&mut bedrock_payload, family, &ctx);
+    debug!("🔄 Transformed to Bedrock payload: {}", truncate_body(&serde_json::to_string_pretty(&bedrock_payload).unwrap_or_else(|_| "Failed to serialize".to_string()), state.log_body_max_bytes));
+
+    let endpoint = state.model_url(&ctx.model_id, family.action(false));
+
+    let body = serde_json::to_vec(&bedrock_payload).unwrap();
+    let mut http_req = Request::builder()
+        .method("POST")
+        .uri(&endpoint)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap();
+    http_req.headers_mut().extend(ctx.forward_headers.clone());
+
+    let reqwest_req = match sign_request(http_req, &state, false) {
+        Ok(r) => r,
+        Err(e) => {
+            return (
+                reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Signing error: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    debug!("🌐 Making request to Bedrock endpoint: {}", endpoint);
+    let started = std::time::Instant::now();
+    let call = async {
+        match &state.singleflight {
+            Some(singleflight) => singleflight.execute(&state, reqwest_req).await,
+            None => recording::execute(&state, reqwest_req)
+                .await
+                .map_err(|e| e.to_string()),
+        }
+    };
+    let result = match with_request_timeout(ctx.timeout, call).await {
+        Ok(result) => result,
+        Err(timeout) => {
+            warn!(
+                "⏰ Bedrock request timed out after {}ms",
+                timeout.as_millis()
+            );
+            return openai_error(
+                reqwest::StatusCode::GATEWAY_TIMEOUT,
+                &request_timeout_message(timeout),
+                "timeout_error",
+                "request_timeout",
+            );
+        }
+    };
+    match result {
+        Ok(resp) => {
+            let status = resp.status();
+            state.record_upstream(!status.is_server_error());
+            state.record_latency(started.elapsed());
+            debug!("📡 Bedrock response status: {}", status);
+            let error_type = bedrock_error_type(&resp);
+            let created = upstream_created(&state, resp.headers());
+            match resp.text().await {
+                Ok(text) => {
+                    debug!(
+                        "📨 Bedrock response body: {}",
+                        truncate_body(&text, state.log_body_max_bytes)
+                    );
+                    if status.is_success() {
+                        if let Ok(bedrock_response) = serde_json::from_str::<Value>(&text) {
+                            debug!("✅ Successfully parsed Bedrock response");
+                            if state.validate_bedrock_responses {
+                                if let Err(e) = family.validate_response(&bedrock_response) {
+                                    error!("❌ {}: {}", e, text);
+                                    return openai_error(
+                                        reqwest::StatusCode::BAD_GATEWAY,
+                                        &e,
+                                        "api_error",
+                                        "invalid_upstream_response",
+                                    );
+                                }
+                            }
+                            let mut openai_response = family
+                                .adapter()
+                                .from_bedrock(&bedrock_response, model, &state);
+                            debug!("🔄 Converted to OpenAI format: {}", truncate_body(&serde_json::to_string_pretty(&openai_response).unwrap_or_else(|_| "Failed to serialize".to_string()), state.log_body_max_bytes));
+                            if let Some(created) = created {
+                                openai_response.created = created;
+                            }
+                            if state.expose_bedrock_stop_reason {
+                                let raw = family.raw_stop_reason(&bedrock_response);
+                                for choice in &mut openai_response.choices {
+                                    choice.x_bedrock_stop_reason = raw.clone();
+                                }
+                            }
+                            let usage_estimated = state.estimate_missing_usage
+                                && estimate_missing_usage(&mut openai_response, &openai_req);
+                            if usage_estimated {
+                                warn!("📏 No usage in Bedrock response, estimating it");
+                            }
+                            let pending_audit = PendingAudit {
+                                request_id: openai_response.id.clone(),
+                                model: model.to_string(),
+                                response: serde_json::to_value(&openai_response)
+                                    .unwrap_or(Value::Null),
+                            };
+                            ctx.record_tokens(
+                                &state,
+                                openai_response.usage.prompt_tokens,
+                                openai_response.usage.completion_tokens,
+                            );
+                            let cost = state.estimate_cost(
+                                &ctx.model_id,
+                                openai_response.usage.prompt_tokens,
+                                openai_response.usage.completion_tokens,
+                            );
+                            let mut body =
+                                serde_json::to_value(&openai_response).unwrap_or(Value::Null);
+                            state.filter_response_fields(&mut body);
+                            if ctx.debug_trace {
+                                body["x_debug_trace"] =
+                                    debug_trace(&bedrock_payload, bedrock_response);
+                            }
+                            let mut response = Json(body).into_response();
+                            response.extensions_mut().insert(pending_audit);
+                            if usage_estimated {
+                                response
+                                    .headers_mut()
+                                    .insert("x-usage-estimated", HeaderValue::from_static("true"));
+                            }
+                            if let Some(cost) = cost {
+                                if let Ok(value) = HeaderValue::from_str(&format!("{:.6}", cost)) {
+                                    response.headers_mut().insert("x-estimated-cost-usd", value);
+                                }
+                            }
+                            response
+                        } else {
+                            error!("❌ Failed to parse Bedrock response as JSON: {}", text);
+                            (status, text).into_response()
+                        }
+                    } else {
+                        error!("❌ Bedrock API error {}: {}", status, text);
+                        audit(
+                            &state,
+                            &uuid::Uuid::new_v4().to_string(),
+                            model,
+                            &openai_req,
+                            json!({"status": status.as_u16(), "body": text}),
+                        );
+                        let mut status = status;
+                        let mut mapped = map_bedrock_error(status, error_type.as_deref(), &text);
+                        let overloaded = state.remap_overloaded_to_429
+                            && is_overloaded(status, error_type.as_deref(), &text);
+                        if overloaded {
+                            warn!("🐢 Bedrock model overloaded ({}), returning 429", status);
+                            status = reqwest::StatusCode::TOO_MANY_REQUESTS;
+                            mapped = Some(overloaded_error_body(&text));
+                        }
+                        let mut response = if ctx.debug_trace {
+                            let mut body = mapped.unwrap_or_else(|| {
+                                openai_error_body(&text, "api_error", "upstream_error")
+                            });
+                            let raw = serde_json::from_str(&text)
+                                .unwrap_or_else(|_| Value::String(text.clone()));
+                            body["x_debug_trace"] = debug_trace(&bedrock_payload, raw);
+                            (status, Json(body)).into_response()
+                        } else {
+                            match mapped {
+                                Some(mapped) => (status, Json(mapped)).into_response(),
+                                None => (status, text).into_response(),
+                            }
+                        };
+                        if overloaded {
+                            response.headers_mut().insert(
+                                "retry-after",
+                                HeaderValue::from(state.overloaded_retry_after_secs),
+                            );
+                        }
+                        response
+                    }
+                }
+                Err(e) => {
+                    error!("❌ Failed to read response body: {}", e);
+                    (
+                        reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to read response: {}", e),
+                    )
+                        .into_response()
+                }
+            }
+        }
+        Err(e) => {
+            state.record_upstream(false);
+            error!("❌ HTTP request failed: {}", e);
+            (
+                reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Request error: {}", e),
+            )
+                .into_response()
+        }
+    }
+}
+
+// x_debug_trace body field: the transformed Bedrock request and the raw Bedrock response
+fn debug_trace(bedrock_request: &Value, bedrock_response: Value) -> Value {
+    json!({
+        "bedrock_request": bedrock_request,
+        "bedrock_response": bedrock_response,
+    })
+}
+
+pub async fn openai_chat_completions_stream_handler(
+    state: Arc<AppState>,
+    openai_req: OpenAIRequest,
+    ctx: RequestContext,
+) -> Sse<Pin<Box<dyn Stream<Item = Result<Event, axum::Error>> + Send>>> {
+    let keep_alive = state.sse_keep_alive();
+    let events: Pin<Box<dyn Stream<Item = Result<Event, axum::Error>> + Send>> = Box::pin(
+        completion_chunks(state.clone(), openai_req, ctx)
+            .map(move |data| Ok(sse_event(&state, &data))),
+    );
+
+    Sse::new(events).keep_alive(keep_alive)
+}
+
+// Most choices one streamed request may ask for with `n`
+const MAX_STREAM_CHOICES: u32 = 8;
+
+// `n` must be 1..=MAX_STREAM_CHOICES, and above 1 only when streaming.
+// Err carries a client-facing message for a 400 response
+fn validate_choice_count(req: &OpenAIRequest, streaming: bool) -> Result<(), String> {
+    match req.n.unwrap_or(1) {
+        0 => Err("'n' must be at least 1".to_string()),
+        1 => Ok(()),
+        _ if !streaming => {
+            Err("'n' greater than 1 is only supported with 'stream': true".to_string())
+        }
+        n if n > MAX_STREAM_CHOICES => Err(format!(
+            "'n' must be at most {} for streamed requests",
+            MAX_STREAM_CHOICES
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Chat completion chunks for one request, multiplexing `n` > 1 streams
+fn completion_chunks(
+    state: Arc<AppState>,
+    openai_req: OpenAIRequest,
+    ctx: RequestContext,
+) -> Pin<Box<dyn Stream<Item = String> + Send>> {
+    // Every chunk carries the same id, whichever choice's stream it comes from
+    let id = format!("chatcmpl-{}", uuid::Uuid::new_v4());
+    let model = openai_req
+        .model
+        .clone()
+        .unwrap_or_else(|| state.default_model_name.clone());
+    // Streamed responses are not buffered, so only the request is audited
+    audit(&state, &id, &model, &openai_req, Value::Null);
+
+    match openai_req.n.unwrap_or(1) {
+        0 | 1 => chat_completion_chunks(state, openai_req, ctx, id),
+        n => multiplexed_chunks(state, openai_req, ctx, n, id),
+    }
+}
+
+// `n` > 1: run n single-choice Bedrock streams concurrently and interleave their chunks,
+// each stream's choices re-indexed to its position. Per-stream usage chunks are summed
+// into one final usage chunk (Bedrock bills the prompt once per stream), then one "[DONE]".
+// A stream error ends the whole response: the other streams are dropped and no usage
+// chunk is sent, since it would leave out the failed choice
+fn multiplexed_chunks(
+    state: Arc<AppState>,
+    openai_req: OpenAIRequest,
+    ctx: RequestContext,
+    n: u32,
+    id: String,
+) -> Pin<Box<dyn Stream<Item = String> + Send>> {
+    let include_usage = openai_req
+        .stream_options
+        .as_ref()
+        .and_then(|o| o.include_usage)
+        .unwrap_or(false);
+    info!("🔀 Streaming n={} choices as parallel Bedrock requests", n);
+
+    let streams = (0..n).map(|index| {
+        let mut req = openai_req.clone();
+        req.n = None;
+        // Usage is always requested so the combined total can be reported
+        req.stream_options = Some(StreamOptions {
+            include_usage: Some(true),
+        });
+        chat_completion_chunks(state.clone(), req, ctx.clone(), id.clone())
+            .map(move |data| (index, data))
+    });
+    let mut merged = futures_util::stream::select_all(streams);
+
+    Box::pin(async_stream::stream! {
+        let mut usage_chunk: Option<Value> = None;
+        let mut failed_choice = None;
+        while let Some((index, data)) = merged.next().await {
+            if data == "[DONE]" {
+                continue;
+            }
+            let Ok(mut chunk) = serde_json::from_str::<Value>(&data) else {
+                // Plain-text stream errors pass through unchanged and end the response
+                failed_choice = Some(index);
+                yield data;
+                break;
+            };
+            if let Some(error) = chunk.get("error") {
+                // So do error bodies, except the VALIDATE_TOOL_ARGUMENTS notice: its stream goes on
+                let ends_stream = error["code"] != "invalid_tool_arguments";
+                yield data;
+                if ends_stream {
+                    failed_choice = Some(index);
+                    break;
+                }
+                continue;
+            }
+
+            let is_usage_chunk = chunk["usage"].is_object()
+                && chunk["choices"].as_array().is_some_and(|c| c.is_empty());
+            if is_usage_chunk {
+                match usage_chunk.as_mut() {
+                    Some(total) => add_usage(&mut total["usage"], &chunk["usage"]),
+                    None => usage_chunk = Some(chunk),
+                }
+                continue;
+            }
+
+            if let Some(choices) = chunk["choices"].as_array_mut() {
+                for choice in choices {
+                    choice["index"] = json!(index);
+                }
+            }
+            yield chunk.to_string();
+        }
+
+        // Dropping the remaining streams cancels their Bedrock requests
+        drop(merged);
+        if let Some(index) = failed_choice {
+            warn!("🔀 Choice {} of n={} failed, ending the stream", index, n);
+        } else if include_usage {
+            if let Some(total) = usage_chunk {
+                yield total.to_string();
+            }
+        }
+        yield "[DONE]".to_string();
+    })
+}
+
+// Sum the token counts, their details and the estimated cost of `other` into `total`
+fn add_usage(total: &mut Value, other: &Value) {
+    for field in ["prompt_tokens", "completion_tokens", "total_tokens"] {
+        let sum = total[field].as_i64().unwrap_or(0) + other[field].as_i64().unwrap_or(0);
+        total[field] = json!(sum);
+    }
+    if let Some(cost) = other["estimated_cost_usd"].as_f64() {
+        let sum = total["estimated_cost_usd"].as_f64().unwrap_or(0.0) + cost;
+        total["estimated_cost_usd"] = json!(sum);
+    }
+    for (details, field) in [
+        ("prompt_tokens_details", "cached_tokens"),
+        ("completion_tokens_details", "reasoning_tokens"),
+    ] {
+        if let Some(count) = other[details][field].as_i64() {
+            let sum = total[details][field].as_i64().unwrap_or(0) + count;
+            total[details][field] = json!(sum);
+        }
+    }
+}
+
+// A Bedrock stream past its first body chunk, ready to be converted. The body still
+// yields that chunk first
+struct OpenedStream {
+    headers: HeaderMap,
+    body: Pin<Box<dyn Stream<Item = reqwest::Result<axum::body::Bytes>> + Send>>,
+}
+
+// Why a Bedrock stream couldn't be opened
+enum StreamOpenError {
+    FirstByteTimeout,
+    RequestTimeout(Duration),
+    Upstream {
+        status: reqwest::StatusCode,
+        error_type: Option<String>,
+        text: String,
+    },
+    Request(reqwest::Error),
+}
+
+impl StreamOpenError {
+    // Headers are already sent, so the failure is reported in-stream
+    fn event_data(self, state: &AppState) -> String {
+        match self {
+            StreamOpenError::FirstByteTimeout => ttfb_timeout_error(state).to_string(),
+            StreamOpenError::RequestTimeout(timeout) => {
+                warn!(
+                    "⏰ Bedrock stream timed out after {}ms",
+                    timeout.as_millis()
+                );
+                openai_error_body(
+                    &request_timeout_message(timeout),
+                    "timeout_error",
+                    "request_timeout",
+                )
+                .to_string()
+            }
+            // An overloaded error can only be relabelled, not turned into a 429
+            StreamOpenError::Upstream {
+                status,
+                error_type,
+                text,
+            } => {
+                if state.remap_overloaded_to_429
+                    && is_overloaded(status, error_type.as_deref(), &text)
+                {
+                    overloaded_error_body(&text).to_string()
+                } else {
+                    match map_bedrock_error(status, error_type.as_deref(), &text) {
+                        Some(mapped) => mapped.to_string(),
+                        None => format!("Error {}: {}", status, text),
+                    }
+                }
+            }
+            StreamOpenError::Request(e) => format!("Request error: {}", e),
+        }
+    }
+}
+
+// Throttling and transient server errors, worth another attempt while nothing is sent
+fn retryable_status(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 500 | 502 | 503 | 504)
+}
+
+// Send a streaming request and wait for its first body chunk. Until that chunk arrives the
+// client has received no data, so throttling, transient 5xx responses and bodies that fail
+// before any data are retried, up to STREAM_RETRY_ATTEMPTS times, without it noticing.
+// STREAM_TTFB_TIMEOUT_MS bounds all attempts together; REQUEST_TIMEOUT_MS bounds each one
+async fn open_upstream_stream(
+    state: &AppState,
+    mut req: reqwest::Request,
+    timeout: Option<Duration>,
+) -> Result<OpenedStream, StreamOpenError> {
+    let ttfb_deadline = state
+        .stream_ttfb_timeout_ms
+        .map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms));
+    let mut attempt = 0;
+
+    loop {
+        let retry_req = req
+            .try_clone()
+            .filter(|_| attempt < state.stream_retry_attempts);
+        let started = std::time::Instant::now();
+        let call = with_request_timeout(timeout, execute_with_connect_retry(state, req));
+        let resp = match before_first_byte(ttfb_deadline, call).await {
+            None => return Err(StreamOpenError::FirstByteTimeout),
+            Some(Err(timeout)) => return Err(StreamOpenError::RequestTimeout(timeout)),
+            Some(Ok(Err(e))) => {
+                state.record_upstream(false);
+                return Err(StreamOpenError::Request(e));
+            }
+            Some(Ok(Ok(resp))) => resp,
+        };
+        let status = resp.status();
+        if !status.is_success() {
+            state.record_upstream(!status.is_server_error());
+        }
+        state.record_latency(started.elapsed());
+
+        let retry_reason = if status.is_success() {
+            let headers = resp.headers().clone();
+            let mut body = resp.bytes_stream();
+            // Headers can arrive before Bedrock stalls; the first body bytes share the deadline
+            let Some(first) = before_first_byte(ttfb_deadline, body.next()).await else {
+                return Err(StreamOpenError::FirstByteTimeout);
+            };
+            // Only a body that starts is a healthy attempt for the breaker
+            state.record_upstream(!matches!(first, Some(Err(_))));
+            match first {
+                Some(Err(e)) if retry_req.is_some() => {
+                    format!("Bedrock stream failed before any data: {}", e)
+                }
+                first => {
+                    return Ok(OpenedStream {
+                        headers,
+                        body: Box::pin(futures_util::stream::iter(first).chain(body)),
+                    })
+                }
+            }
+        } else if retryable_status(status) && retry_req.is_some() {
+            format!("Bedrock returned {}", status)
+        } else {
+            let error_type = bedrock_error_type(&resp);
+            let text = resp.text().await.unwrap_or_default();
+            return Err(StreamOpenError::Upstream {
+                status,
+                error_type,
+                text,
+            });
+        };
+
+        let Some(next) = retry_req else {
+            unreachable!("retries are only chosen with a request to resend");
+        };
+        attempt += 1;
+        warn!(
+            "🔁 {}, retrying stream ({}/{})",
+            retry_reason, attempt, state.stream_retry_attempts
+        );
+        tokio::time::sleep(Duration::from_millis(100 << attempt.min(6))).await;
+        req = next;
+    }
+}
+
+// Streams the chat completion as serialized chunk payloads with response id `id`, ending
+// with "[DONE]". Shared by the SSE and WebSocket transports, which only differ in framing.
+fn chat_completion_chunks(
+    state: Arc<AppState>,
+    openai_req: OpenAIRequest,
+    ctx: RequestContext,
+    id: String,
+) -> Pin<Box<dyn Stream<Item = String> + Send>> {
+    let model = openai_req
+        .model
+        .clone()
+        .unwrap_or_else(|| state.default_model_name.clone());
+    let include_usage = openai_req
+        .stream_options
+        .as_ref()
+        .and_then(|o| o.include_usage)
+        .unwrap_or(false);
+    let tool_call_forced = forces_tool_call(&openai_req);
+    let family = ModelFamily::for_request(&state, &ctx.model_id);
+    let mut bedrock_payload = family
+        .adapter()
+        .to_bedrock(&openai_req, &ctx.model_id, &state);
+    attach_request_tags(&mut bedrock_payload, family, &ctx);
+
+    let endpoint = state.model_url(&ctx.model_id, family.action(true));
+
+    let body = serde_json::to_vec(&bedrock_payload).unwrap();
+    let mut http_req = Request::builder()
+        .method("POST")
+        .uri(&endpoint)
+        .header("Content-Type", "application/json")
+        .header("X-Amzn-Bedrock-Accept", "application/json")
+        .body(body)
+        .unwrap();
+    http_req.headers_mut().extend(ctx.forward_headers.clone());
+
+    let reqwest_req = match sign_request(http_req, &state, true) {
+        Ok(r) => r,
+        Err(e) => {
+            let error_msg = format!("Signing error: {}", e);
+            return Box::pin(futures_util::stream::once(async move { error_msg }));
+        }
+    };
+
+    Box::pin(async_stream::stream! {
+        let opened = match open_upstream_stream(&state, reqwest_req, ctx.timeout).await {
+            Ok(opened) => opened,
+            Err(error) => {
+                yield error.event_data(&state);
+                yield "[DONE]".to_string();
+                return;
+            }
+        };
+
+        let mut stream_state = StreamState {
+            id,
+            model: model.clone(),
+            tool_call_forced,
+            upstream_created: upstream_created(&state, &opened.headers),
+            clock: state.clock,
+            validate_tool_arguments: state.validate_tool_arguments,
+            ..StreamState::default()
+        };
+        let mut stream = opened.body;
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut sentences = state.stream_flush_on_sentence.then(SentenceBuffer::default);
+
+        let mut sent_first = false;
+
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(bytes) => {
+                    debug!("📦 Raw chunk: {:?}", String::from_utf8_lossy(&bytes));
+
+                    buffer.extend_from_slice(&bytes);
+                    let (events, consumed) = match family {
+                        ModelFamily::Converse => decode_event_stream_messages(&buffer),
+                        _ => extract_json_from_bedrock_chunk(&buffer),
+                    };
+                    buffer.drain(..consumed);
+
+                    for json_chunk in events {
+                        let converted = family
+                            .adapter()
+                            .chunk_to_openai(&json_chunk, &mut stream_state);
+                        for openai_chunk in converted {
+                            let openai_chunk = match sentences.as_mut() {
+                                Some(sentences) => sentences.push(openai_chunk),
+                                None => Some(openai_chunk),
+                            };
+                            if let Some(openai_chunk) = openai_chunk {
+                                yield chunk_data(&state, &openai_chunk);
+                                sent_first = true;
+                            }
+                        }
+                        if let Some(message) = stream_state.invalid_tool_arguments.take() {
+                            if state.tool_arguments_error_chunk {
+                                yield openai_error_body(&message, "api_error", "invalid_tool_arguments").to_string();
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    yield format!("Stream error: {}", e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(rest) = sentences.as_mut().and_then(|s| s.finish()) {
+            yield chunk_data(&state, &rest);
+            sent_first = true;
+        }
+
+        // Ensure at least one chunk unless disabled via EMIT_EMPTY_STREAM_CHUNK=false
+        if !sent_first && state.emit_empty_stream_chunk {
+            let dummy = json!({
+                "id": stream_state.id,
+                "object": CHAT_COMPLETION_CHUNK_OBJECT,
+                "created": stream_state.created(),
+                "model": model,
+                "choices": [{
+                    "index": 0,
+                    "delta": {"role": "assistant", "content": ""},
+                    "finish_reason": null
+                }]
+            });
+            yield chunk_data(&state, &dummy);
+        }
+
+        ctx.record_tokens(
+            &state,
+            stream_state.prompt_tokens,
+            stream_state.completion_tokens,
+        );
+        if include_usage {
+            let mut chunk = usage_chunk(&stream_state, &model);
+            if let Some(usage) = chunk.usage.as_mut() {
+                usage.estimated_cost_usd = state.estimate_cost(
+                    &ctx.model_id,
+                    usage.prompt_tokens,
+                    usage.completion_tokens,
+                );
+            }
+            yield chunk_data(&state, &chunk);
+        }
+
+        yield "[DONE]".to_string();
+    })
+}
+
+// Runs several non-streaming chat completions concurrently (at most BATCH_CONCURRENCY
+// at a time) and returns their bodies in request order. Failed items are returned as
+// OpenAI error objects with their HTTP status, so one failure doesn't fail the batch.
+pub async fn openai_chat_batch_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(requests): Json<Vec<OpenAIRequest>>,
+) -> Response {
+    info!("📚 Chat completions batch ({} requests)", requests.len());
+
+    let ctx = match RequestContext::from_headers(&state, &headers) {
+        Ok(ctx) => ctx,
+        Err(e) => return invalid_header(&e),
+    };
+    state.request_stats.record_model(&ctx.model_id);
+
+    let results: Vec<Value> = futures_util::stream::iter(requests)
+        .map(|mut openai_req| {
+            let state = state.clone();
+            let ctx = ctx.clone();
+            async move {
+                if openai_req.stream == Some(true) {
+                    return batch_item_error(
+                        reqwest::StatusCode::BAD_REQUEST,
+                        "Streaming is not supported in batch requests",
+                        "invalid_request_error",
+                        "invalid_value",
+                    );
+                }
+                if let Err(rejection) = validate_chat_request(&state, &ctx, &mut openai_req, false)
+                {
+                    return rejection.batch_item();
+                }
+
+                let response = openai_chat_completions_json(state, openai_req, ctx).await;
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap_or_default();
+
+                match serde_json::from_slice::<Value>(&body) {
+                    Ok(value) if status.is_success() => value,
+                    Ok(mut value) if value.get("error").is_some() => {
+                        value["status"] = json!(status.as_u16());
+                        value
+                    }
+                    _ => batch_item_error(
+                        status,
+                        &String::from_utf8_lossy(&body),
+                        "api_error",
+                        "upstream_error",
+                    ),
+                }
+            }
+        })
+        .buffered(state.batch_concurrency)
+        .collect()
+        .await;
+
+    Json(results).into_response()
+}
+
+fn batch_item_error(
+    status: reqwest::StatusCode,
+    message: &str,
+    error_type: &str,
+    code: &str,
+) -> Value {
+    let mut error = openai_error_body(message, error_type, code);
+    error["status"] = json!(status.as_u16());
+    error
+}
+
+// WebSocket transport for streaming chat: the client sends one OpenAI chat request
+// as a text message and receives the same chunk payloads as the SSE stream,
+// one per message, ending with "[DONE]"
+pub async fn openai_chat_ws_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    // Resolve the model before upgrading so a bad override is a plain 400
+    let ctx = match RequestContext::from_headers(&state, &headers) {
+        Ok(ctx) => ctx,
+        Err(e) => return invalid_header(&e),
+    };
+    state.request_stats.record_model(&ctx.model_id);
+
+    ws.on_upgrade(move |socket| chat_ws_session(socket, state, ctx))
+}
+
+async fn chat_ws_session(mut socket: WebSocket, state: Arc<AppState>, ctx: RequestContext) {
+    let mut openai_req = match socket.recv().await {
+        Some(Ok(Message::Text(text))) => match serde_json::from_str::<OpenAIRequest>(&text) {
+            Ok(req) => req,
+            Err(e) => {
+                let error = openai_error_body(
+                    &format!("Invalid chat request: {}", e),
+                    "invalid_request_error",
+                    "invalid_request",
+                );
+                let _ = socket.send(Message::Text(error.to_string().into())).await;
+                return;
+            }
+        },
+        _ => return,
+    };
+
+    // Every WebSocket response is streamed, whatever the request says
+    openai_req.stream = Some(true);
+    if let Err(rejection) = validate_chat_request(&state, &ctx, &mut openai_req, true) {
+        let _ = socket
+            .send(Message::Text(rejection.body().to_string().into()))
+            .await;
+        return;
+    }
+
+    info!(
+        "🔌 WebSocket chat request (messages={})",
+        openai_req.messages.len()
+    );
+
+    // Chunks are relayed while listening for a {"type":"cancel"} message from the client.
+    // Dropping the chunk stream drops the Bedrock response, which stops generation
+    let mut chunks = completion_chunks(state, openai_req, ctx);
+    loop {
+        tokio::select! {
+            data = chunks.next() => {
+                let Some(data) = data else {
+                    break;
+                };
+                if socket.send(Message::Text(data.into())).await.is_err() {
+                    info!("🔌 WebSocket client disconnected mid-stream");
+                    return;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) if is_cancel_message(&text) => {
+                    info!("🛑 WebSocket client cancelled the generation");
+                    drop(chunks);
+                    let cancelled = json!({"type": "cancelled"}).to_string();
+                    let _ = socket.send(Message::Text(cancelled.into())).await;
+                    break;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                    info!("🔌 WebSocket client disconnected mid-stream");
+                    return;
+                }
+                // Pings are answered by axum; other messages are ignored mid-stream
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+fn is_cancel_message(text: &str) -> bool {
+    serde_json::from_str::<Value>(text).is_ok_and(|m| m["type"] == "cancel")
+}
+
+// One SSE event carrying `data`. Per the SSE spec, a multi-line payload (e.g. an upstream
+// error body) is sent as several `data:` lines that clients rejoin with "\n"; CR and CRLF
+// are normalized to LF first, as a bare CR would end the line early. With
+// SSE_SINGLE_LINE_DATA=true line breaks are escaped as `\n` instead, for minimal clients
+// that read exactly one `data:` line per event
+fn sse_event(state: &AppState, data: &str) -> Event {
+    let data = data.replace("\r\n", "\n").replace('\r', "\n");
+    if state.sse_single_line_data {
+        Event::default().data(data.replace('\n', "\\n"))
+    } else {
+        Event::default().data(data)
+    }
+}
+
+// Serialize a stream chunk with RESPONSE_FIELDS exclusions applied
+fn chunk_data(state: &AppState, chunk: &impl serde::Serialize) -> String {
+    let mut chunk = serde_json::to_value(chunk).unwrap_or(Value::Null);
+    state.filter_response_fields(&mut chunk);
+    chunk.to_string()
+}
+
+pub async fn catch_all_handler(
+    uri: axum::http::Uri,
+    method: axum::http::Method,
+) -> impl IntoResponse {
+    info!("🔍 Unhandled request: {} {}", method, uri);
+    openai_error(
+        axum::http::StatusCode::NOT_FOUND,
+        "Unknown endpoint",
+        "invalid_request_error",
+        "not_found",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api_keys::ApiKeys,
+        audit::AuditLogger,
+        sessions::StickySessions,
+        state::{test_state, test_state_with},
+    };
+
+    // An InvokeModel stream frame carrying `event` as base64
+    fn frame(event: &Value) -> Vec<u8> {
+        let b64 = base64::prelude::BASE64_STANDARD.encode(event.to_string());
+        format!(r#"{{"bytes":"{}"}}"#, b64).into_bytes()
+    }
+
+    #[test]
+    fn extracts_single_frame() {
+        let event = json!({"type": "message_stop"});
+        let buffer = frame(&event);
+
+        let (events, consumed) = extract_json_from_bedrock_chunk(&buffer);
+        assert_eq!(events, vec![event]);
+        assert_eq!(consumed, buffer.len());
+    }
+
+    #[test]
+    fn extracts_two_frames_from_one_buffer() {
+        let first = json!({"type": "content_block_delta", "delta": {"text": "héllo 👋"}});
+        let second = json!({"type": "message_stop"});
+        let buffer = [frame(&first), frame(&second)].concat();
+
+        let (events, consumed) = extract_json_from_bedrock_chunk(&buffer);
+        assert_eq!(events, vec![first, second]);
+        assert_eq!(consumed, buffer.len());
+    }
+
+    #[test]
+    fn leaves_split_frame_for_next_read() {
+        let first = json!({"type": "message_start"});
+        let second = json!({"type": "content_block_delta", "delta": {"text": "split"}});
+        let mut buffer = [frame(&first), frame(&second)].concat();
+        let split_at = buffer.len() - 10;
+
+        let (events, consumed) = extract_json_from_bedrock_chunk(&buffer[..split_at]);
+        assert_eq!(events, vec![first]);
+        assert_eq!(consumed, frame(&json!({"type": "message_start"})).len());
+
+        // The caller drops the consumed prefix and retries once the rest arrives
+        buffer.drain(..consumed);
+        let (events, consumed) = extract_json_from_bedrock_chunk(&buffer);
+        assert_eq!(events, vec![second]);
+        assert_eq!(consumed, buffer.len());
+    }
+
+    #[test]
+    fn skips_binary_prelude_bytes() {
+        let event = json!({"type": "message_stop"});
+        let mut buffer = vec![0x00, 0x00, 0x01, 0x2a, 0xff, b'{', b'x', b'}', b':'];
+        buffer.extend(b":event-type\x07\x00\x05chunk");
+        buffer.extend(frame(&event));
+        buffer.extend([0xde, 0xad, 0xbe, 0xef]);
+
+        let (events, consumed) = extract_json_from_bedrock_chunk(&buffer);
+        assert_eq!(events, vec![event]);
+        // Trailing bytes shorter than a frame start are kept, as one may be arriving
+        assert_eq!(consumed, buffer.len() - 4);
+    }
+
+    #[test]
+    fn skips_frame_with_invalid_base64() {
+        let event = json!({"type": "message_stop"});
+        let mut buffer = br#"{"bytes":"not base64!"}"#.to_vec();
+        buffer.extend(frame(&event));
+
+        let (events, consumed) = extract_json_from_bedrock_chunk(&buffer);
+        assert_eq!(events, vec![event]);
+        assert_eq!(consumed, buffer.len());
+    }
+
+    // A converse-stream event-stream message with an `:event-type` header and valid CRCs
+    fn event_message(event_type: &str, payload: &[u8]) -> Vec<u8> {
+        let mut headers = vec![b":event-type".len() as u8];
+        headers.extend(b":event-type");
+        headers.push(7);
+        headers.extend((event_type.len() as u16).to_be_bytes());
+        headers.extend(event_type.as_bytes());
+
+        let total_len = 12 + headers.len() + payload.len() + 4;
+        let mut message = (total_len as u32).to_be_bytes().to_vec();
+        message.extend((headers.len() as u32).to_be_bytes());
+        message.extend(crc32fast::hash(&message).to_be_bytes());
+        message.extend(headers);
+        message.extend(payload);
+        message.extend(crc32fast::hash(&message).to_be_bytes());
+        message
+    }
+
+    #[test]
+    fn decodes_single_event_stream_message() {
+        let delta = json!({"contentBlockIndex": 0, "delta": {"text": "hi"}});
+        let buffer = event_message("contentBlockDelta", delta.to_string().as_bytes());
+
+        let (events, consumed) = decode_event_stream_messages(&buffer);
+        assert_eq!(events, vec![json!({"contentBlockDelta": delta})]);
+        assert_eq!(consumed, buffer.len());
+    }
+
+    #[test]
+    fn decodes_two_event_stream_messages_from_one_buffer() {
+        let delta = json!({"contentBlockIndex": 0, "delta": {"text": "héllo 👋"}});
+        let stop = json!({"stopReason": "end_turn"});
+        let buffer = [
+            event_message("contentBlockDelta", delta.to_string().as_bytes()),
+            event_message("messageStop", stop.to_string().as_bytes()),
+        ]
+        .concat();
+
+        let (events, consumed) = decode_event_stream_messages(&buffer);
+        assert_eq!(
+            events,
+            vec![
+                json!({"contentBlockDelta": delta}),
+                json!({"messageStop": stop})
+            ]
+        );
+        assert_eq!(consumed, buffer.len());
+    }
+
+    #[test]
+    fn leaves_split_event_stream_message_for_next_read() {
+        let start = json!({"role": "assistant"});
+        let first = event_message("messageStart", start.to_string().as_bytes());
+        let stop = json!({"stopReason": "end_turn"});
+        let mut buffer = [
+            first.clone(),
+            event_message("messageStop", stop.to_string().as_bytes()),
+        ]
+        .concat();
+
+        // Cut inside the second message's prelude, then inside its payload
+        for split_at in [first.len() + 6, buffer.len() - 10] {
+            let (events, consumed) = decode_event_stream_messages(&buffer[..split_at]);
+            assert_eq!(events, vec![json!({"messageStart": start})]);
+            assert_eq!(consumed, first.len());
+        }
+
+        buffer.drain(..first.len());
+        let (events, consumed) = decode_event_stream_messages(&buffer);
+        assert_eq!(events, vec![json!({"messageStop": stop})]);
+        assert_eq!(consumed, buffer.len());
+    }
+
+    #[test]
+    fn discards_event_stream_buffer_with_corrupt_prelude() {
+        let stop = json!({"stopReason": "end_turn"});
+        let mut buffer = event_message("messageStop", stop.to_string().as_bytes());
+        buffer[9] ^= 0xff;
+        buffer.extend(event_message("messageStop", stop.to_string().as_bytes()));
+
+        // The lengths can't be trusted, so nothing after a bad prelude is read
+        let (events, consumed) = decode_event_stream_messages(&buffer);
+        assert!(events.is_empty());
+        assert_eq!(consumed, buffer.len());
+    }
+
+    #[test]
+    fn skips_event_stream_message_failing_crc_or_json() {
+        let stop = json!({"stopReason": "end_turn"});
+        let mut corrupt = event_message("messageStop", stop.to_string().as_bytes());
+        let last = corrupt.len() - 5;
+        corrupt[last] ^= 0xff;
+        let buffer = [
+            corrupt,
+            event_message("messageStop", b"not json"),
+            event_message("messageStop", stop.to_string().as_bytes()),
+        ]
+        .concat();
+
+        let (events, consumed) = decode_event_stream_messages(&buffer);
+        assert_eq!(events, vec![json!({"messageStop": stop})]);
+        assert_eq!(consumed, buffer.len());
+    }
+
+    fn chat_request(body: Value) -> OpenAIRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn session_headers(session_id: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-session-id", HeaderValue::from_static(session_id));
+        headers
+    }
+
+    #[tokio::test]
+    async fn session_keeps_model_of_last_accepted_request() {
+        let mut state = test_state();
+        state.sticky_sessions = Some(Arc::new(StickySessions::new(Duration::from_secs(60))));
+        state.allowed_model_ids = vec!["anthropic.claude-3-sonnet".to_string()];
+        state.reject_logprobs = true;
+        let state = Arc::new(state);
+        let hi = json!([{"role": "user", "content": "hi"}]);
+
+        // An accepted request with an explicit model and override is remembered
+        let mut headers = session_headers("s1");
+        headers.insert(
+            "x-bedrock-model-id",
+            HeaderValue::from_static("anthropic.claude-3-sonnet"),
+        );
+        let mut req = chat_request(json!({"model": "first", "messages": hi}));
+        let session_id = apply_session_model(&state, &mut headers, &mut req).unwrap();
+        remember_session_model(&state, &session_id, &headers, &req);
+
+        // A rejected request must not replace it
+        let req = chat_request(json!({"model": "rejected", "logprobs": true, "messages": hi}));
+        let response =
+            openai_chat_completions_handler(State(state.clone()), session_headers("s1"), Json(req))
+                .await;
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_IMPLEMENTED);
+
+        // A later request that omits both gets the remembered model and override
+        let mut headers = session_headers("s1");
+        let mut req = chat_request(json!({"messages": hi}));
+        apply_session_model(&state, &mut headers, &mut req);
+        assert_eq!(req.model.as_deref(), Some("first"));
+        assert_eq!(headers["x-bedrock-model-id"], "anthropic.claude-3-sonnet");
+        let ctx = RequestContext::from_headers(&state, &headers).ok().unwrap();
+        assert_eq!(ctx.model_id, "anthropic.claude-3-sonnet");
+
+        // An explicit model wins, and other sessions are unaffected
+        let mut req = chat_request(json!({"model": "explicit", "messages": hi}));
+        apply_session_model(&state, &mut session_headers("s1"), &mut req);
+        assert_eq!(req.model.as_deref(), Some("explicit"));
+        let mut headers = session_headers("s2");
+        let mut req = chat_request(json!({"messages": hi}));
+        apply_session_model(&state, &mut headers, &mut req);
+        assert_eq!(req.model, None);
+        assert!(!headers.contains_key("x-bedrock-model-id"));
+    }
+
+    #[test]
+    fn session_model_expires_after_ttl() {
+        let sessions = StickySessions::new(Duration::from_millis(20));
+        let model = SessionModel {
+            model: Some("first".to_string()),
+            model_id: None,
+        };
+        sessions.remember("s1", model);
+        assert!(sessions.recall("s1").is_some());
+
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(sessions.recall("s1").is_none());
+    }
+
+    // Serve `router` as a stand-in Bedrock runtime endpoint and point `state` at it
+    async fn mock_bedrock(state: &mut AppState, router: axum::Router) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        state.bedrock_endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    }
+
+    // Anthropic InvokeModel reply naming how many messages it received, or a 500 when
+    // the request mentions "fail"
+    async fn anthropic_invoke(Json(body): Json<Value>) -> Response {
+        if body.to_string().contains("fail") {
+            let error = json!({"message": "mock upstream failure"});
+            return (reqwest::StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+        }
+        let received = body["messages"].as_array().map_or(0, Vec::len);
+        Json(json!({
+            "id": "msg_mock",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "text", "text": format!("{} messages", received)}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 3, "output_tokens": 2},
+        }))
+        .into_response()
+    }
+
+    async fn response_json(response: Response) -> (reqwest::StatusCode, Value) {
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    // Two tool-call round trips between the first and last user message
+    fn tool_turn_messages() -> Value {
+        let call = |id: &str| {
+            json!({"role": "assistant", "content": null, "tool_calls": [
+                {"id": id, "type": "function", "function": {"name": "lookup", "arguments": "{}"}}
+            ]})
+        };
+        json!([
+            {"role": "user", "content": "look it up"},
+            call("c1"),
+            {"role": "tool", "tool_call_id": "c1", "content": "r1"},
+            call("c2"),
+            {"role": "tool", "tool_call_id": "c2", "content": "r2"},
+            {"role": "user", "content": "thanks"},
+        ])
+    }
+
+    #[tokio::test]
+    async fn batch_mixes_successes_and_per_item_errors() {
+        let mut state = test_state();
+        state.max_tool_turns = Some(1);
+        state.reject_tool_turns = true;
+        state.strict_sampling_params = true;
+        state.truncate_context_tokens = Some(100);
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route("/model/{id}/invoke", axum::routing::post(anthropic_invoke)),
+        )
+        .await;
+
+        let long = "x".repeat(400);
+        let hi = json!([{"role": "user", "content": "hi"}]);
+        let requests = vec![
+            chat_request(json!({"messages": hi})),
+            chat_request(json!({"messages": tool_turn_messages()})),
+            chat_request(json!({"messages": hi, "temperature": 0.5, "top_p": 0.9})),
+            chat_request(json!({"messages": hi, "stream": true})),
+            chat_request(json!({"messages": [{"role": "user", "content": "please fail"}]})),
+            chat_request(json!({"messages": [
+                {"role": "user", "content": long},
+                {"role": "assistant", "content": long},
+                {"role": "user", "content": "latest"},
+            ]})),
+        ];
+
+        let response =
+            openai_chat_batch_handler(State(Arc::new(state)), HeaderMap::new(), Json(requests))
+                .await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+        let items = body.as_array().unwrap();
+        assert_eq!(items.len(), 6);
+
+        assert_eq!(items[0]["choices"][0]["message"]["content"], "1 messages");
+        // The limits /v1/chat/completions applies hold for batch items too
+        assert_eq!(items[1]["status"], 400);
+        assert_eq!(items[1]["error"]["code"], "tool_turn_limit_exceeded");
+        assert_eq!(items[2]["status"], 400);
+        assert_eq!(items[2]["error"]["code"], "invalid_value");
+        assert_eq!(items[3]["status"], 400);
+        assert_eq!(items[4]["status"], 500);
+        assert!(items[4]["error"].is_object());
+        // TRUNCATE_CONTEXT_TOKENS dropped the oldest turn before calling Bedrock
+        assert_eq!(items[5]["choices"][0]["message"]["content"], "1 messages");
+    }
+
+    // InvokeModel stream body: one `{"bytes": ...}` frame per Anthropic event
+    fn anthropic_stream_body(text_deltas: &[&str]) -> Vec<u8> {
+        let mut events =
+            vec![json!({"type": "message_start", "message": {"usage": {"input_tokens": 3}}})];
+        events.extend(text_deltas.iter().map(|text| {
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": text}})
+        }));
+        events.push(json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}, "usage": {"output_tokens": 2}}));
+        events.push(json!({"type": "message_stop"}));
+        events.iter().flat_map(frame).collect()
+    }
+
+    async fn anthropic_stream() -> Vec<u8> {
+        anthropic_stream_body(&["Hel", "lo"])
+    }
+
+    // Send one chat request over /v1/chat/ws and collect the text messages it gets back
+    async fn ws_exchange(addr: std::net::SocketAddr, request: Value) -> Vec<String> {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut ws, _) =
+            tokio_tungstenite::client_async(format!("ws://{}/v1/chat/ws", addr), stream)
+                .await
+                .unwrap();
+        ws.send(WsMessage::Text(request.to_string().into()))
+            .await
+            .unwrap();
+
+        let mut received = Vec::new();
+        while let Some(Ok(message)) = ws.next().await {
+            match message {
+                WsMessage::Text(text) => received.push(text.to_string()),
+                WsMessage::Close(_) => break,
+                _ => {}
+            }
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn websocket_streams_chunks_and_applies_chat_checks() {
+        let mut state = test_state();
+        state.max_tool_turns = Some(1);
+        state.reject_tool_turns = true;
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/invoke-with-response-stream",
+                axum::routing::post(anthropic_stream),
+            ),
+        )
+        .await;
+
+        let proxy = axum::Router::new()
+            .route("/v1/chat/ws", axum::routing::get(openai_chat_ws_handler))
+            .with_state(Arc::new(state));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, proxy).await.unwrap() });
+
+        let received = ws_exchange(
+            addr,
+            json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream_options": {"include_usage": true},
+            }),
+        )
+        .await;
+        assert_eq!(received.last().map(String::as_str), Some("[DONE]"));
+        let chunks: Vec<Value> = received[..received.len() - 1]
+            .iter()
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        let text: String = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert_eq!(text, "Hello");
+        assert!(chunks
+            .iter()
+            .all(|c| c["object"] == CHAT_COMPLETION_CHUNK_OBJECT));
+        let usage = &chunks.last().unwrap()["usage"];
+        assert_eq!(usage["prompt_tokens"], 3);
+        assert_eq!(usage["completion_tokens"], 2);
+
+        // MAX_TOOL_TURNS holds over WebSocket too: one error message, then the socket closes
+        let received = ws_exchange(addr, json!({"messages": tool_turn_messages()})).await;
+        assert_eq!(received.len(), 1);
+        let error: Value = serde_json::from_str(&received[0]).unwrap();
+        assert_eq!(error["error"]["code"], "tool_turn_limit_exceeded");
+    }
+
+    type SeenMaxTokens = Arc<std::sync::Mutex<Vec<u64>>>;
+
+    // Anthropic InvokeModel reply that is cut off mid-JSON below 200 max_tokens
+    async fn truncating_invoke(
+        State(seen): State<SeenMaxTokens>,
+        Json(body): Json<Value>,
+    ) -> Json<Value> {
+        let max_tokens = body["max_tokens"].as_u64().unwrap();
+        seen.lock().unwrap().push(max_tokens);
+        let (text, stop_reason) = if max_tokens < 200 {
+            (r#"{"answer": "#, "max_tokens")
+        } else {
+            (r#"{"answer": 42}"#, "end_turn")
+        };
+        Json(json!({
+            "id": "msg_mock",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "text", "text": text}],
+            "stop_reason": stop_reason,
+            "usage": {"input_tokens": 3, "output_tokens": max_tokens},
+        }))
+    }
+
+    async fn truncating_bedrock(state: &mut AppState) -> SeenMaxTokens {
+        let seen = SeenMaxTokens::default();
+        let router = axum::Router::new()
+            .route("/model/{id}/invoke", axum::routing::post(truncating_invoke))
+            .with_state(seen.clone());
+        mock_bedrock(state, router).await;
+        seen
+    }
+
+    fn json_mode_request(max_tokens: u32) -> OpenAIRequest {
+        chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "max_tokens": max_tokens,
+            "response_format": {"type": "json_object"},
+        }))
+    }
+
+    #[tokio::test]
+    async fn json_retry_doubles_max_tokens_and_audits_returned_attempt() {
+        let audit_path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let mut state = test_state();
+        state.json_retry_max_tokens = Some(400);
+        state.audit_log = Some(Arc::new(AuditLogger::new(
+            audit_path.to_string_lossy().into_owned(),
+            vec![],
+        )));
+        let seen = truncating_bedrock(&mut state).await;
+        let state = Arc::new(state);
+        let mut ctx = RequestContext::from_headers(&state, &HeaderMap::new())
+            .ok()
+            .unwrap();
+        ctx.api_key = Some(Arc::new(ApiKey {
+            name: "team-a".to_string(),
+            max_tokens_cap: None,
+            max_temperature: None,
+        }));
+
+        let response =
+            openai_chat_completions_json(state.clone(), json_mode_request(100), ctx).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+        assert_eq!(*seen.lock().unwrap(), [100, 200]);
+        // Bedrock bills every attempt, so each one counts toward the key's tokens
+        let tokens = &state.request_stats.snapshot()["tokens_by_api_key"]["team-a"];
+        assert_eq!(tokens["prompt_tokens"], 6);
+        assert_eq!(tokens["completion_tokens"], 300);
+        assert_eq!(body["choices"][0]["finish_reason"], "stop");
+        assert_eq!(
+            body["choices"][0]["message"]["content"],
+            r#"{"answer": 42}"#
+        );
+
+        // One audit entry, for the attempt the client got
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        std::fs::remove_file(&audit_path).ok();
+        let entries: Vec<Value> = audit
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["request"]["max_tokens"], 200);
+        assert_eq!(entries[0]["request_id"], body["id"]);
+    }
+
+    #[tokio::test]
+    async fn json_retry_stops_when_context_window_leaves_no_room() {
+        let mut state = test_state();
+        state.json_retry_max_tokens = Some(400);
+        // "hi" is estimated at 4 tokens, leaving 146 for output
+        state.model_context_window = Some(150);
+        let seen = truncating_bedrock(&mut state).await;
+        let state = Arc::new(state);
+        let ctx = RequestContext::from_headers(&state, &HeaderMap::new())
+            .ok()
+            .unwrap();
+
+        let response = openai_chat_completions_json(state, json_mode_request(100), ctx).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+        // Grows to the room once, then returns instead of resending the same request
+        assert_eq!(*seen.lock().unwrap(), [100, 146]);
+        assert_eq!(body["choices"][0]["finish_reason"], "length");
+    }
+
+    // State and headers for a caller whose key caps max_tokens at `cap`
+    fn capped_key(state: &mut AppState, cap: u32, clamp: bool) -> HeaderMap {
+        let key = ApiKey {
+            name: "team-a".to_string(),
+            max_tokens_cap: Some(cap),
+            max_temperature: None,
+        };
+        let keys = ApiKeys::new(vec![("sk-a".to_string(), Arc::new(key))], clamp);
+        state.api_keys = Some(Arc::new(keys));
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer sk-a".parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn json_retry_stays_under_the_key_cap() {
+        let mut state = test_state();
+        state.json_retry_max_tokens = Some(400);
+        let headers = capped_key(&mut state, 150, false);
+        let seen = truncating_bedrock(&mut state).await;
+        let state = Arc::new(state);
+        let ctx = RequestContext::from_headers(&state, &headers).ok().unwrap();
+
+        let response = openai_chat_completions_json(state, json_mode_request(100), ctx).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+        assert_eq!(*seen.lock().unwrap(), [100, 150]);
+        assert_eq!(body["choices"][0]["finish_reason"], "length");
+    }
+
+    #[tokio::test]
+    async fn invoke_applies_key_caps_to_native_payloads() {
+        let native = json!({
+            "anthropic_version": "bedrock-2023-05-31",
+            "max_tokens": 100000,
+            "messages": [{"role": "user", "content": "hi"}],
+        });
+
+        let mut state = test_state();
+        let headers = capped_key(&mut state, 1024, false);
+        let response = invoke_handler(State(Arc::new(state)), headers, Json(native.clone())).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "api_key_limit_exceeded");
+
+        let mut state = test_state();
+        let headers = capped_key(&mut state, 1024, true);
+        let seen = truncating_bedrock(&mut state).await;
+        let response = invoke_handler(State(Arc::new(state)), headers, Json(native)).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(*seen.lock().unwrap(), [1024]);
+    }
+
+    #[tokio::test]
+    async fn invoke_applies_key_caps_to_openai_payloads() {
+        let openai = json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "max_tokens": 100000,
+        });
+
+        let mut state = test_state();
+        let headers = capped_key(&mut state, 1024, false);
+        let response = invoke_handler(State(Arc::new(state)), headers, Json(openai.clone())).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "api_key_limit_exceeded");
+
+        let mut state = test_state();
+        let headers = capped_key(&mut state, 1024, true);
+        let seen = truncating_bedrock(&mut state).await;
+        let response = invoke_handler(State(Arc::new(state)), headers, Json(openai)).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(*seen.lock().unwrap(), [1024]);
+    }
+
+    #[tokio::test]
+    async fn reasoning_effort_budget_counts_toward_the_key_cap() {
+        let request = || {
+            chat_request(json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 1024,
+                "reasoning_effort": "low",
+            }))
+        };
+
+        let mut state = test_state();
+        let headers = capped_key(&mut state, 1500, false);
+        let state = Arc::new(state);
+        let mut ctx = RequestContext::from_headers(&state, &headers).ok().unwrap();
+        ctx.model_id = "anthropic.claude-sonnet-4-20250514-v1:0".to_string();
+        // The 1024-token low budget is added to max_tokens, sending 2048
+        let mut req = request();
+        assert!(ctx.enforce_key_limits(&state, &mut req).is_err());
+
+        let mut state = test_state();
+        let headers = capped_key(&mut state, 1500, true);
+        let state = Arc::new(state);
+        let mut ctx = RequestContext::from_headers(&state, &headers).ok().unwrap();
+        ctx.model_id = "anthropic.claude-sonnet-4-20250514-v1:0".to_string();
+        let mut req = request();
+        ctx.enforce_key_limits(&state, &mut req).unwrap();
+        let budget = thinking_budget(&req, &ctx.model_id, &state).unwrap();
+        assert_eq!(req.max_tokens, Some(1500));
+        assert!(budget < 1500);
+    }
+
+    // The `data:` payloads of an SSE response
+    async fn sse_data(response: Response) -> Vec<String> {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(str::to_string)
+            .collect()
+    }
+
+    // A 200 whose body fails before any data arrives
+    fn failing_stream() -> Response {
+        let error = std::io::Error::other("mock body failure");
+        // The pause lets the headers go out before the body fails
+        let body = futures_util::stream::once(async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Err::<Vec<u8>, _>(error)
+        });
+        Response::new(axum::body::Body::from_stream(body))
+    }
+
+    #[tokio::test]
+    async fn stream_retries_until_bedrock_sends_data() {
+        let mut state = test_state();
+        state.stream_retry_attempts = 2;
+        let breaker = Arc::new(crate::circuit_breaker::CircuitBreaker::new(
+            2,
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        ));
+        state.circuit_breaker = Some(breaker.clone());
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let seen = calls.clone();
+        let stream = move || {
+            let call = seen.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                match call {
+                    0 => reqwest::StatusCode::SERVICE_UNAVAILABLE.into_response(),
+                    1 => failing_stream(),
+                    _ => anthropic_stream().await.into_response(),
+                }
+            }
+        };
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/invoke-with-response-stream",
+                axum::routing::post(stream),
+            ),
+        )
+        .await;
+        let state = Arc::new(state);
+
+        // The 503 and the failed body count against the breaker, which opens at two
+        // failures; the stream that finally starts closes it again
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+        }));
+        let response =
+            openai_chat_completions_handler(State(state.clone()), HeaderMap::new(), Json(request))
+                .await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let data = sse_data(response).await;
+        assert_eq!(data.last().map(String::as_str), Some("[DONE]"));
+        let text: String = data[..data.len() - 1]
+            .iter()
+            .map(|d| serde_json::from_str::<Value>(d).unwrap())
+            .filter_map(|c| {
+                c["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .map(str::to_string)
+            })
+            .collect();
+        assert_eq!(text, "Hello");
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert!(breaker.allow_request());
+    }
+
+    #[tokio::test]
+    async fn failed_stream_body_counts_against_breaker() {
+        let mut state = test_state();
+        state.stream_retry_attempts = 1;
+        let breaker = Arc::new(crate::circuit_breaker::CircuitBreaker::new(
+            2,
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        ));
+        state.circuit_breaker = Some(breaker.clone());
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/invoke-with-response-stream",
+                axum::routing::post(|| async { failing_stream() }),
+            ),
+        )
+        .await;
+
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+        }));
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        sse_data(response).await;
+        // Both attempts got a 200, but neither body started
+        assert!(!breaker.allow_request());
+    }
+
+    // Serve n=2 streaming requests from a mock where the second Bedrock call gets `second`
+    async fn stream_two_choices(mut state: AppState, second: Response) -> Vec<Value> {
+        let second = Arc::new(std::sync::Mutex::new(Some(second)));
+        let stream = move || {
+            let reply = second.lock().unwrap().take();
+            async move {
+                match reply {
+                    Some(reply) => reply,
+                    None => anthropic_stream().await.into_response(),
+                }
+            }
+        };
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/invoke-with-response-stream",
+                axum::routing::post(stream),
+            ),
+        )
+        .await;
+
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+            "n": 2,
+            "stream_options": {"include_usage": true},
+        }));
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        let data = sse_data(response).await;
+        assert_eq!(data.last().map(String::as_str), Some("[DONE]"));
+        data[..data.len() - 1]
+            .iter()
+            .map(|d| serde_json::from_str(d).unwrap_or_else(|_| json!(d)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn multiplexes_n_choices_with_combined_usage() {
+        let audit_path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let mut state = test_state();
+        state.audit_log = Some(Arc::new(AuditLogger::new(
+            audit_path.to_string_lossy().into_owned(),
+            vec![],
+        )));
+        let chunks = stream_two_choices(state, anthropic_stream().await.into_response()).await;
+
+        for index in 0..2 {
+            let text: String = chunks
+                .iter()
+                .filter_map(|c| c["choices"].as_array())
+                .flatten()
+                .filter(|choice| choice["index"] == index)
+                .filter_map(|choice| choice["delta"]["content"].as_str())
+                .collect();
+            assert_eq!(text, "Hello");
+        }
+        let usage = &chunks.last().unwrap()["usage"];
+        assert_eq!(usage["prompt_tokens"], 6);
+        assert_eq!(usage["completion_tokens"], 4);
+        assert_eq!(chunks.iter().filter(|c| c["usage"].is_object()).count(), 1);
+
+        // Both choices and the usage chunk belong to one response
+        let id = &chunks[0]["id"];
+        assert!(id.as_str().unwrap().starts_with("chatcmpl-"));
+        assert!(chunks.iter().all(|c| &c["id"] == id));
+
+        // The request is audited once, under that id
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        std::fs::remove_file(&audit_path).ok();
+        let entries: Vec<Value> = audit
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(&entries[0]["request_id"], id);
+        assert_eq!(entries[0]["request"]["n"], 2);
+    }
+
+    #[tokio::test]
+    async fn single_choice_stream_keeps_one_id() {
+        let mut state = test_state();
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/invoke-with-response-stream",
+                axum::routing::post(anthropic_stream),
+            ),
+        )
+        .await;
+
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+            "stream_options": {"include_usage": true},
+        }));
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        let data = sse_data(response).await;
+        let chunks: Vec<Value> = data[..data.len() - 1]
+            .iter()
+            .map(|d| serde_json::from_str(d).unwrap())
+            .collect();
+
+        // Content chunks and the final usage chunk all share the response id
+        assert!(chunks.len() > 2);
+        assert!(chunks.last().unwrap()["usage"].is_object());
+        let id = &chunks[0]["id"];
+        assert!(id.as_str().unwrap().starts_with("chatcmpl-"));
+        assert!(chunks.iter().all(|c| &c["id"] == id));
+    }
+
+    #[tokio::test]
+    async fn failed_choice_ends_multiplexed_stream() {
+        let error = json!({"message": "mock validation failure"});
+        let failure = (reqwest::StatusCode::BAD_REQUEST, Json(error)).into_response();
+        let chunks = stream_two_choices(test_state(), failure).await;
+
+        // The error is the last thing before [DONE], and no partial usage is reported
+        let last = chunks.last().unwrap().as_str().unwrap();
+        assert!(last.starts_with("Error 400"), "{}", last);
+        assert!(chunks.iter().all(|c| !c["usage"].is_object()));
+    }
+
+    #[tokio::test]
+    async fn invalid_headers_get_openai_errors() {
+        let state = Arc::new(test_state());
+        let request = || chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+
+        for (name, value) in [
+            ("x-bedrock-model-id", "amazon.titan-text-premier-v1:0"),
+            ("x-request-timeout-ms", "soon"),
+            ("x-bedrock-request-tags", "team"),
+        ] {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, value.parse().unwrap());
+            let response =
+                openai_chat_completions_handler(State(state.clone()), headers, Json(request()))
+                    .await;
+            let (status, body) = response_json(response).await;
+            assert_eq!(status, reqwest::StatusCode::BAD_REQUEST, "{}", name);
+            assert_eq!(body["error"]["type"], "invalid_request_error");
+            assert_eq!(body["error"]["code"], "invalid_header");
+        }
+    }
+
+    #[tokio::test]
+    async fn stats_count_requests_and_keep_content_length() {
+        let state = Arc::new(test_state());
+        let app = axum::Router::new()
+            .route(
+                "/fixed",
+                axum::routing::get(|| async { Json(json!({"ok": true})) }),
+            )
+            .route(
+                "/streamed",
+                axum::routing::get(|| async {
+                    let events = futures_util::stream::iter([Ok::<_, std::convert::Infallible>(
+                        Event::default().data("[DONE]"),
+                    )]);
+                    Sse::new(events)
+                }),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                stats_middleware,
+            ))
+            .with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let response = client
+            .get(format!("http://{}/fixed", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()["content-length"],
+            r#"{"ok":true}"#.len().to_string().as_str()
+        );
+        assert_eq!(response.text().await.unwrap(), r#"{"ok":true}"#);
+
+        let response = client
+            .get(format!("http://{}/streamed", addr))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.headers().get("content-length").is_none());
+        assert_eq!(response.text().await.unwrap(), "data: [DONE]\n\n");
+
+        // The server drops the streamed body just after its last chunk
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let stats = state.request_stats.snapshot();
+        assert_eq!(stats["total_requests"], 2);
+        assert_eq!(stats["in_flight"], 0);
+    }
+
+    #[tokio::test]
+    async fn cohere_logprobs_are_returned_even_when_unsupported_ones_are_refused() {
+        let mut state = test_state();
+        state.inference_profile = "cohere.command-text-v14".to_string();
+        state.reject_logprobs = true;
+        let cohere_invoke = |Json(body): Json<Value>| async move {
+            assert_eq!(body["return_likelihoods"], "GENERATION");
+            Json(json!({
+                "generations": [{
+                    "finish_reason": "COMPLETE",
+                    "text": " Hi",
+                    "token_likelihoods": [{"token": " Hi", "likelihood": -0.5}],
+                }],
+            }))
+        };
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route("/model/{id}/invoke", axum::routing::post(cohere_invoke)),
+        )
+        .await;
+
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "logprobs": true,
+        }));
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+        assert_eq!(body["choices"][0]["message"]["content"], "Hi");
+        let token = &body["choices"][0]["logprobs"]["content"][0];
+        assert_eq!(token["token"], " Hi");
+        assert_eq!(token["logprob"], -0.5);
+    }
+
+    #[tokio::test]
+    async fn open_circuit_rejects_with_openai_error() {
+        let mut state = test_state();
+        let breaker = crate::circuit_breaker::CircuitBreaker::new(
+            1,
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        );
+        breaker.record_failure();
+        state.circuit_breaker = Some(Arc::new(breaker));
+        let state = Arc::new(state);
+
+        let proxy = axum::Router::new()
+            .route(
+                "/v1/chat/completions",
+                axum::routing::post(openai_chat_completions_handler),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                circuit_breaker_middleware,
+            ))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, proxy).await.unwrap() });
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/v1/chat/completions", addr))
+            .json(&json!({"messages": [{"role": "user", "content": "hi"}]}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "api_error");
+        assert_eq!(body["error"]["code"], "circuit_open");
+    }
+
+    type SeenBodies = Arc<std::sync::Mutex<Vec<Value>>>;
+
+    // Bedrock mock answering every InvokeModel call with `reply`, keeping the bodies it got
+    async fn capturing_bedrock(state: &mut AppState, reply: Value) -> SeenBodies {
+        let seen = SeenBodies::default();
+        let captured = seen.clone();
+        let router = axum::Router::new().route(
+            "/model/{id}/invoke",
+            axum::routing::post(move |Json(body): Json<Value>| {
+                captured.lock().unwrap().push(body);
+                let reply = reply.clone();
+                async move { Json(reply) }
+            }),
+        );
+        mock_bedrock(state, router).await;
+        seen
+    }
+
+    fn titan_reply(text: &str) -> Value {
+        json!({
+            "inputTextTokenCount": 3,
+            "results": [{"tokenCount": 2, "outputText": text, "completionReason": "FINISH"}],
+        })
+    }
+
+    #[tokio::test]
+    async fn temperature_and_top_p_are_sent_unchanged_with_a_warning() {
+        let mut state = test_state();
+        state.allowed_model_ids = vec!["amazon.titan-text-express-v1".to_string()];
+        let seen = capturing_bedrock(&mut state, titan_reply("ok")).await;
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-bedrock-model-id",
+            HeaderValue::from_static("amazon.titan-text-express-v1"),
+        );
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "temperature": 0.5,
+            "top_p": 0.25,
+        }));
+
+        let response =
+            openai_chat_completions_handler(State(Arc::new(state)), headers, Json(request)).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+        assert_eq!(body["choices"][0]["message"]["content"], "ok");
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0]["textGenerationConfig"]["temperature"], 0.5);
+        assert_eq!(seen[0]["textGenerationConfig"]["topP"], 0.25);
+    }
+
+    #[tokio::test]
+    async fn strict_sampling_params_rejects_temperature_with_top_p() {
+        let mut state = test_state();
+        state.strict_sampling_params = true;
+        let seen = capturing_bedrock(&mut state, titan_reply("ok")).await;
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "temperature": 0.5,
+            "top_p": 0.25,
+        }));
+
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_value");
+        assert!(seen.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn responses_carry_the_openai_object_names() {
+        let mut state = test_state();
+        mock_bedrock(
+            &mut state,
+            axum::Router::new()
+                .route("/model/{id}/invoke", axum::routing::post(anthropic_invoke))
+                .route(
+                    "/model/{id}/invoke-with-response-stream",
+                    axum::routing::post(anthropic_stream),
+                ),
+        )
+        .await;
+        let state = Arc::new(state);
+        let hi = json!([{"role": "user", "content": "hi"}]);
+
+        let request = chat_request(json!({"messages": hi}));
+        let response =
+            openai_chat_completions_handler(State(state.clone()), HeaderMap::new(), Json(request))
+                .await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#""object":"chat.completion""#), "{}", body);
+
+        let request = chat_request(json!({"messages": hi, "stream": true}));
+        let response =
+            openai_chat_completions_handler(State(state), HeaderMap::new(), Json(request)).await;
+        let data = sse_data(response).await;
+        assert_eq!(data.last().unwrap(), "[DONE]");
+        for chunk in &data[..data.len() - 1] {
+            assert!(
+                chunk.contains(r#""object":"chat.completion.chunk""#),
+                "{}",
+                chunk
+            );
+        }
+    }
+
+    // InvokeModel stream of a forced tool call: the first content block is a tool_use
+    async fn tool_use_stream(Json(body): Json<Value>) -> Vec<u8> {
+        assert_eq!(body["tool_choice"], json!({"type": "any"}));
+        let events = [
+            json!({"type": "message_start", "message": {"usage": {"input_tokens": 12}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {
+                "type": "tool_use", "id": "toolu_01", "name": "get_weather", "input": {}
+            }}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {
+                "type": "input_json_delta", "partial_json": "{\"city\": "
+            }}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {
+                "type": "input_json_delta", "partial_json": "\"Paris\"}"
+            }}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 9}}),
+            json!({"type": "message_stop"}),
+        ];
+        events.iter().flat_map(frame).collect()
+    }
+
+    #[tokio::test]
+    async fn required_tool_choice_streams_tool_call_deltas() {
+        let mut state = test_state();
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/invoke-with-response-stream",
+                axum::routing::post(tool_use_stream),
+            ),
+        )
+        .await;
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "weather in Paris?"}],
+            "stream": true,
+            "tool_choice": "required",
+            "tools": [{"type": "function", "function": {
+                "name": "get_weather",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+            }}],
+        }));
+
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        let data = sse_data(response).await;
+        assert_eq!(data.last().unwrap(), "[DONE]");
+        let chunks: Vec<Value> = data[..data.len() - 1]
+            .iter()
+            .map(|d| serde_json::from_str(d).unwrap())
+            .collect();
+
+        // No text precedes the tool call
+        assert!(chunks.iter().all(|c| c["choices"][0]["delta"]["content"]
+            .as_str()
+            .unwrap_or("")
+            .is_empty()));
+        let calls: Vec<&Value> = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["tool_calls"].as_array())
+            .flatten()
+            .collect();
+        assert_eq!(calls[0]["id"], "toolu_01");
+        assert_eq!(calls[0]["type"], "function");
+        assert_eq!(calls[0]["function"]["name"], "get_weather");
+        assert!(calls.iter().all(|call| call["index"] == 0));
+        let arguments: String = calls
+            .iter()
+            .filter_map(|call| call["function"]["arguments"].as_str())
+            .collect();
+        assert_eq!(arguments, r#"{"city": "Paris"}"#);
+        let finish = chunks
+            .iter()
+            .find_map(|c| c["choices"][0]["finish_reason"].as_str());
+        assert_eq!(finish, Some("tool_calls"));
+    }
+
+    type UpstreamDropped = Arc<std::sync::atomic::AtomicBool>;
+
+    // Sets its flag when dropped, i.e. when the mock's response body is abandoned
+    struct DropFlag(UpstreamDropped);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    // Bedrock stream that sends one text delta and then never finishes
+    async fn endless_stream(State(dropped): State<UpstreamDropped>) -> Response {
+        let body = async_stream::stream! {
+            let _flag = DropFlag(dropped);
+            let start = json!({"type": "message_start", "message": {"usage": {"input_tokens": 3}}});
+            let delta = json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Hel"}});
+            yield Ok::<_, std::convert::Infallible>([frame(&start), frame(&delta)].concat());
+            std::future::pending::<()>().await;
+        };
+        Response::new(axum::body::Body::from_stream(body))
+    }
+
+    // Proxy serving /v1/chat/ws in front of `endless_stream`
+    async fn endless_ws_proxy() -> (std::net::SocketAddr, UpstreamDropped) {
+        let dropped = UpstreamDropped::default();
+        let mut state = test_state();
+        mock_bedrock(
+            &mut state,
+            axum::Router::new()
+                .route(
+                    "/model/{id}/invoke-with-response-stream",
+                    axum::routing::post(endless_stream),
+                )
+                .with_state(dropped.clone()),
+        )
+        .await;
+        let proxy = axum::Router::new()
+            .route("/v1/chat/ws", axum::routing::get(openai_chat_ws_handler))
+            .with_state(Arc::new(state));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, proxy).await.unwrap() });
+        (addr, dropped)
+    }
+
+    async fn wait_for_upstream_drop(dropped: &UpstreamDropped) {
+        for _ in 0..100 {
+            if dropped.load(std::sync::atomic::Ordering::SeqCst) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("the Bedrock stream was not dropped");
+    }
+
+    #[tokio::test]
+    async fn websocket_cancel_and_disconnect_drop_the_bedrock_stream() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let request = json!({"messages": [{"role": "user", "content": "hi"}]});
+        for cancel in [true, false] {
+            let (addr, dropped) = endless_ws_proxy().await;
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (mut ws, _) =
+                tokio_tungstenite::client_async(format!("ws://{}/v1/chat/ws", addr), stream)
+                    .await
+                    .unwrap();
+            ws.send(WsMessage::Text(request.to_string().into()))
+                .await
+                .unwrap();
+
+            // Wait until generation is under way
+            let mut text = String::new();
+            while text != "Hel" {
+                let Some(Ok(WsMessage::Text(data))) = ws.next().await else {
+                    panic!("stream ended before the first delta");
+                };
+                let chunk: Value = serde_json::from_str(&data).unwrap();
+                text.push_str(
+                    chunk["choices"][0]["delta"]["content"]
+                        .as_str()
+                        .unwrap_or(""),
+                );
+            }
+            assert!(!dropped.load(std::sync::atomic::Ordering::SeqCst));
+
+            if cancel {
+                ws.send(WsMessage::Text(
+                    json!({"type": "cancel"}).to_string().into(),
+                ))
+                .await
+                .unwrap();
+                let Some(Ok(WsMessage::Text(data))) = ws.next().await else {
+                    panic!("no reply to cancel");
+                };
+                assert_eq!(
+                    serde_json::from_str::<Value>(&data).unwrap()["type"],
+                    "cancelled"
+                );
+                assert!(matches!(ws.next().await, Some(Ok(WsMessage::Close(_)))));
+            } else {
+                drop(ws);
+            }
+            wait_for_upstream_drop(&dropped).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn multi_line_stream_errors_keep_sse_framing() {
+        for single_line in [false, true] {
+            let mut state = test_state();
+            state.sse_single_line_data = single_line;
+            mock_bedrock(
+                &mut state,
+                axum::Router::new().route(
+                    "/model/{id}/invoke-with-response-stream",
+                    axum::routing::post(|| async {
+                        (
+                            reqwest::StatusCode::BAD_REQUEST,
+                            "first line\nsecond line\r\nthird line",
+                        )
+                    }),
+                ),
+            )
+            .await;
+            let request = chat_request(json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": true,
+            }));
+
+            let response = openai_chat_completions_handler(
+                State(Arc::new(state)),
+                HeaderMap::new(),
+                Json(request),
+            )
+            .await;
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(!body.contains('\r'));
+            // Every non-blank line of an event is a `data:` line
+            let events: Vec<Vec<&str>> = body
+                .split("\n\n")
+                .filter(|event| !event.is_empty())
+                .map(|event| event.lines().collect())
+                .collect();
+            assert!(events
+                .iter()
+                .flatten()
+                .all(|line| line.starts_with("data: ")));
+            assert_eq!(events.last().unwrap(), &["data: [DONE]"]);
+
+            let error = &events[events.len() - 2];
+            if single_line {
+                assert_eq!(
+                    error,
+                    &[r"data: Error 400 Bad Request: first line\nsecond line\nthird line"]
+                );
+            } else {
+                assert_eq!(
+                    error,
+                    &[
+                        "data: Error 400 Bad Request: first line",
+                        "data: second line",
+                        "data: third line",
+                    ]
+                );
+            }
+        }
+    }
+
+    // Stream `data` lines for a streamed "hi" answered by `bedrock_stream`
+    async fn stream_data_with(mut state: AppState, bedrock_stream: Vec<u8>) -> Vec<String> {
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/invoke-with-response-stream",
+                axum::routing::post(move || async move { bedrock_stream }),
+            ),
+        )
+        .await;
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+        }));
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        sse_data(response).await
+    }
+
+    #[tokio::test]
+    async fn empty_stream_chunk_is_configurable() {
+        // By default a stream that produced nothing still gets one empty assistant chunk
+        let data = stream_data_with(test_state(), Vec::new()).await;
+        assert_eq!(data.len(), 2);
+        let dummy: Value = serde_json::from_str(&data[0]).unwrap();
+        assert_eq!(
+            dummy["choices"][0]["delta"],
+            json!({"role": "assistant", "content": ""})
+        );
+        assert_eq!(data[1], "[DONE]");
+
+        let state = test_state_with(&[("EMIT_EMPTY_STREAM_CHUNK", "false")])
+            .ok()
+            .unwrap();
+        let data = stream_data_with(state, Vec::new()).await;
+        assert_eq!(data, ["[DONE]"]);
+
+        // A stream with real chunks never gets the dummy appended
+        let data = stream_data_with(test_state(), anthropic_stream_body(&["Hi"])).await;
+        let chunks: Vec<Value> = data[..data.len() - 1]
+            .iter()
+            .map(|d| serde_json::from_str(d).unwrap())
+            .collect();
+        assert!(chunks.last().unwrap()["choices"][0]["finish_reason"].is_string());
+    }
+
+    #[tokio::test]
+    async fn chat_completion_writes_a_redacted_audit_line() {
+        let audit_path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let mut state = test_state();
+        state.audit_log = Some(Arc::new(AuditLogger::new(
+            audit_path.to_string_lossy().into_owned(),
+            vec!["user".to_string()],
+        )));
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route("/model/{id}/invoke", axum::routing::post(anthropic_invoke)),
+        )
+        .await;
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "user": "alice@example.com",
+        }));
+
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        std::fs::remove_file(&audit_path).ok();
+        let entries: Vec<Value> = audit
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert!(chrono::DateTime::parse_from_rfc3339(entry["timestamp"].as_str().unwrap()).is_ok());
+        assert_eq!(entry["request_id"], body["id"]);
+        assert_eq!(entry["model"], body["model"]);
+        assert_eq!(entry["request"]["messages"][0]["content"], "hi");
+        assert_eq!(entry["request"]["user"], "[REDACTED]");
+        assert_eq!(
+            entry["response"]["choices"][0]["message"]["content"],
+            "1 messages"
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_failures_are_retried_until_bedrock_listens() {
+        // A port nothing listens on yet, so connecting is refused
+        let addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut state = test_state();
+        state.bedrock_endpoint = format!("http://{}", addr);
+        let request = |state: &AppState| {
+            state
+                .client
+                .post(state.model_url(
+                    "anthropic.claude-3-haiku-20240307-v1:0",
+                    "invoke-with-response-stream",
+                ))
+                .body("{}")
+                .build()
+                .unwrap()
+        };
+
+        state.connect_retry_attempts = 0;
+        let error = execute_with_connect_retry(&state, request(&state))
+            .await
+            .unwrap_err();
+        assert!(error.is_connect());
+
+        // Bedrock comes up while the first retry is waiting
+        state.connect_retry_attempts = 3;
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+            let router = axum::Router::new().route(
+                "/model/{id}/invoke-with-response-stream",
+                axum::routing::post(anthropic_stream),
+            );
+            axum::serve(listener, router).await.unwrap();
+        });
+        let response = execute_with_connect_retry(&state, request(&state))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn model_id_header_changes_the_bedrock_endpoint() {
+        let mut state = test_state();
+        state.allowed_model_ids = vec!["anthropic.claude-3-5-sonnet-20240620-v1:0".to_string()];
+        let router = axum::Router::new().route(
+            "/model/{id}/invoke",
+            axum::routing::post(
+                |axum::extract::Path(id): axum::extract::Path<String>| async move {
+                    Json(json!({
+                        "type": "message",
+                        "role": "assistant",
+                        "content": [{"type": "text", "text": id}],
+                        "stop_reason": "end_turn",
+                        "usage": {"input_tokens": 3, "output_tokens": 1},
+                    }))
+                },
+            ),
+        );
+        mock_bedrock(&mut state, router).await;
+        let state = Arc::new(state);
+        let called = |headers: HeaderMap| {
+            let state = state.clone();
+            async move {
+                let request =
+                    chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+                let response =
+                    openai_chat_completions_handler(State(state), headers, Json(request)).await;
+                let (status, body) = response_json(response).await;
+                assert_eq!(status, reqwest::StatusCode::OK, "{}", body);
+                body["choices"][0]["message"]["content"].clone()
+            }
+        };
+
+        assert_eq!(
+            called(HeaderMap::new()).await,
+            "anthropic.claude-3-haiku-20240307-v1:0"
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-bedrock-model-id",
+            HeaderValue::from_static("anthropic.claude-3-5-sonnet-20240620-v1:0"),
+        );
+        assert_eq!(
+            called(headers).await,
+            "anthropic.claude-3-5-sonnet-20240620-v1:0"
+        );
+    }
+
+    #[tokio::test]
+    async fn access_denied_reaches_the_client_as_an_openai_403() {
+        let mut state = test_state();
+        let router = axum::Router::new().route(
+            "/model/{id}/invoke",
+            axum::routing::post(|| async {
+                (
+                    reqwest::StatusCode::FORBIDDEN,
+                    [("x-amzn-errortype", "AccessDeniedException")],
+                    r#"{"message":"User: arn:aws:iam::123456789012:user/proxy is not authorized to perform: bedrock:InvokeModel"}"#,
+                )
+            }),
+        );
+        mock_bedrock(&mut state, router).await;
+        let request = chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["code"], "access_denied");
+        assert!(body["error"]["bedrock_error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("not authorized to perform: bedrock:InvokeModel"));
+    }
+
+    #[tokio::test]
+    async fn stream_options_needs_stream_and_include_usage_defaults_off() {
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream_options": {"include_usage": true},
+        }));
+        let response = openai_chat_completions_handler(
+            State(Arc::new(test_state())),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["error"]["message"],
+            "The 'stream_options' parameter is only allowed when 'stream' is enabled."
+        );
+
+        let mut state = test_state();
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/invoke-with-response-stream",
+                axum::routing::post(anthropic_stream),
+            ),
+        )
+        .await;
+        let state = Arc::new(state);
+        let usage_chunks = |stream_options: Value| {
+            let state = state.clone();
+            async move {
+                let mut request = json!({
+                    "messages": [{"role": "user", "content": "hi"}],
+                    "stream": true,
+                });
+                if !stream_options.is_null() {
+                    request["stream_options"] = stream_options;
+                }
+                let response = openai_chat_completions_handler(
+                    State(state),
+                    HeaderMap::new(),
+                    Json(chat_request(request)),
+                )
+                .await;
+                let data = sse_data(response).await;
+                data[..data.len() - 1]
+                    .iter()
+                    .map(|d| serde_json::from_str::<Value>(d).unwrap())
+                    .filter(|c| c["usage"].is_object())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert!(usage_chunks(Value::Null).await.is_empty());
+        assert!(usage_chunks(json!({})).await.is_empty());
+        let usage = usage_chunks(json!({"include_usage": true})).await;
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0]["choices"], json!([]));
+        assert_eq!(usage[0]["usage"]["prompt_tokens"], 3);
+        assert_eq!(usage[0]["usage"]["completion_tokens"], 2);
+        assert_eq!(usage[0]["usage"]["total_tokens"], 5);
+    }
+
+    #[tokio::test]
+    async fn generated_images_are_returned_as_image_url_parts() {
+        let reply = json!({
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                {"type": "text", "text": "Here is the chart:"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}},
+            ],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 3, "output_tokens": 9},
+        });
+        let content = |mut state: AppState| {
+            let reply = reply.clone();
+            async move {
+                capturing_bedrock(&mut state, reply).await;
+                let request =
+                    chat_request(json!({"messages": [{"role": "user", "content": "chart"}]}));
+                let response = openai_chat_completions_handler(
+                    State(Arc::new(state)),
+                    HeaderMap::new(),
+                    Json(request),
+                )
+                .await;
+                let (status, body) = response_json(response).await;
+                assert_eq!(status, reqwest::StatusCode::OK);
+                body["choices"][0]["message"]["content"].clone()
+            }
+        };
+
+        let state = test_state_with(&[("RESPONSE_CONTENT_PARTS", "true")])
+            .ok()
+            .unwrap();
+        assert_eq!(
+            content(state).await,
+            json!([
+                {"type": "text", "text": "Here is the chart:"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+            ])
+        );
+        // A plain string can only carry the text
+        assert_eq!(content(test_state()).await, "Here is the chart:");
+    }
+
+    #[tokio::test]
+    async fn estimated_cost_is_reported_for_priced_models() {
+        let mut state = test_state_with(&[(
+            "MODEL_PRICES",
+            "anthropic.claude-3-haiku-20240307-v1:0=0.25/1.25",
+        )])
+        .ok()
+        .unwrap();
+        mock_bedrock(
+            &mut state,
+            axum::Router::new()
+                .route("/model/{id}/invoke", axum::routing::post(anthropic_invoke))
+                .route(
+                    "/model/{id}/invoke-with-response-stream",
+                    axum::routing::post(anthropic_stream),
+                ),
+        )
+        .await;
+        let state = Arc::new(state);
+        let hi = json!([{"role": "user", "content": "hi"}]);
+
+        // 3 input and 2 output tokens: (3 * 0.25 + 2 * 1.25) / 1000
+        let request = chat_request(json!({"messages": hi}));
+        let response =
+            openai_chat_completions_handler(State(state.clone()), HeaderMap::new(), Json(request))
+                .await;
+        assert_eq!(response.headers()["x-estimated-cost-usd"], "0.003250");
+
+        let request = chat_request(json!({
+            "messages": hi,
+            "stream": true,
+            "stream_options": {"include_usage": true},
+        }));
+        let response =
+            openai_chat_completions_handler(State(state), HeaderMap::new(), Json(request)).await;
+        let data = sse_data(response).await;
+        let usage: Value = serde_json::from_str(&data[data.len() - 2]).unwrap();
+        let cost = usage["usage"]["estimated_cost_usd"].as_f64().unwrap();
+        assert!((cost - 0.00325).abs() < 1e-12, "{}", cost);
+    }
+
+    #[tokio::test]
+    async fn bedrock_calls_feed_latency_samples() {
+        let stats = Arc::new(crate::latency::LatencyStats::new(
+            Duration::from_secs(60),
+            Duration::from_secs(300),
+        ));
+        let mut state = test_state();
+        state.latency_stats = Some(stats.clone());
+        mock_bedrock(
+            &mut state,
+            axum::Router::new()
+                .route("/model/{id}/invoke", axum::routing::post(anthropic_invoke))
+                .route(
+                    "/model/{id}/invoke-with-response-stream",
+                    axum::routing::post(anthropic_stream),
+                ),
+        )
+        .await;
+        let state = Arc::new(state);
+        let hi = json!([{"role": "user", "content": "hi"}]);
+
+        for stream in [false, true] {
+            let request = chat_request(json!({"messages": hi, "stream": stream}));
+            let response = openai_chat_completions_handler(
+                State(state.clone()),
+                HeaderMap::new(),
+                Json(request),
+            )
+            .await;
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+        }
+        let (count, _, _, p99) = stats.percentiles().unwrap();
+        assert_eq!(count, 2);
+        assert!(p99 < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn request_timeout_header_cuts_off_a_slow_bedrock() {
+        let mut state = test_state();
+        state.request_timeout_ms = None;
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/invoke",
+                axum::routing::post(|body: Json<Value>| async {
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    anthropic_invoke(body).await
+                }),
+            ),
+        )
+        .await;
+        let call_with = |state: AppState, timeout_ms: Option<&'static str>| async move {
+            let mut headers = HeaderMap::new();
+            if let Some(ms) = timeout_ms {
+                headers.insert("x-request-timeout-ms", HeaderValue::from_static(ms));
+            }
+            let request = chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+            let started = std::time::Instant::now();
+            let response =
+                openai_chat_completions_handler(State(Arc::new(state)), headers, Json(request))
+                    .await;
+            let (status, body) = response_json(response).await;
+            (status, body, started.elapsed())
+        };
+
+        let (status, body, elapsed) = call_with(state.clone(), Some("50")).await;
+        assert_eq!(status, reqwest::StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body["error"]["type"], "timeout_error");
+        assert_eq!(body["error"]["code"], "request_timeout");
+        assert!(elapsed < Duration::from_millis(250), "{:?}", elapsed);
+
+        // Overrides are clamped to MAX_REQUEST_TIMEOUT_MS
+        let mut clamped = state.clone();
+        clamped.max_request_timeout_ms = 50;
+        let (status, _, _) = call_with(clamped, Some("60000")).await;
+        assert_eq!(status, reqwest::StatusCode::GATEWAY_TIMEOUT);
+
+        let (status, _, _) = call_with(state, None).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn missing_usage_is_estimated_and_flagged() {
+        let reply = json!({
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "text", "text": "A reply of about forty characters long."}],
+            "stop_reason": "end_turn",
+        });
+        let call = |mut state: AppState| {
+            let reply = reply.clone();
+            async move {
+                capturing_bedrock(&mut state, reply).await;
+                let request = chat_request(
+                    json!({"messages": [{"role": "user", "content": "Say something"}]}),
+                );
+                let response = openai_chat_completions_handler(
+                    State(Arc::new(state)),
+                    HeaderMap::new(),
+                    Json(request),
+                )
+                .await;
+                let estimated = response.headers().get("x-usage-estimated").cloned();
+                let (_, body) = response_json(response).await;
+                (estimated, body["usage"].clone())
+            }
+        };
+
+        // By default the counts are reported as Bedrock gave them (none)
+        let (estimated, usage) = call(test_state()).await;
+        assert_eq!(estimated, None);
+        assert_eq!(usage["completion_tokens"], 0);
+
+        let state = test_state_with(&[("ESTIMATE_MISSING_USAGE", "true")])
+            .ok()
+            .unwrap();
+        let (estimated, usage) = call(state).await;
+        assert_eq!(estimated.unwrap(), "true");
+        let prompt = usage["prompt_tokens"].as_i64().unwrap();
+        let completion = usage["completion_tokens"].as_i64().unwrap();
+        assert!(prompt > 0);
+        assert!((8..=12).contains(&completion), "{}", completion);
+        assert_eq!(usage["total_tokens"], prompt + completion);
+    }
+
+    #[tokio::test]
+    async fn response_fields_can_exclude_usage() {
+        let body_with = |mut state: AppState| async move {
+            mock_bedrock(
+                &mut state,
+                axum::Router::new()
+                    .route("/model/{id}/invoke", axum::routing::post(anthropic_invoke)),
+            )
+            .await;
+            let request = chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+            let response = openai_chat_completions_handler(
+                State(Arc::new(state)),
+                HeaderMap::new(),
+                Json(request),
+            )
+            .await;
+            response_json(response).await.1
+        };
+
+        let body = body_with(test_state()).await;
+        assert_eq!(body["usage"]["total_tokens"], 5);
+
+        // Required fields such as `id` can't be excluded
+        let state = test_state_with(&[("RESPONSE_FIELDS", "-usage,-id")])
+            .ok()
+            .unwrap();
+        assert_eq!(state.response_excluded_fields, ["usage"]);
+        let body = body_with(state).await;
+        assert!(body.get("usage").is_none());
+        assert!(body["id"].is_string());
+        assert_eq!(body["choices"][0]["message"]["content"], "1 messages");
+    }
+
+    #[tokio::test]
+    async fn default_model_name_propagates_to_responses() {
+        let models_with = |mut state: AppState| async move {
+            mock_bedrock(
+                &mut state,
+                axum::Router::new()
+                    .route("/model/{id}/invoke", axum::routing::post(anthropic_invoke))
+                    .route(
+                        "/model/{id}/invoke-with-response-stream",
+                        axum::routing::post(anthropic_stream),
+                    ),
+            )
+            .await;
+            let state = Arc::new(state);
+            let hi = json!([{"role": "user", "content": "hi"}]);
+            let mut models = Vec::new();
+
+            let response = models_handler(State(state.clone())).await.into_response();
+            models.push(response_json(response).await.1["data"][0]["id"].clone());
+            let request = chat_request(json!({"messages": hi}));
+            let response = openai_chat_completions_handler(
+                State(state.clone()),
+                HeaderMap::new(),
+                Json(request),
+            )
+            .await;
+            models.push(response_json(response).await.1["model"].clone());
+            let request = chat_request(json!({"messages": hi, "stream": true}));
+            let response =
+                openai_chat_completions_handler(State(state), HeaderMap::new(), Json(request))
+                    .await;
+            let data = sse_data(response).await;
+            models.push(serde_json::from_str::<Value>(&data[0]).unwrap()["model"].clone());
+            models
+        };
+
+        // Derived from INFERENCE_PROFILE when unset
+        assert_eq!(
+            models_with(test_state()).await,
+            ["claude-3-haiku-20240307"; 3]
+        );
+        let state = test_state_with(&[("DEFAULT_MODEL_NAME", "house-model")])
+            .ok()
+            .unwrap();
+        assert_eq!(models_with(state).await, ["house-model"; 3]);
+    }
+
+    #[tokio::test]
+    async fn created_can_come_from_the_upstream_date_header() {
+        const DATE: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let created_with = |mut state: AppState| async move {
+            mock_bedrock(
+                &mut state,
+                axum::Router::new()
+                    .route(
+                        "/model/{id}/invoke",
+                        axum::routing::post(|body: Json<Value>| async {
+                            ([(http::header::DATE, DATE)], anthropic_invoke(body).await)
+                        }),
+                    )
+                    .route(
+                        "/model/{id}/invoke-with-response-stream",
+                        axum::routing::post(|| async {
+                            ([(http::header::DATE, DATE)], anthropic_stream().await)
+                        }),
+                    ),
+            )
+            .await;
+            let state = Arc::new(state);
+            let hi = json!([{"role": "user", "content": "hi"}]);
+
+            let request = chat_request(json!({"messages": hi}));
+            let response = openai_chat_completions_handler(
+                State(state.clone()),
+                HeaderMap::new(),
+                Json(request),
+            )
+            .await;
+            let created = response_json(response).await.1["created"].as_i64().unwrap();
+            let request = chat_request(json!({"messages": hi, "stream": true}));
+            let response =
+                openai_chat_completions_handler(State(state), HeaderMap::new(), Json(request))
+                    .await;
+            let data = sse_data(response).await;
+            let chunk: Value = serde_json::from_str(&data[0]).unwrap();
+            [created, chunk["created"].as_i64().unwrap()]
+        };
+
+        let state = test_state_with(&[("CREATED_FROM_UPSTREAM_DATE", "true")])
+            .ok()
+            .unwrap();
+        assert_eq!(created_with(state).await, [1_445_412_480; 2]);
+
+        // Local time otherwise
+        let now = chrono::Utc::now().timestamp();
+        for created in created_with(test_state()).await {
+            assert!((created - now).abs() < 60, "{}", created);
+        }
+    }
+
+    #[tokio::test]
+    async fn denylisted_prompts_are_blocked_before_bedrock() {
+        let mut state = test_state_with(&[("MODERATION_DENYLIST", "secret sauce")])
+            .ok()
+            .unwrap();
+        let reply = json!({
+            "content": [{"type": "text", "text": "ok"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 3, "output_tokens": 1},
+        });
+        let seen = capturing_bedrock(&mut state, reply).await;
+        let state = Arc::new(state);
+
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "What is in the Secret Sauce?"}],
+        }));
+        let response =
+            openai_chat_completions_handler(State(state.clone()), HeaderMap::new(), Json(request))
+                .await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "content_filter");
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert_eq!(
+            body["error"]["message"],
+            "Request blocked by content moderation"
+        );
+        assert!(seen.lock().unwrap().is_empty());
+
+        let request = chat_request(json!({
+            "messages": [{"role": "user", "content": "What is for dinner?"}],
+        }));
+        let response =
+            openai_chat_completions_handler(State(state), HeaderMap::new(), Json(request)).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn allowlisted_headers_are_forwarded_and_signed() {
+        let mut state =
+            test_state_with(&[("FORWARD_HEADERS", "Anthropic-Beta, host, Authorization")])
+                .ok()
+                .unwrap();
+        assert_eq!(state.forward_headers, ["anthropic-beta"]);
+        let seen = Arc::new(std::sync::Mutex::new(HeaderMap::new()));
+        let captured = seen.clone();
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/invoke",
+                axum::routing::post(move |headers: HeaderMap, body: Json<Value>| {
+                    *captured.lock().unwrap() = headers;
+                    anthropic_invoke(body)
+                }),
+            ),
+        )
+        .await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("anthropic-beta", "context-1m-2025-08-07".parse().unwrap());
+        headers.insert("x-not-listed", "1".parse().unwrap());
+        let request = chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+        let response =
+            openai_chat_completions_handler(State(Arc::new(state)), headers, Json(request)).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen["anthropic-beta"], "context-1m-2025-08-07");
+        assert!(seen.get("x-not-listed").is_none());
+        let authorization = seen["authorization"].to_str().unwrap();
+        let signed = authorization
+            .split("SignedHeaders=")
+            .nth(1)
+            .and_then(|s| s.split(',').next())
+            .unwrap();
+        assert!(
+            signed.split(';').any(|h| h == "anthropic-beta"),
+            "{}",
+            signed
+        );
+    }
+
+    #[tokio::test]
+    async fn unrecognized_bedrock_responses_are_a_502_when_validating() {
+        let malformed = json!({"completion": "hi", "stop_reason": "end_turn"});
+        let complete = |mut state: AppState| {
+            let malformed = malformed.clone();
+            async move {
+                capturing_bedrock(&mut state, malformed).await;
+                let request =
+                    chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+                let response = openai_chat_completions_handler(
+                    State(Arc::new(state)),
+                    HeaderMap::new(),
+                    Json(request),
+                )
+                .await;
+                response_json(response).await
+            }
+        };
+
+        let state = test_state_with(&[("VALIDATE_BEDROCK_RESPONSES", "true")])
+            .ok()
+            .unwrap();
+        let (status, body) = complete(state).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_GATEWAY);
+        assert_eq!(body["error"]["type"], "api_error");
+        assert_eq!(body["error"]["code"], "invalid_upstream_response");
+        assert_eq!(
+            body["error"]["message"],
+            "Unrecognized Bedrock response: expected a `content` array, got top-level keys [completion, stop_reason]"
+        );
+
+        // Without validation the transform quietly produces an empty reply
+        let (status, body) = complete(test_state()).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+        assert!(body["choices"][0]["message"]["content"].is_null());
+    }
+
+    #[tokio::test]
+    async fn raw_stop_reason_is_exposed_under_the_debug_flag() {
+        let complete = |mut state: AppState| async move {
+            let reply = json!({
+                "content": [{"type": "text", "text": "cut"}],
+                "stop_reason": "max_tokens",
+                "usage": {"input_tokens": 3, "output_tokens": 1},
+            });
+            capturing_bedrock(&mut state, reply).await;
+            let request = chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+            let response = openai_chat_completions_handler(
+                State(Arc::new(state)),
+                HeaderMap::new(),
+                Json(request),
+            )
+            .await;
+            response_json(response).await.1["choices"][0].clone()
+        };
+
+        let state = test_state_with(&[("EXPOSE_BEDROCK_STOP_REASON", "true")])
+            .ok()
+            .unwrap();
+        let choice = complete(state).await;
+        assert_eq!(choice["finish_reason"], "length");
+        assert_eq!(choice["x_bedrock_stop_reason"], "max_tokens");
+
+        let choice = complete(test_state()).await;
+        assert_eq!(choice["finish_reason"], "length");
+        assert!(choice.get("x_bedrock_stop_reason").is_none());
+    }
+
+    #[tokio::test]
+    async fn sentence_flushing_regroups_deltas_at_sentence_ends() {
+        let deltas = ["Hel", "lo there. Pi is 3.", "14! Next", " line\nTa", "il"];
+        let contents = |data: Vec<String>| -> Vec<Value> {
+            data.iter()
+                .filter(|d| *d != "[DONE]")
+                .map(|d| serde_json::from_str::<Value>(d).unwrap())
+                .map(|chunk| chunk["choices"][0]["delta"]["content"].clone())
+                .collect()
+        };
+
+        let state = test_state_with(&[("STREAM_FLUSH_ON_SENTENCE", "true")])
+            .ok()
+            .unwrap();
+        let data = stream_data_with(state, anthropic_stream_body(&deltas)).await;
+        let last: Value = serde_json::from_str(&data[data.len() - 2]).unwrap();
+        assert_eq!(last["choices"][0]["finish_reason"], "stop");
+        assert_eq!(
+            contents(data),
+            ["", "Hello there. ", "Pi is 3.14! ", "Next line\n", "Tail"]
+        );
+
+        // Unbuffered, deltas pass through as Bedrock sent them
+        let data = stream_data_with(test_state(), anthropic_stream_body(&deltas)).await;
+        assert_eq!(
+            contents(data),
+            [
+                json!(""),
+                json!("Hel"),
+                json!("lo there. Pi is 3."),
+                json!("14! Next"),
+                json!(" line\nTa"),
+                json!("il"),
+                Value::Null
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn debug_trace_needs_both_the_header_and_the_flag() {
+        let complete = |allow: &str, header: Option<&str>| {
+            let mut state = test_state_with(&[("ALLOW_DEBUG_TRACE", allow)])
+                .ok()
+                .unwrap();
+            let mut headers = HeaderMap::new();
+            if let Some(header) = header {
+                headers.insert("x-debug-trace", header.parse().unwrap());
+            }
+            async move {
+                mock_bedrock(
+                    &mut state,
+                    axum::Router::new()
+                        .route("/model/{id}/invoke", axum::routing::post(anthropic_invoke)),
+                )
+                .await;
+                let request =
+                    chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+                let response =
+                    openai_chat_completions_handler(State(Arc::new(state)), headers, Json(request))
+                        .await;
+                response_json(response).await.1
+            }
+        };
+
+        let body = complete("true", Some("true")).await;
+        let trace = &body["x_debug_trace"];
+        assert_eq!(trace["bedrock_request"]["messages"][0]["content"], "hi");
+        assert_eq!(trace["bedrock_response"]["id"], "msg_mock");
+        assert_eq!(
+            trace["bedrock_response"]["content"][0]["text"],
+            body["choices"][0]["message"]["content"]
+        );
+
+        for (allow, header) in [
+            ("true", None),
+            ("true", Some("false")),
+            ("false", Some("true")),
+        ] {
+            let body = complete(allow, header).await;
+            assert_eq!(body["choices"][0]["message"]["content"], "1 messages");
+            assert!(
+                body.get("x_debug_trace").is_none(),
+                "{} {:?}",
+                allow,
+                header
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn overloaded_bedrock_errors_can_become_a_429() {
+        // Status, x-amzn-ErrorType and body of overloaded responses from Claude on Bedrock
+        let overloaded = [
+            (
+                529,
+                "",
+                r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+            ),
+            (
+                503,
+                "ServiceUnavailableException:http://internal.amazon.com/coral/com.amazon.bedrock/",
+                r#"{"message":"Model is overloaded. Please try again later."}"#,
+            ),
+        ];
+        let complete = |mut state: AppState,
+                        status: u16,
+                        error_type: &'static str,
+                        body: &'static str| async move {
+            let reply = move || async move {
+                let status = reqwest::StatusCode::from_u16(status).unwrap();
+                (status, [("x-amzn-ErrorType", error_type)], body)
+            };
+            mock_bedrock(
+                &mut state,
+                axum::Router::new().route("/model/{id}/invoke", axum::routing::post(reply)),
+            )
+            .await;
+            let request = chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+            openai_chat_completions_handler(State(Arc::new(state)), HeaderMap::new(), Json(request))
+                .await
+        };
+
+        for (status, error_type, body) in overloaded {
+            let state = test_state_with(&[
+                ("REMAP_OVERLOADED_TO_429", "true"),
+                ("OVERLOADED_RETRY_AFTER_SECS", "7"),
+            ])
+            .ok()
+            .unwrap();
+            let response = complete(state, status, error_type, body).await;
+            assert_eq!(response.headers()["retry-after"], "7");
+            let (remapped, error) = response_json(response).await;
+            assert_eq!(remapped, reqwest::StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(error["error"]["type"], "rate_limit_error");
+            assert_eq!(error["error"]["code"], "model_overloaded");
+            assert_eq!(
+                error["error"]["bedrock_error"],
+                serde_json::from_str::<Value>(body).unwrap()
+            );
+
+            // Passed through as Bedrock sent it otherwise
+            let response = complete(test_state(), status, error_type, body).await;
+            assert_eq!(response.status().as_u16(), status);
+            assert!(response.headers().get("retry-after").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn request_tags_are_sent_as_signed_converse_metadata() {
+        use sha2::{Digest, Sha256};
+
+        let mut state = test_state_with(&[
+            ("USE_CONVERSE_API", "true"),
+            ("INFERENCE_TAGS", "team=search,env=prod"),
+        ])
+        .ok()
+        .unwrap();
+        let seen = Arc::new(std::sync::Mutex::new(None));
+        let captured = seen.clone();
+        mock_bedrock(
+            &mut state,
+            axum::Router::new().route(
+                "/model/{id}/converse",
+                axum::routing::post(move |headers: HeaderMap, body: axum::body::Bytes| {
+                    *captured.lock().unwrap() = Some((headers, body));
+                    async {
+                        Json(json!({
+                            "output": {"message": {"role": "assistant", "content": [{"text": "ok"}]}},
+                            "stopReason": "end_turn",
+                            "usage": {"inputTokens": 3, "outputTokens": 1, "totalTokens": 4},
+                        }))
+                    }
+                }),
+            ),
+        )
+        .await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-bedrock-request-tags",
+            "env=staging, feature=chat".parse().unwrap(),
+        );
+        let request = chat_request(json!({"messages": [{"role": "user", "content": "hi"}]}));
+        let response =
+            openai_chat_completions_handler(State(Arc::new(state)), headers, Json(request)).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let (headers, body) = seen.lock().unwrap().take().unwrap();
+        let payload: Value = serde_json::from_slice(&body).unwrap();
+        // The header overrides INFERENCE_TAGS per key
+        assert_eq!(
+            payload["requestMetadata"],
+            json!({"team": "search", "env": "staging", "feature": "chat"})
+        );
+        // Tags travel in the body, which the signature covers through its hash
+        let body_hash: String = Sha256::digest(&body)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(headers["x-amz-content-sha256"], body_hash.as_str());
+        assert!(headers["authorization"]
+            .to_str()
+            .unwrap()
+            .contains("x-amz-content-sha256"));
+    }
+
+    #[tokio::test]
+    async fn accept_header_negotiates_streaming() {
+        let complete = |mut state: AppState, accept: &'static str, request: Value| async move {
+            mock_bedrock(
+                &mut state,
+                axum::Router::new()
+                    .route("/model/{id}/invoke", axum::routing::post(anthropic_invoke))
+                    .route(
+                        "/model/{id}/invoke-with-response-stream",
+                        axum::routing::post(anthropic_stream),
+                    ),
+            )
+            .await;
+            let mut headers = HeaderMap::new();
+            headers.insert(http::header::ACCEPT, HeaderValue::from_static(accept));
+            let response = openai_chat_completions_handler(
+                State(Arc::new(state)),
+                headers,
+                Json(chat_request(request)),
+            )
+            .await;
+            let content_type = response.headers()[http::header::CONTENT_TYPE].clone();
+            (content_type, response)
+        };
+        let hi = json!([{"role": "user", "content": "hi"}]);
+
+        // Accept: text/event-stream streams a request without `stream`
+        let (content_type, response) =
+            complete(test_state(), "text/event-stream", json!({"messages": hi})).await;
+        assert_eq!(content_type, "text/event-stream");
+        let data = sse_data(response).await;
+        assert_eq!(data.last().unwrap(), "[DONE]");
+        // ... but not one that asked for JSON
+        let request = json!({"messages": hi, "stream": false});
+        let (content_type, _) = complete(test_state(), "text/event-stream", request).await;
+        assert_eq!(content_type, "application/json");
+
+        // Accept: application/json only overrides `stream: true` when enabled
+        let request = json!({"messages": hi, "stream": true});
+        let (content_type, _) = complete(test_state(), "application/json", request.clone()).await;
+        assert_eq!(content_type, "text/event-stream");
+        let state = test_state_with(&[("ACCEPT_JSON_OVERRIDES_STREAM", "true")])
+            .ok()
+            .unwrap();
+        let (content_type, response) = complete(state, "application/json", request).await;
+        assert_eq!(content_type, "application/json");
+        let (_, body) = response_json(response).await;
+        assert_eq!(body["object"], "chat.completion");
+        assert_eq!(body["choices"][0]["message"]["content"], "1 messages");
+    }
+
+    #[tokio::test]
+    async fn tool_call_ids_and_indexes_match_between_stream_and_json() {
+        let calls = [
+            ("toolu_01A", "get_weather", r#"{"city": "Paris"}"#),
+            ("toolu_01B", "get_time", r#"{"tz": "CET"}"#),
+        ];
+        let mut content = vec![json!({"type": "text", "text": "Checking both."})];
+        let mut events = vec![
+            json!({"type": "message_start", "message": {"usage": {"input_tokens": 12}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {
+                "type": "text", "text": ""
+            }}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {
+                "type": "text_delta", "text": "Checking both."
+            }}),
+            json!({"type": "content_block_stop", "index": 0}),
+        ];
+        for (block, (id, name, arguments)) in (1..).zip(calls) {
+            let input: Value = serde_json::from_str(arguments).unwrap();
+            content.push(json!({"type": "tool_use", "id": id, "name": name, "input": input}));
+            events.push(
+                json!({"type": "content_block_start", "index": block, "content_block": {
+                    "type": "tool_use", "id": id, "name": name, "input": {}
+                }}),
+            );
+            let (head, tail) = arguments.split_at(5);
+            for fragment in [head, tail] {
+                events.push(
+                    json!({"type": "content_block_delta", "index": block, "delta": {
+                        "type": "input_json_delta", "partial_json": fragment
+                    }}),
+                );
+            }
+            events.push(json!({"type": "content_block_stop", "index": block}));
+        }
+        events.push(json!({
+            "type": "message_delta",
+            "delta": {"stop_reason": "tool_use"},
+            "usage": {"output_tokens": 20},
+        }));
+        events.push(json!({"type": "message_stop"}));
+        let reply = json!({
+            "content": content,
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 12, "output_tokens": 20},
+        });
+        let stream: Vec<u8> = events.iter().flat_map(frame).collect();
+
+        let mut state = test_state();
+        mock_bedrock(
+            &mut state,
+            axum::Router::new()
+                .route(
+                    "/model/{id}/invoke",
+                    axum::routing::post(move || async move { Json(reply) }),
+                )
+                .route(
+                    "/model/{id}/invoke-with-response-stream",
+                    axum::routing::post(move || async move { stream }),
+                ),
+        )
+        .await;
+        let state = Arc::new(state);
+        let request = |stream: bool| {
+            chat_request(json!({
+                "messages": [{"role": "user", "content": "Weather and time in Paris?"}],
+                "tools": [
+                    {"type": "function", "function": {"name": "get_weather"}},
+                    {"type": "function", "function": {"name": "get_time"}},
+                ],
+                "stream": stream,
+            }))
+        };
+
+        let response = openai_chat_completions_handler(
+            State(state.clone()),
+            HeaderMap::new(),
+            Json(request(false)),
+        )
+        .await;
+        let (_, body) = response_json(response).await;
+        let tool_calls = body["choices"][0]["message"]["tool_calls"]
+            .as_array()
+            .unwrap()
+            .clone();
+
+        // Assemble the streamed calls by index the way OpenAI clients do
+        let response =
+            openai_chat_completions_handler(State(state), HeaderMap::new(), Json(request(true)))
+                .await;
+        let mut streamed: Vec<Value> = Vec::new();
+        let mut indexes = Vec::new();
+        for data in sse_data(response).await.iter().filter(|d| *d != "[DONE]") {
+            let chunk: Value = serde_json::from_str(data).unwrap();
+            for delta in chunk["choices"][0]["delta"]["tool_calls"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                let index = delta["index"].as_u64().unwrap() as usize;
+                indexes.push(index);
+                if index == streamed.len() {
+                    assert!(
+                        delta["id"].is_string(),
+                        "first delta for a call carries its id"
+                    );
+                    let name = &delta["function"]["name"];
+                    streamed.push(json!({"id": delta["id"], "name": name, "arguments": ""}));
+                }
+                let arguments = delta["function"]["arguments"].as_str().unwrap_or_default();
+                let assembled = format!(
+                    "{}{}",
+                    streamed[index]["arguments"].as_str().unwrap(),
+                    arguments
+                );
+                streamed[index]["arguments"] = json!(assembled);
+            }
+        }
+        // Indexes count tool calls from 0, not Bedrock content blocks
+        assert_eq!(indexes.first(), Some(&0));
+        assert!(
+            indexes.windows(2).all(|w| w[1] == w[0] || w[1] == w[0] + 1),
+            "{:?}",
+            indexes
+        );
+
+        assert_eq!(streamed.len(), 2);
+        for ((call, streamed), (id, name, arguments)) in tool_calls.iter().zip(&streamed).zip(calls)
+        {
+            assert_eq!(call["id"], id);
+            assert_eq!(streamed["id"], id);
+            assert_eq!(call["function"]["name"], name);
+            assert_eq!(streamed["name"], name);
+            let parsed = |v: &Value| serde_json::from_str::<Value>(v.as_str().unwrap()).unwrap();
+            assert_eq!(
+                parsed(&call["function"]["arguments"]),
+                parsed(&streamed["arguments"])
+            );
+            assert_eq!(
+                parsed(&streamed["arguments"]),
+                serde_json::from_str::<Value>(arguments).unwrap()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn audio_input_parts_are_rejected_before_bedrock() {
+        let mut state = test_state();
+        let seen = capturing_bedrock(&mut state, json!({})).await;
+        let request = chat_request(json!({"messages": [{"role": "user", "content": [
+            {"type": "text", "text": "Transcribe this"},
+            {"type": "input_audio", "input_audio": {"data": "UklGRiQAAABXQVZF", "format": "wav"}},
+        ]}]}));
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "unsupported_value");
+        assert_eq!(
+            body["error"]["message"],
+            "Audio input ('input_audio' content parts) is not supported for model 'anthropic.claude-3-haiku-20240307-v1:0'"
+        );
+        assert!(seen.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn too_many_images_are_rejected_before_bedrock() {
+        let image = json!({"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}});
+        // 21 images over two user turns, one past Claude's default limit of 20
+        let request = || {
+            let mut first = vec![json!({"type": "text", "text": "Compare these"})];
+            first.extend(std::iter::repeat_n(image.clone(), 11));
+            chat_request(json!({"messages": [
+                {"role": "user", "content": first},
+                {"role": "assistant", "content": "Send the rest"},
+                {"role": "user", "content": vec![image.clone(); 10]},
+            ]}))
+        };
+        let reply = json!({
+            "content": [{"type": "text", "text": "ok"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 3, "output_tokens": 1},
+        });
+
+        let mut state = test_state();
+        let seen = capturing_bedrock(&mut state, reply.clone()).await;
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request()),
+        )
+        .await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "too_many_images");
+        assert_eq!(
+            body["error"]["message"],
+            "Request contains 21 images, exceeding the limit of 20 per request"
+        );
+        assert!(seen.lock().unwrap().is_empty());
+
+        let mut state = test_state_with(&[("MAX_IMAGES_PER_REQUEST", "25")])
+            .ok()
+            .unwrap();
+        let seen = capturing_bedrock(&mut state, reply).await;
+        let response = openai_chat_completions_handler(
+            State(Arc::new(state)),
+            HeaderMap::new(),
+            Json(request()),
+        )
+        .await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stalled_streams_hit_the_first_byte_timeout() {
+        // Bedrock mocks that stall before the response headers and before the first body bytes
+        let slow_headers = || async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            anthropic_stream().await
+        };
+        let slow_body = || async {
+            let body = futures_util::stream::once(async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok::<_, std::io::Error>(anthropic_stream().await)
+            });
+            Response::new(axum::body::Body::from_stream(body))
+        };
+        let stalled = [
+            axum::routing::post(slow_headers),
+            axum::routing::post(slow_body),
+        ];
+
+        for stall in stalled {
+            let mut state = test_state_with(&[("STREAM_TTFB_TIMEOUT_MS", "100")])
+                .ok()
+                .unwrap();
+            mock_bedrock(
+                &mut state,
+                axum::Router::new().route("/model/{id}/invoke-with-response-stream", stall),
+            )
+            .await;
+            let request = chat_request(json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": true,
+            }));
+
+            let started = std::time::Instant::now();
+            let response = openai_chat_completions_handler(
+                State(Arc::new(state)),
+                HeaderMap::new(),
+                Json(request),
+            )
+            .await;
+            let data = sse_data(response).await;
+            assert!(started.elapsed() < Duration::from_secs(2));
+            assert_eq!(data.len(), 2);
+            let error: Value = serde_json::from_str(&data[0]).unwrap();
+            assert_eq!(
+                error,
+                json!({"error": {
+                    "message": "Bedrock sent no data within the 100ms stream first-byte timeout",
+                    "type": "timeout_error",
+                    "code": "stream_ttfb_timeout",
+                }})
+            );
+            assert_eq!(data[1], "[DONE]");
+        }
+
+        // A stream that starts in time runs to completion
+        let state = test_state_with(&[("STREAM_TTFB_TIMEOUT_MS", "1000")])
+            .ok()
+            .unwrap();
+        let data = stream_data_with(state, anthropic_stream().await).await;
+        let text: String = data
+            .iter()
+            .filter(|d| *d != "[DONE]")
+            .map(|d| serde_json::from_str::<Value>(d).unwrap())
+            .filter_map(|chunk| {
+                chunk["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .map(str::to_string)
+            })
+            .collect();
+        assert_eq!(text, "Hello");
+    }
+
+    #[test]
+    fn forwarded_for_chains_yield_the_entry_the_proxy_added() {
+        let forwarded = |values: &[&str]| {
+            let mut headers = HeaderMap::new();
+            for value in values {
+                headers.append("x-forwarded-for", value.parse().unwrap());
+            }
+            forwarded_client_ip(&headers).map(|ip| ip.to_string())
+        };
+
+        assert_eq!(forwarded(&["203.0.113.7"]).as_deref(), Some("203.0.113.7"));
+        // Earlier entries are client-supplied; the last one was added by the trusted proxy
+        assert_eq!(
+            forwarded(&["1.2.3.4, 198.51.100.2,203.0.113.7"]).as_deref(),
+            Some("203.0.113.7")
+        );
+        assert_eq!(
+            forwarded(&["1.2.3.4", "2001:db8::1, "]).as_deref(),
+            Some("2001:db8::1")
+        );
+        assert_eq!(forwarded(&["203.0.113.7, unknown"]), None);
+        assert_eq!(forwarded(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn request_span_carries_the_client_ip() {
+        // Log lines written while the test runs, with their span context
+        #[derive(Clone, Default)]
+        struct Logs(Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for Logs {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _logging = tracing::subscriber::set_default(subscriber);
+
+        let client_ip_of = |trust: &str| {
+            let state = Arc::new(
+                test_state_with(&[("TRUST_FORWARDED_FOR", trust)])
+                    .ok()
+                    .unwrap(),
+            );
+            let logs = logs.clone();
+            async move {
+                let app = axum::Router::new()
+                    .route("/", axum::routing::get(|| async { info!("handled") }))
+                    .layer(axum::middleware::from_fn_with_state(
+                        state,
+                        client_ip_middleware,
+                    ));
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                let app = app.into_make_service_with_connect_info::<SocketAddr>();
+                tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+                logs.0.lock().unwrap().clear();
+                reqwest::Client::new()
+                    .get(format!("http://{}/", addr))
+                    .header("x-forwarded-for", "1.2.3.4, 203.0.113.7")
+                    .send()
+                    .await
+                    .unwrap();
+                let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+                logs.lines()
+                    .find(|l| l.contains("handled"))
+                    .unwrap()
+                    .to_string()
+            }
+        };
+
+        let line = client_ip_of("true").await;
+        assert!(line.contains("request{client_ip=203.0.113.7}"), "{}", line);
+        // The header is ignored unless trusted; the TCP peer is used instead
+        let line = client_ip_of("false").await;
+        assert!(line.contains("request{client_ip=127.0.0.1}"), "{}", line);
+    }
+
+    #[tokio::test]
+    async fn frozen_clock_stamps_every_response() {
+        let mut state = test_state_with(&[("FIXED_CREATED_TIMESTAMP", "1700000000")])
+            .ok()
+            .unwrap();
+        mock_bedrock(
+            &mut state,
+            axum::Router::new()
+                .route("/model/{id}/invoke", axum::routing::post(anthropic_invoke))
+                .route(
+                    "/model/{id}/invoke-with-response-stream",
+                    axum::routing::post(anthropic_stream),
+                ),
+        )
+        .await;
+        let state = Arc::new(state);
+        let hi = json!([{"role": "user", "content": "hi"}]);
+
+        let request = chat_request(json!({"messages": hi}));
+        let response =
+            openai_chat_completions_handler(State(state.clone()), HeaderMap::new(), Json(request))
+                .await;
+        assert_eq!(response_json(response).await.1["created"], 1_700_000_000);
+
+        let request = chat_request(json!({"messages": hi, "stream": true}));
+        let response =
+            openai_chat_completions_handler(State(state), HeaderMap::new(), Json(request)).await;
+        let chunks: Vec<Value> = sse_data(response)
+            .await
+            .iter()
+            .filter(|d| *d != "[DONE]")
+            .map(|d| serde_json::from_str(d).unwrap())
+            .collect();
+        assert!(!chunks.is_empty());
+        for chunk in chunks {
+            assert_eq!(chunk["created"], 1_700_000_000);
+        }
+    }
 }
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::state::AppState;
+
+// Rolling window of Bedrock response latencies, summarised in the logs
+// every LATENCY_LOG_INTERVAL_SECS seconds
+pub struct LatencyStats {
+    interval: Duration,
+    window: Duration,
+    samples: Mutex<VecDeque<(Instant, Duration)>>,
+}
+
+impl LatencyStats {
+    pub fn from_env() -> Option<Self> {
+        let interval_secs: u64 = std::env::var("LATENCY_LOG_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|s| *s > 0)?;
+        let window_secs = std::env::var("LATENCY_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300);
+
+        Some(Self::new(
+            Duration::from_secs(interval_secs),
+            Duration::from_secs(window_secs),
+        ))
+    }
+
+    pub fn new(interval: Duration, window: Duration) -> Self {
+        Self {
+            interval,
+            window,
+            samples: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn record(&self, latency: Duration) {
+        let mut samples = self.samples.lock().unwrap();
+        let now = Instant::now();
+        samples.push_back((now, latency));
+        Self::evict(&mut samples, now, self.window);
+    }
+
+    // (count, p50, p95, p99) over the window, None when there are no samples
+    pub fn percentiles(&self) -> Option<(usize, Duration, Duration, Duration)> {
+        let mut samples = self.samples.lock().unwrap();
+        Self::evict(&mut samples, Instant::now(), self.window);
+        if samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<Duration> = samples.iter().map(|(_, l)| *l).collect();
+        sorted.sort();
+        // Nearest-rank percentile
+        let rank = |p: usize| sorted[((sorted.len() * p).div_ceil(100)).max(1) - 1];
+        Some((sorted.len(), rank(50), rank(95), rank(99)))
+    }
+
+    fn evict(samples: &mut VecDeque<(Instant, Duration)>, now: Instant, window: Duration) {
+        while samples
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > window)
+        {
+            samples.pop_front();
+        }
+    }
+}
+
+pub fn start_latency_logging(state: Arc<AppState>) {
+    let Some(stats) = state.latency_stats.clone() else {
+        return;
+    };
+
+    info!(
+        "⏱️ Logging latency percentiles every {}s over a {}s window",
+        stats.interval.as_secs(),
+        stats.window.as_secs()
+    );
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(stats.interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match stats.percentiles() {
+                Some((count, p50, p95, p99)) => info!(
+                    "⏱️ Bedrock latency over {} requests: p50={}ms p95={}ms p99={}ms",
+                    count,
+                    p50.as_millis(),
+                    p95.as_millis(),
+                    p99.as_millis()
+                ),
+                None => info!("⏱️ No Bedrock requests in the latency window"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_nearest_rank_percentiles() {
+        let stats = LatencyStats::new(Duration::from_secs(60), Duration::from_secs(300));
+        assert!(stats.percentiles().is_none());
+
+        // Recorded out of order; percentiles are taken over the sorted samples
+        for ms in (1..=100).rev() {
+            stats.record(Duration::from_millis(ms));
+        }
+        let (count, p50, p95, p99) = stats.percentiles().unwrap();
+        assert_eq!(count, 100);
+        assert_eq!(p50, Duration::from_millis(50));
+        assert_eq!(p95, Duration::from_millis(95));
+        assert_eq!(p99, Duration::from_millis(99));
+    }
+
+    #[test]
+    fn drops_samples_older_than_the_window() {
+        let stats = LatencyStats::new(Duration::from_secs(60), Duration::from_millis(20));
+        stats.record(Duration::from_millis(900));
+        std::thread::sleep(Duration::from_millis(40));
+        stats.record(Duration::from_millis(10));
+
+        let (count, p50, _, p99) = stats.percentiles().unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(p50, Duration::from_millis(10));
+        assert_eq!(p99, Duration::from_millis(10));
+    }
+}
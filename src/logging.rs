@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use tracing_subscriber::EnvFilter;
+
+// Set up tracing from RUST_LOG. With LOG_EMOJI=false the emoji prefixes are stripped
+// from every log line, for log pipelines that garble non-ASCII symbols.
+// Returns whether emoji output is enabled
+pub fn init() -> bool {
+    let log_emoji = std::env::var("LOG_EMOJI")
+        .map(|v| v != "false")
+        .unwrap_or(true);
+
+    let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+    if log_emoji {
+        builder.init();
+    } else {
+        builder.with_writer(|| StripEmoji(io::stdout())).init();
+    }
+    log_emoji
+}
+
+// Shorten a logged body to at most `max_bytes` (LOG_BODY_MAX_BYTES), cutting at a char
+// boundary so multibyte characters are never split, and mark the cut with an ellipsis
+pub fn truncate_body(body: &str, max_bytes: Option<usize>) -> Cow<'_, str> {
+    let Some(max_bytes) = max_bytes.filter(|max| body.len() > *max) else {
+        return Cow::Borrowed(body);
+    };
+
+    let mut end = max_bytes;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!("{}… ({} bytes total)", &body[..end], body.len()))
+}
+
+// Drop emoji (and the space after them) from `text`, leaving everything else intact
+pub fn strip_emoji(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let emoji = is_emoji(c) || chars.peek() == Some(&'\u{FE0F}');
+        if !emoji {
+            out.push(c);
+            continue;
+        }
+        while chars.peek().is_some_and(|&next| is_emoji_modifier(next)) {
+            chars.next();
+        }
+        if chars.peek() == Some(&' ') {
+            chars.next();
+        }
+    }
+    out
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF // pictographs, emoticons, transport, supplemental symbols
+        | 0x2300..=0x23FF // ⏰ ⏱ and other technical symbols
+        | 0x2600..=0x27BF // ⚠ ✅ ❌ ✂ and other dingbats
+    )
+}
+
+// Variation selector, zero-width joiner and keycap combiner
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(c, '\u{FE0F}' | '\u{200D}' | '\u{20E3}')
+}
+
+// Writer used when LOG_EMOJI=false; each formatted event arrives in a single write
+struct StripEmoji<W>(W);
+
+impl<W: Write> Write for StripEmoji<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(text) => self.0.write_all(strip_emoji(text).as_bytes())?,
+            Err(_) => self.0.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_body_backs_off_to_char_boundary() {
+        // "é" is two bytes and "👋" four: limits 2, 4, 5 and 6 each land inside a character
+        let body = "aé👋b";
+        let expected = ["a", "a", "aé", "aé", "aé", "aé"];
+        for (max_bytes, kept) in (1..=6).zip(expected) {
+            let truncated = truncate_body(body, Some(max_bytes));
+            assert_eq!(truncated, format!("{}… (8 bytes total)", kept));
+        }
+    }
+
+    #[test]
+    fn truncate_body_leaves_short_bodies_alone() {
+        assert!(matches!(
+            truncate_body("aé👋b", Some(8)),
+            Cow::Borrowed("aé👋b")
+        ));
+        assert!(matches!(truncate_body("aé👋b", None), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn strip_emoji_keeps_the_message_text() {
+        assert_eq!(
+            strip_emoji("🚀 Server listening on 0.0.0.0:8080"),
+            "Server listening on 0.0.0.0:8080"
+        );
+        // Variation selectors and multi-codepoint sequences go with their emoji
+        assert_eq!(strip_emoji("⚠️ Retrying: 🛡️ blocked"), "Retrying: blocked");
+        assert_eq!(strip_emoji("👩‍💻 done ✅"), "done ");
+        // Non-emoji non-ASCII text is kept
+        assert_eq!(strip_emoji("Café → 42 …"), "Café → 42 …");
+    }
+
+    #[test]
+    fn strip_emoji_writer_removes_emoji_from_log_lines() {
+        let mut writer = StripEmoji(Vec::new());
+        let line = "INFO bedrock_proxy: 📨 Received request\n";
+        assert_eq!(writer.write(line.as_bytes()).unwrap(), line.len());
+        // Invalid UTF-8 passes through untouched
+        writer.write_all(&[0xff, b'\n']).unwrap();
+        assert_eq!(writer.0, b"INFO bedrock_proxy: Received request\n\xff\n");
+    }
+}
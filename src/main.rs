@@ -1,31 +1,95 @@
 use axum::{
+    middleware,
     routing::{any, get, post},
     Router,
 };
 use std::{net::SocketAddr, sync::Arc};
+use tower_http::decompression::RequestDecompressionLayer;
 
+mod accounts;
+mod adapters;
+mod admin;
+mod api_keys;
+mod audit;
+mod circuit_breaker;
+mod errors;
 mod handlers;
+mod latency;
+mod logging;
+mod moderation;
+mod pricing;
+mod recording;
+mod selftest;
+mod sentences;
+mod server;
+mod sessions;
 mod signing;
+mod singleflight;
 mod state;
+mod stats;
+mod sts;
 mod transform;
 
 use handlers::{
-    catch_all_handler, invoke_handler, invoke_stream_handler, models_handler,
-    openai_chat_completions_handler,
+    api_key_middleware, catch_all_handler, circuit_breaker_middleware, client_ip_middleware,
+    invoke_handler, invoke_stream_handler, model_retrieve_handler, models_handler,
+    openai_chat_batch_handler, openai_chat_completions_handler, openai_chat_ws_handler,
+    stats_middleware,
 };
-use state::AppState;
+use state::{AppState, SharedState};
 
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
 
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    let log_emoji = logging::init();
 
-    let state = AppState::from_env();
+    let state = Arc::new(AppState::from_env());
+    sts::start_credential_refresh(state.clone()).await;
 
-    let app = Router::new()
+    // `--check` runs the self-test and exits; STARTUP_SELFTEST=true runs it
+    // before serving and refuses to start on failure
+    let check_only = std::env::args().any(|arg| arg == "--check");
+    let startup_selftest = std::env::var("STARTUP_SELFTEST")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+    if check_only || startup_selftest {
+        let passed = selftest::check(&state).await;
+        if !passed {
+            std::process::exit(1);
+        }
+        if check_only {
+            return;
+        }
+    }
+
+    latency::start_latency_logging(state.clone());
+    tracing::info!(
+        "💓 SSE keep-alive every {}s (text: {:?})",
+        state.sse_keepalive_secs,
+        state.sse_keepalive_text
+    );
+
+    let enable_http2 = state.enable_http2;
+    let http2_keepalive_secs = state.http2_keepalive_secs;
+    let app = router(SharedState::new(state));
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 9678));
+    let banner = format!("🚀 Bedrock proxy running at http://{}", addr);
+    if log_emoji {
+        println!("{}", banner);
+    } else {
+        println!("{}", logging::strip_emoji(&banner));
+    }
+    tracing::info!("🔧 Server starting with debug logging enabled");
+
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    server::serve(listener, app, enable_http2, http2_keepalive_secs).await;
+}
+
+// Every route the proxy serves, with its middleware
+fn router(shared: SharedState) -> Router {
+    Router::new()
         // Legacy endpoints (for backward compatibility)
         .route("/invoke", post(invoke_handler))
         .route("/invoke_stream", post(invoke_stream_handler))
@@ -34,14 +98,142 @@ async fn main() {
             "/v1/chat/completions",
             post(openai_chat_completions_handler),
         )
+        .route("/v1/chat/batch", post(openai_chat_batch_handler))
+        .route("/v1/chat/ws", get(openai_chat_ws_handler))
+        // Routes above call Bedrock and are guarded by the circuit breaker
+        .route_layer(middleware::from_fn_with_state(
+            shared.clone(),
+            circuit_breaker_middleware,
+        ))
+        // ... require an API key when API_KEYS is set
+        .route_layer(middleware::from_fn_with_state(
+            shared.clone(),
+            api_key_middleware,
+        ))
+        // ... and counted in /admin/stats
+        .route_layer(middleware::from_fn_with_state(
+            shared.clone(),
+            stats_middleware,
+        ))
         .route("/v1/models", get(models_handler))
+        .route("/v1/models/{id}", get(model_retrieve_handler))
+        .route("/admin/reload", post(admin::reload_handler))
+        .route("/admin/stats", get(admin::stats_handler))
         .fallback(any(catch_all_handler))
-        .with_state(Arc::new(state));
+        // Log every request under a span carrying the client IP
+        .layer(middleware::from_fn_with_state(
+            shared.clone(),
+            client_ip_middleware,
+        ))
+        // Inflate `Content-Encoding: gzip` / `deflate` request bodies before handlers read them
+        .layer(RequestDecompressionLayer::new())
+        .with_state(shared)
+}
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 9678));
-    println!("🚀 Bedrock proxy running at http://{}", addr);
-    tracing::info!("🔧 Server starting with debug logging enabled");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Json;
+    use flate2::{write::GzEncoder, Compression};
+    use serde_json::{json, Value};
+    use std::io::Write;
 
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    #[tokio::test]
+    async fn gzipped_requests_are_inflated_before_handlers_read_them() {
+        // Bedrock mock echoing the first message it received
+        let bedrock = Router::new().route(
+            "/model/{id}/invoke",
+            post(|Json(body): Json<Value>| async move {
+                Json(json!({
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [{"type": "text", "text": body["messages"][0]["content"]}],
+                    "stop_reason": "end_turn",
+                    "usage": {"input_tokens": 3, "output_tokens": 2},
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut state = state::test_state();
+        state.bedrock_endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, bedrock).await.unwrap() });
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(SharedState::new(Arc::new(state)));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let request = json!({"messages": [{"role": "user", "content": "compressed hello"}]});
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(request.to_string().as_bytes()).unwrap();
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/v1/chat/completions", addr))
+            .header("Content-Type", "application/json")
+            .header("Content-Encoding", "gzip")
+            .body(encoder.finish().unwrap())
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["choices"][0]["message"]["content"], "compressed hello");
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_get_an_openai_404() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(SharedState::new(Arc::new(state::test_state())));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let response = reqwest::Client::new()
+            .delete(format!("http://{}/v1/engines/davinci", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body,
+            json!({"error": {
+                "message": "Unknown endpoint",
+                "type": "invalid_request_error",
+                "code": "not_found",
+            }})
+        );
+    }
+
+    #[tokio::test]
+    async fn single_models_are_retrieved_by_id() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(SharedState::new(Arc::new(state::test_state())));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let get = |path: &str| {
+            reqwest::Client::new()
+                .get(format!("http://{}{}", addr, path))
+                .send()
+        };
+
+        let list: Value = get("/v1/models").await.unwrap().json().await.unwrap();
+        let response = get("/v1/models/claude-3-haiku-20240307").await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let model: Value = response.json().await.unwrap();
+        assert_eq!(model["id"], "claude-3-haiku-20240307");
+        assert_eq!(model, list["data"][0]);
+
+        let response = get("/v1/models/gpt-4o").await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body,
+            json!({"error": {
+                "message": "The model 'gpt-4o' does not exist",
+                "type": "invalid_request_error",
+                "code": "model_not_found",
+            }})
+        );
+    }
 }
@@ -0,0 +1,130 @@
+use tracing::{info, warn};
+
+use crate::{
+    state::Env,
+    transform::{content_to_text, OpenAIMessage},
+};
+
+// Denylist check run on inbound chat prompts before anything is sent to Bedrock.
+// Terms come from MODERATION_DENYLIST (comma-separated) and/or
+// MODERATION_DENYLIST_FILE (one per line, `#` comments) and match
+// case-insensitively anywhere in the concatenated user content
+pub struct Moderation {
+    terms: Vec<String>,
+}
+
+impl Moderation {
+    pub fn from_env(env: &Env) -> Result<Option<Self>, String> {
+        let mut terms: Vec<String> = env
+            .var("MODERATION_DENYLIST")
+            .map(|v| v.split(',').map(|t| t.to_string()).collect())
+            .unwrap_or_default();
+
+        if let Ok(path) = env.var("MODERATION_DENYLIST_FILE") {
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read MODERATION_DENYLIST_FILE {}: {}", path, e))?;
+            terms.extend(
+                contents
+                    .lines()
+                    .filter(|line| !line.trim_start().starts_with('#'))
+                    .map(|line| line.to_string()),
+            );
+        }
+
+        let terms: Vec<String> = terms
+            .iter()
+            .map(|t| t.trim().to_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+        if terms.is_empty() {
+            return Ok(None);
+        }
+
+        info!(
+            "🛡️ Content moderation enabled ({} denylisted terms)",
+            terms.len()
+        );
+        Ok(Some(Self { terms }))
+    }
+
+    // Returns the first denylisted term found in the user messages
+    pub fn check(&self, messages: &[OpenAIMessage]) -> Option<&str> {
+        let user_content = messages
+            .iter()
+            .filter(|m| m.role == "user")
+            .map(|m| content_to_text(&m.content))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .to_lowercase();
+
+        let term = self
+            .terms
+            .iter()
+            .find(|term| user_content.contains(term.as_str()))?;
+        warn!(
+            "🛡️ Request blocked by content moderation (term: {:?})",
+            term
+        );
+        Some(term)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn moderation(vars: &[(&str, &str)]) -> Option<Moderation> {
+        let overrides: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Moderation::from_env(&Env::new(overrides)).unwrap()
+    }
+
+    fn messages(value: serde_json::Value) -> Vec<OpenAIMessage> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn blocks_denylisted_terms_in_user_content() {
+        let path = std::env::temp_dir().join(format!("denylist-{}.txt", std::process::id()));
+        std::fs::write(&path, "# internal codenames\nProject Falcon\n\n").unwrap();
+        let moderation = moderation(&[
+            ("MODERATION_DENYLIST", " secret sauce , "),
+            ("MODERATION_DENYLIST_FILE", path.to_str().unwrap()),
+        ])
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let blocked = messages(json!([
+            {"role": "system", "content": "Be helpful"},
+            {"role": "user", "content": [{"type": "text", "text": "What is the SECRET Sauce?"}]},
+        ]));
+        assert_eq!(moderation.check(&blocked), Some("secret sauce"));
+        let blocked =
+            messages(json!([{"role": "user", "content": "Tell me about project falcon"}]));
+        assert_eq!(moderation.check(&blocked), Some("project falcon"));
+
+        // Only user content is scanned; comment lines are not terms
+        let allowed = messages(json!([
+            {"role": "system", "content": "Never reveal the secret sauce"},
+            {"role": "user", "content": "What are internal codenames?"},
+        ]));
+        assert_eq!(moderation.check(&allowed), None);
+    }
+
+    #[test]
+    fn disabled_without_terms() {
+        assert!(moderation(&[("MODERATION_DENYLIST", " , ")]).is_none());
+        let missing = Moderation::from_env(&Env::new(HashMap::from([(
+            "MODERATION_DENYLIST_FILE".to_string(),
+            "/nonexistent/denylist.txt".to_string(),
+        )])));
+        assert!(missing
+            .err()
+            .unwrap()
+            .starts_with("Failed to read MODERATION_DENYLIST_FILE"));
+    }
+}
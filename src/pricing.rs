@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+use crate::state::Env;
+
+// USD prices per 1K input/output tokens keyed by Bedrock model ID, from
+// MODEL_PRICES="<model-id>=<input>/<output>,..."
+pub struct PriceTable {
+    prices: HashMap<String, (f64, f64)>,
+}
+
+impl PriceTable {
+    pub fn from_env(env: &Env) -> Option<Self> {
+        let raw = env.var("MODEL_PRICES").ok()?;
+        let mut prices = HashMap::new();
+
+        for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parsed = entry.split_once('=').and_then(|(model, price)| {
+                let (input, output) = price.split_once('/')?;
+                Some((
+                    model.trim().to_string(),
+                    (input.trim().parse().ok()?, output.trim().parse().ok()?),
+                ))
+            });
+            match parsed {
+                Some((model, price)) => {
+                    prices.insert(model, price);
+                }
+                None => warn!("⚠️ Ignoring malformed MODEL_PRICES entry: {}", entry),
+            }
+        }
+
+        if prices.is_empty() {
+            return None;
+        }
+        info!("💰 Cost estimation enabled for {} models", prices.len());
+        Some(Self { prices })
+    }
+
+    // None when the model has no configured price
+    pub fn estimate(
+        &self,
+        model_id: &str,
+        prompt_tokens: i32,
+        completion_tokens: i32,
+    ) -> Option<f64> {
+        let (input, output) = self.prices.get(model_id)?;
+        Some((prompt_tokens as f64 * input + completion_tokens as f64 * output) / 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(prices: &str) -> Option<PriceTable> {
+        let env = Env::new(HashMap::from([(
+            "MODEL_PRICES".to_string(),
+            prices.to_string(),
+        )]));
+        PriceTable::from_env(&env)
+    }
+
+    #[test]
+    fn estimates_cost_from_per_1k_prices() {
+        let prices =
+            table("anthropic.claude-3-haiku-20240307-v1:0=0.25/1.25, bad-entry, x=1/oops").unwrap();
+        let cost = prices
+            .estimate("anthropic.claude-3-haiku-20240307-v1:0", 2000, 400)
+            .unwrap();
+        assert!((cost - 1.0).abs() < 1e-9, "{}", cost);
+        assert_eq!(prices.estimate("x", 1, 1), None);
+        assert_eq!(prices.estimate("unpriced-model", 1000, 1000), None);
+    }
+
+    #[test]
+    fn no_valid_entries_disables_estimation() {
+        assert!(table("").is_none());
+        assert!(table("model=cheap").is_none());
+    }
+}
@@ -0,0 +1,218 @@
+use base64::Engine;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+use crate::state::{AppState, Env};
+
+// Send a signed request to Bedrock, honouring RECORD_DIR / REPLAY_DIR.
+//
+// REPLAY_DIR serves a previously recorded response instead of calling Bedrock.
+// RECORD_DIR calls Bedrock and writes the exchange to a fixture file first; the
+// whole body is read before it is returned, so recorded streams arrive in one piece.
+// Fixtures are keyed by method, URL and request body, so the same chat request
+// always maps to the same file.
+pub async fn execute(
+    state: &AppState,
+    req: reqwest::Request,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let key = fixture_key(&req);
+
+    if let Some(dir) = &state.replay_dir {
+        return Ok(replay(dir, &key));
+    }
+
+    let Some(dir) = &state.record_dir else {
+        return state.client.execute(req).await;
+    };
+
+    let request = json!({
+        "method": req.method().as_str(),
+        "url": req.url().as_str(),
+        "body": req.body().and_then(|b| b.as_bytes()).map(body_value),
+    });
+    let resp = state.client.execute(req).await?;
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let body = resp.bytes().await?;
+
+    let mut fixture = json!({
+        "request": request,
+        "status": status.as_u16(),
+        "headers": recorded_headers(&headers),
+    });
+    // JSON bodies stay readable; event streams are binary
+    match serde_json::from_slice::<Value>(&body) {
+        Ok(json) => fixture["response"] = json,
+        Err(_) => {
+            fixture["response_base64"] = json!(base64::prelude::BASE64_STANDARD.encode(&body))
+        }
+    }
+
+    let path = dir.join(format!("{}.json", key));
+    let written = serde_json::to_vec_pretty(&fixture)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| std::fs::write(&path, bytes).map_err(|e| e.to_string()));
+    match written {
+        Ok(()) => info!("📼 Recorded Bedrock exchange to {}", path.display()),
+        Err(e) => error!("❌ Failed to write recording {}: {}", path.display(), e),
+    }
+
+    Ok(response_from_parts(status, &headers, body))
+}
+
+// Rebuild a Bedrock response from a body that has already been read
+pub fn response_from_parts(
+    status: http::StatusCode,
+    headers: &http::HeaderMap,
+    body: axum::body::Bytes,
+) -> reqwest::Response {
+    let mut builder = http::Response::builder().status(status);
+    for (k, v) in headers.iter() {
+        builder = builder.header(k, v);
+    }
+    builder.body(body).unwrap().into()
+}
+
+// Recorded response for `key`, or a 500 naming the missing fixture
+fn replay(dir: &Path, key: &str) -> reqwest::Response {
+    let path = dir.join(format!("{}.json", key));
+    let fixture = std::fs::read(&path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok());
+    let Some(fixture) = fixture else {
+        warn!("📼 No recording at {}", path.display());
+        return http::Response::builder()
+            .status(500)
+            .body(format!(
+                "No recording for this request in REPLAY_DIR ({})",
+                path.display()
+            ))
+            .unwrap()
+            .into();
+    };
+
+    info!("📼 Replaying Bedrock exchange from {}", path.display());
+    let mut builder =
+        http::Response::builder().status(fixture["status"].as_u64().unwrap_or(200) as u16);
+    if let Some(headers) = fixture["headers"].as_object() {
+        for (k, v) in headers {
+            if let Some(v) = v.as_str() {
+                builder = builder.header(k.as_str(), v);
+            }
+        }
+    }
+    let body = match fixture.get("response") {
+        Some(json) => serde_json::to_vec(json).unwrap_or_default(),
+        None => fixture["response_base64"]
+            .as_str()
+            .and_then(|b| base64::prelude::BASE64_STANDARD.decode(b).ok())
+            .unwrap_or_default(),
+    };
+    builder.body(body).unwrap().into()
+}
+
+// Response headers worth replaying; framing headers no longer match the stored body
+fn recorded_headers(headers: &http::HeaderMap) -> Map<String, Value> {
+    headers
+        .iter()
+        .filter(|(k, _)| {
+            !matches!(
+                k.as_str(),
+                "content-length" | "transfer-encoding" | "connection"
+            )
+        })
+        .filter_map(|(k, v)| Some((k.to_string(), json!(v.to_str().ok()?))))
+        .collect()
+}
+
+// First 16 bytes of SHA-256 over method, URL and body, hex-encoded
+fn fixture_key(req: &reqwest::Request) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(req.url().as_str());
+    if let Some(body) = req.body().and_then(|b| b.as_bytes()) {
+        hasher.update(body);
+    }
+    hasher.finalize()[..16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Request bodies are JSON; keep them readable in the fixture
+fn body_value(bytes: &[u8]) -> Value {
+    serde_json::from_slice(bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(bytes).to_string()))
+}
+
+// RECORD_DIR / REPLAY_DIR, created if missing
+pub fn dir_from_env(env: &Env, var: &str) -> Result<Option<PathBuf>, String> {
+    let Ok(dir) = env.var(var) else {
+        return Ok(None);
+    };
+    let dir = PathBuf::from(dir);
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create {} {}: {}", var, dir.display(), e))?;
+    info!("📼 {} enabled: {}", var, dir.display());
+    Ok(Some(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::test_state;
+
+    const HAIKU_URL: &str = "https://bedrock-runtime.us-east-1.amazonaws.com/model/anthropic.claude-3-haiku-20240307-v1:0/invoke";
+
+    fn invoke_request(state: &AppState, body: &str) -> reqwest::Request {
+        state
+            .client
+            .post(HAIKU_URL)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_fixture() {
+        let mut state = test_state();
+        state.replay_dir =
+            Some(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/replay"));
+        let req = invoke_request(
+            &state,
+            r#"{"anthropic_version":"bedrock-2023-05-31","max_tokens":64,"messages":[{"role":"user","content":"Say hello"}]}"#,
+        );
+
+        let resp = execute(&state, req).await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["x-amzn-bedrock-output-token-count"], "12");
+        let body: Value = resp.json().await.unwrap();
+        assert_eq!(
+            body["content"][0]["text"],
+            "Hello! How can I help you today?"
+        );
+        assert_eq!(body["stop_reason"], "end_turn");
+        assert_eq!(body["usage"]["output_tokens"], 12);
+    }
+
+    #[tokio::test]
+    async fn missing_fixture_is_a_500() {
+        let mut state = test_state();
+        state.replay_dir =
+            Some(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/replay"));
+        let req = invoke_request(
+            &state,
+            r#"{"messages":[{"role":"user","content":"unrecorded"}]}"#,
+        );
+        let key = fixture_key(&req);
+
+        let resp = execute(&state, req).await.unwrap();
+        assert_eq!(resp.status(), 500);
+        let body = resp.text().await.unwrap();
+        assert!(body.starts_with("No recording for this request in REPLAY_DIR"));
+        assert!(body.contains(&format!("{}.json", key)));
+    }
+}
@@ -0,0 +1,78 @@
+use http::Request;
+use serde_json::json;
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::errors::map_bedrock_error;
+use crate::handlers::bedrock_error_type;
+use crate::recording;
+use crate::signing::sign_request;
+use crate::state::AppState;
+use crate::transform::{ModelFamily, OpenAIRequest};
+
+const SELFTEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Send a one-token completion for the configured inference profile through the
+// normal transform/sign path, so bad credentials, region or model access show up
+// before any traffic arrives
+pub async fn run(state: &AppState) -> Result<(), String> {
+    let openai_req: OpenAIRequest = serde_json::from_value(json!({
+        "messages": [{"role": "user", "content": "ping"}],
+        "max_tokens": 1,
+    }))
+    .map_err(|e| e.to_string())?;
+
+    let model_id = &state.inference_profile;
+    let family = ModelFamily::for_request(state, model_id);
+    let payload = family.adapter().to_bedrock(&openai_req, model_id, state);
+
+    let endpoint = state.model_url(model_id, family.action(false));
+    let http_req = Request::builder()
+        .method("POST")
+        .uri(&endpoint)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_vec(&payload).unwrap())
+        .unwrap();
+    let reqwest_req =
+        sign_request(http_req, state, false).map_err(|e| format!("Signing error: {}", e))?;
+
+    let resp = tokio::time::timeout(SELFTEST_TIMEOUT, recording::execute(state, reqwest_req))
+        .await
+        .map_err(|_| {
+            format!(
+                "no response from {} within {:?}",
+                endpoint, SELFTEST_TIMEOUT
+            )
+        })?
+        .map_err(|e| format!("Request error: {}", e))?;
+
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let error_type = bedrock_error_type(&resp);
+    let body = resp.text().await.unwrap_or_default();
+    let message = map_bedrock_error(status, error_type.as_deref(), &body)
+        .and_then(|e| e["error"]["message"].as_str().map(str::to_string))
+        .unwrap_or(body);
+    Err(format!("Bedrock returned {}: {}", status, message))
+}
+
+// Run the self-test and log the outcome; returns whether it passed
+pub async fn check(state: &AppState) -> bool {
+    info!(
+        "🩺 Self-test: invoking {} in {}",
+        state.inference_profile, state.region
+    );
+    match run(state).await {
+        Ok(()) => {
+            info!("✅ Self-test passed: credentials, region and model access look good");
+            true
+        }
+        Err(e) => {
+            error!("❌ Self-test failed: {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use serde_json::Value;
+
+use crate::transform::OpenAIStreamResponse;
+
+// Sentence-ending punctuation that needs whitespace after it to count as a boundary
+// (so "3.14" or "e.g." mid-word don't split); CJK full stops end a sentence on their own
+const SENTENCE_ENDS: &[char] = &['.', '!', '?'];
+const CJK_SENTENCE_ENDS: &[char] = &['。', '！', '？'];
+
+// Regroups streamed content deltas into whole sentences (STREAM_FLUSH_ON_SENTENCE=true)
+// for TTS-driven clients. Text is held back until a sentence boundary; chunks carrying a
+// role, tool calls or a finish_reason flush whatever is buffered along with them
+#[derive(Default)]
+pub struct SentenceBuffer {
+    text: String,
+    // Last chunk seen, reused as the envelope for text flushed at end of stream
+    last: Option<OpenAIStreamResponse>,
+}
+
+impl SentenceBuffer {
+    // Returns the chunk to send for `chunk`, if any: its content replaced by the
+    // complete sentences buffered so far
+    pub fn push(&mut self, mut chunk: OpenAIStreamResponse) -> Option<OpenAIStreamResponse> {
+        let choice = chunk.choices.first_mut()?;
+        let Some(delta) = choice.delta.as_object_mut() else {
+            return Some(chunk);
+        };
+
+        if let Some(content) = delta.remove("content") {
+            if let Some(text) = content.as_str() {
+                self.text.push_str(text);
+            }
+        }
+
+        let flush_all = choice.finish_reason.is_some() || delta.contains_key("tool_calls");
+        let flushed = if flush_all {
+            std::mem::take(&mut self.text)
+        } else {
+            let end = last_sentence_end(&self.text);
+            self.text.drain(..end).collect()
+        };
+
+        let passthrough = !delta.is_empty() || choice.finish_reason.is_some();
+        if flushed.is_empty() && !passthrough {
+            self.last = Some(chunk);
+            return None;
+        }
+
+        // Keep `content` on role chunks so the first delta looks as it did unbuffered
+        if !flushed.is_empty() || delta.contains_key("role") {
+            delta.insert("content".to_string(), Value::String(flushed));
+        }
+        self.last = Some(chunk.clone());
+        Some(chunk)
+    }
+
+    // Text left over when the stream ends without a finish_reason
+    pub fn finish(&mut self) -> Option<OpenAIStreamResponse> {
+        if self.text.is_empty() {
+            return None;
+        }
+
+        let mut chunk = self.last.take()?;
+        let choice = chunk.choices.first_mut()?;
+        choice.finish_reason = None;
+        choice.delta = serde_json::json!({"content": std::mem::take(&mut self.text)});
+        Some(chunk)
+    }
+}
+
+// Byte offset just past the last sentence boundary in `text` (0 if none):
+// a newline, a CJK full stop, or ./!/? followed by whitespace
+fn last_sentence_end(text: &str) -> usize {
+    let mut end = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '\n' || CJK_SENTENCE_ENDS.contains(&c) {
+            end = i + c.len_utf8();
+        } else if SENTENCE_ENDS.contains(&c) {
+            if let Some(&(j, next)) = chars.peek() {
+                if next.is_whitespace() {
+                    end = j + next.len_utf8();
+                }
+            }
+        }
+    }
+    end
+}
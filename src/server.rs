@@ -0,0 +1,64 @@
+use axum::{extract::ConnectInfo, Extension, Router};
+use hyper::server::conn::http1;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tracing::{debug, error, info};
+
+// Accept loop for the inbound server. HTTP/1.1 connections always use keep-alive;
+// with ENABLE_HTTP2 the same port also accepts cleartext HTTP/2 (h2c prior knowledge).
+// The auto builder ignores `http1_only` for upgradeable connections, so HTTP/1.1-only
+// mode uses the hyper http1 builder directly.
+pub async fn serve(listener: TcpListener, app: Router, enable_http2: bool, keepalive_secs: u64) {
+    let mut http1 = http1::Builder::new();
+    http1.keep_alive(true);
+
+    let mut auto = auto::Builder::new(TokioExecutor::new());
+    auto.http1().keep_alive(true);
+    auto.http2()
+        .timer(TokioTimer::new())
+        .enable_connect_protocol()
+        .keep_alive_interval((keepalive_secs > 0).then(|| Duration::from_secs(keepalive_secs)));
+
+    if enable_http2 {
+        info!("🔀 HTTP/2 (h2c) enabled alongside HTTP/1.1");
+    }
+
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("❌ Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let io = TokioIo::new(stream);
+        // Peer address for handlers, the same extension axum's connect-info service sets
+        let app = app.clone().layer(Extension(ConnectInfo(remote_addr)));
+        let service = TowerToHyperService::new(app);
+        let http1 = http1.clone();
+        let auto = auto.clone();
+        tokio::spawn(async move {
+            let result = if enable_http2 {
+                auto.serve_connection_with_upgrades(io, service).await
+            } else {
+                http1
+                    .serve_connection(io, service)
+                    .with_upgrades()
+                    .await
+                    .map_err(Into::into)
+            };
+            if let Err(e) = result {
+                debug!(
+                    "🔌 Connection from {} closed with error: {}",
+                    remote_addr, e
+                );
+            }
+        });
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Model a session last asked for: `model` from the body and the X-Bedrock-Model-Id override
+#[derive(Clone, Default)]
+pub struct SessionModel {
+    pub model: Option<String>,
+    pub model_id: Option<String>,
+}
+
+// Sticky model selection keyed by X-Session-Id (SESSION_TTL_SECS > 0). An entry expires
+// SESSION_TTL_SECS after the session's last request; nothing survives a restart
+pub struct StickySessions {
+    ttl: Duration,
+    sessions: Mutex<HashMap<String, (Instant, SessionModel)>>,
+}
+
+impl StickySessions {
+    pub fn from_env() -> Option<Self> {
+        let ttl_secs: u64 = std::env::var("SESSION_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|s| *s > 0)?;
+        Some(Self::new(Duration::from_secs(ttl_secs)))
+    }
+
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn recall(&self, session_id: &str) -> Option<SessionModel> {
+        let mut sessions = self.sessions.lock().unwrap();
+        Self::evict(&mut sessions, Instant::now(), self.ttl);
+        sessions.get(session_id).map(|(_, model)| model.clone())
+    }
+
+    pub fn remember(&self, session_id: &str, model: SessionModel) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        Self::evict(&mut sessions, now, self.ttl);
+        sessions.insert(session_id.to_string(), (now, model));
+    }
+
+    fn evict(sessions: &mut HashMap<String, (Instant, SessionModel)>, now: Instant, ttl: Duration) {
+        sessions.retain(|_, (at, _)| now.duration_since(*at) <= ttl);
+    }
+}
@@ -1,5 +1,6 @@
 use aws_sigv4::http_request::{
-    sign, SignableBody, SignableRequest, SigningParams as HttpSigningParams, SigningSettings,
+    sign, PayloadChecksumKind, SignableBody, SignableRequest, SigningParams as HttpSigningParams,
+    SigningSettings,
 };
 use aws_sigv4::sign::v4::SigningParams;
 use http::Request;
@@ -7,33 +8,55 @@ use std::time::SystemTime;
 
 use crate::state::AppState;
 
+// "/model/{id}/invoke" → "{id}", selecting a MODEL_CREDENTIALS set
+fn model_id_from_path(path: &str) -> Option<String> {
+    let id = path.strip_prefix("/model/")?.split('/').next()?;
+    urlencoding::decode(id).ok().map(|id| id.into_owned())
+}
+
 pub fn sign_request(
     req: Request<Vec<u8>>,
     state: &AppState,
     is_streaming: bool,
 ) -> Result<reqwest::Request, Box<dyn std::error::Error>> {
-    let identity = aws_credential_types::Credentials::new(
-        &state.access_key,
-        &state.secret_key,
-        state.session_token.clone(),
-        None,
-        "hardcoded-credentials",
-    )
+    sign_request_at(req, state, is_streaming, SystemTime::now())
+}
+
+// Same as `sign_request` with an explicit signing time, so a fixed
+// request/time/credentials triple always yields the same X-Amz-Date and signature
+pub fn sign_request_at(
+    req: Request<Vec<u8>>,
+    state: &AppState,
+    is_streaming: bool,
+    time: SystemTime,
+) -> Result<reqwest::Request, Box<dyn std::error::Error>> {
+    let identity = match model_id_from_path(req.uri().path()) {
+        Some(model_id) => state.credentials_for_model(&model_id),
+        None => state.credentials(),
+    }
     .into();
 
-    let signing_settings = SigningSettings::default();
+    // Send the body hash as X-Amz-Content-Sha256 so Bedrock checks it
+    // against the bytes it receives
+    let mut signing_settings = SigningSettings::default();
+    signing_settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
 
     let signing_params: HttpSigningParams = SigningParams::builder()
         .identity(&identity)
         .region(&state.region)
         .name("bedrock")
-        .time(SystemTime::now())
+        .time(time)
         .settings(signing_settings)
         .build()
         .unwrap()
         .into();
 
-    let host_header = format!("bedrock-runtime.{}.amazonaws.com", state.region);
+    // Signed Host is the one the request goes to (BEDROCK_ENDPOINT_URL may change it)
+    let host_header = match (req.uri().host(), req.uri().port_u16()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => format!("bedrock-runtime.{}.amazonaws.com", state.region),
+    };
 
     let mut headers = vec![
         ("content-type", "application/json"),
@@ -47,11 +70,23 @@ pub fn sign_request(
     };
     headers.push(("accept", accept_header));
 
+    // Any other headers on the request (e.g. FORWARD_HEADERS) are signed and sent too
+    let extra_headers: Vec<(&str, &str)> = req
+        .headers()
+        .iter()
+        .filter(|(name, _)| !matches!(name.as_str(), "content-type" | "host" | "accept"))
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect();
+    headers.extend(extra_headers.iter().copied());
+
+    // The signed bytes and the sent bytes must be the same buffer
+    let body = req.body();
+
     let signable_req = SignableRequest::new(
         req.method().as_str(),
         req.uri().to_string(),
         headers.clone().into_iter(),
-        SignableBody::Bytes(req.body()),
+        SignableBody::Bytes(body),
     )?;
 
     let (signing_instructions, _signature) = sign(signable_req, &signing_params)?.into_parts();
@@ -60,8 +95,11 @@ pub fn sign_request(
         .method(req.method().clone())
         .uri(req.uri().clone())
         .header("content-type", "application/json")
-        .header("accept", accept_header)
-        .body(req.body().clone())?;
+        .header("accept", accept_header);
+    for (name, value) in &extra_headers {
+        signed_http = signed_http.header(*name, *value);
+    }
+    let mut signed_http = signed_http.body(body.clone())?;
 
     signing_instructions.apply_to_request_http1x(&mut signed_http);
 
@@ -77,4 +115,174 @@ pub fn sign_request(
     builder = builder.body(signed_http.body().clone());
 
     Ok(builder.build()?)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::test_state;
+    use sha2::{Digest, Sha256};
+    use std::time::Duration;
+
+    // 2015-08-30T12:36:00Z, the date of AWS's published SigV4 examples
+    const SIGNING_TIME: u64 = 1_440_938_160;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut block = [0u8; 64];
+        if key.len() > block.len() {
+            block[..32].copy_from_slice(&Sha256::digest(key));
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let inner = Sha256::new()
+            .chain_update(block.map(|b| b ^ 0x36))
+            .chain_update(data)
+            .finalize();
+        Sha256::new()
+            .chain_update(block.map(|b| b ^ 0x5c))
+            .chain_update(inner)
+            .finalize()
+            .to_vec()
+    }
+
+    fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+        let key = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+        let key = hmac_sha256(&key, region.as_bytes());
+        let key = hmac_sha256(&key, service.as_bytes());
+        hmac_sha256(&key, b"aws4_request")
+    }
+
+    #[test]
+    fn reference_signing_key_matches_aws_example() {
+        // From the AWS SigV4 documentation's "derive a signing key" example
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20150830",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9"
+        );
+    }
+
+    #[test]
+    fn signs_fixed_request_at_fixed_time() {
+        let mut state = test_state();
+        state.session_token = None;
+        let body = br#"{"messages":[{"role":"user","content":"hi"}]}"#.to_vec();
+        let req = Request::builder()
+            .method("POST")
+            .uri("https://bedrock-runtime.us-east-1.amazonaws.com/model/anthropic.claude-v2/invoke")
+            .header("Content-Type", "application/json")
+            .body(body.clone())
+            .unwrap();
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(SIGNING_TIME);
+
+        let signed = sign_request_at(req, &state, false, time).unwrap();
+        let header = |name: &str| signed.headers()[name].to_str().unwrap().to_string();
+        assert_eq!(header("x-amz-date"), "20150830T123600Z");
+
+        let body_hash = hex(&Sha256::digest(&body));
+        let canonical_request = [
+            "POST",
+            "/model/anthropic.claude-v2/invoke",
+            "",
+            "accept:application/json",
+            "content-type:application/json",
+            "host:bedrock-runtime.us-east-1.amazonaws.com",
+            &format!("x-amz-content-sha256:{}", body_hash),
+            "x-amz-date:20150830T123600Z",
+            "",
+            "accept;content-type;host;x-amz-content-sha256;x-amz-date",
+            &body_hash,
+        ]
+        .join("\n");
+        let string_to_sign = [
+            "AWS4-HMAC-SHA256",
+            "20150830T123600Z",
+            "20150830/us-east-1/bedrock/aws4_request",
+            &hex(&Sha256::digest(canonical_request.as_bytes())),
+        ]
+        .join("\n");
+        let key = signing_key(&state.secret_key, "20150830", "us-east-1", "bedrock");
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        assert_eq!(
+            header("authorization"),
+            format!(
+                "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/bedrock/aws4_request, \
+                 SignedHeaders=accept;content-type;host;x-amz-content-sha256;x-amz-date, \
+                 Signature={}",
+                signature
+            )
+        );
+    }
+
+    #[test]
+    fn content_sha256_matches_sent_body() {
+        let state = test_state();
+        let text = "héllo wörld 👋 ".repeat(20_000);
+        let body = serde_json::to_vec(&serde_json::json!({
+            "messages": [{"role": "user", "content": text}]
+        }))
+        .unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri("https://bedrock-runtime.us-east-1.amazonaws.com/model/anthropic.claude-v2/invoke")
+            .header("Content-Type", "application/json")
+            .body(body.clone())
+            .unwrap();
+
+        let signed = sign_request(req, &state, false).unwrap();
+        let sent = signed.body().and_then(|b| b.as_bytes()).unwrap();
+        assert_eq!(sent, body.as_slice());
+        assert_eq!(
+            signed.headers()["x-amz-content-sha256"],
+            hex(&Sha256::digest(sent)).as_str()
+        );
+    }
+
+    #[test]
+    fn models_sign_with_their_credential_set() {
+        let state = crate::state::test_state_with(&[
+            ("MODEL_CREDENTIALS", "meta.llama3-70b-instruct-v1:0=llama"),
+            ("AWS_ACCESS_KEY_ID_LLAMA", "AKIDLLAMAEXAMPLE"),
+            ("AWS_SECRET_ACCESS_KEY_LLAMA", "llamaSecretKeyEXAMPLE"),
+            ("AWS_SESSION_TOKEN_LLAMA", "llama-session-token"),
+        ])
+        .ok()
+        .unwrap();
+        let sign = |model_path: &str| {
+            let req = Request::builder()
+                .method("POST")
+                .uri(format!(
+                    "https://bedrock-runtime.us-east-1.amazonaws.com/model/{}/invoke",
+                    model_path
+                ))
+                .header("Content-Type", "application/json")
+                .body(b"{}".to_vec())
+                .unwrap();
+            sign_request(req, &state, false).unwrap()
+        };
+        let access_key = |signed: &reqwest::Request| {
+            let authorization = signed.headers()["authorization"].to_str().unwrap();
+            let credential = authorization.split("Credential=").nth(1).unwrap();
+            credential.split('/').next().unwrap().to_string()
+        };
+
+        let claude = sign("anthropic.claude-3-haiku-20240307-v1%3A0");
+        assert_eq!(access_key(&claude), "AKIDEXAMPLE");
+        let llama = sign("meta.llama3-70b-instruct-v1%3A0");
+        assert_eq!(access_key(&llama), "AKIDLLAMAEXAMPLE");
+        assert_eq!(
+            llama.headers()["x-amz-security-token"],
+            "llama-session-token"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use axum::body::Bytes;
+use http::{HeaderMap, StatusCode};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+use tracing::info;
+
+use crate::{recording, state::AppState};
+
+// Status, headers and fully read body of one upstream call; errors are shared as text
+type SharedReply = Result<(StatusCode, HeaderMap, Bytes), String>;
+
+// Coalesces concurrent identical non-streaming Bedrock calls (COALESCE_REQUESTS=true):
+// the first caller makes the request and everyone waiting on the same key gets a copy
+// of its response. Entries are dropped as soon as the call completes, so nothing is cached
+#[derive(Default)]
+pub struct SingleFlight {
+    inflight: Mutex<HashMap<String, Arc<OnceCell<SharedReply>>>>,
+}
+
+impl SingleFlight {
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("COALESCE_REQUESTS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        enabled.then(Self::default)
+    }
+
+    pub async fn execute(
+        &self,
+        state: &AppState,
+        req: reqwest::Request,
+    ) -> Result<reqwest::Response, String> {
+        let key = request_key(&req);
+        let cell = {
+            let mut inflight = self.inflight.lock().unwrap();
+            if inflight.contains_key(&key) {
+                info!("🔗 Coalescing identical in-flight Bedrock request");
+            }
+            inflight.entry(key.clone()).or_default().clone()
+        };
+
+        let reply = cell
+            .get_or_init(|| async move {
+                let resp = recording::execute(state, req)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let status = resp.status();
+                let headers = resp.headers().clone();
+                let body = resp.bytes().await.map_err(|e| e.to_string())?;
+                Ok((status, headers, body))
+            })
+            .await
+            .clone();
+
+        let mut inflight = self.inflight.lock().unwrap();
+        if inflight.get(&key).is_some_and(|c| Arc::ptr_eq(c, &cell)) {
+            inflight.remove(&key);
+        }
+        drop(inflight);
+
+        let (status, headers, body) = reply?;
+        Ok(recording::response_from_parts(status, &headers, body))
+    }
+}
+
+// Identical requests: same URL, body and unsigned headers (e.g. FORWARD_HEADERS)
+fn request_key(req: &reqwest::Request) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.url().as_str());
+    let mut headers: Vec<(&str, &[u8])> = req
+        .headers()
+        .iter()
+        .filter(|(name, _)| {
+            !matches!(
+                name.as_str(),
+                "authorization" | "x-amz-date" | "x-amz-security-token" | "x-amz-content-sha256"
+            )
+        })
+        .map(|(name, value)| (name.as_str(), value.as_bytes()))
+        .collect();
+    headers.sort();
+    for (name, value) in headers {
+        hasher.update(name);
+        hasher.update(b":");
+        hasher.update(value);
+        hasher.update(b"\n");
+    }
+    if let Some(body) = req.body().and_then(|b| b.as_bytes()) {
+        hasher.update(body);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::test_state;
+    use axum::Json;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    // Bedrock mock that counts calls and answers slowly enough for callers to overlap
+    async fn counting_bedrock(state: &mut AppState) -> Arc<AtomicUsize> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let router = axum::Router::new().route(
+            "/model/{id}/invoke",
+            axum::routing::post(move |Json(body): Json<Value>| {
+                let call = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    Json(json!({"call": call, "echo": body}))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        state.bedrock_endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        calls
+    }
+
+    async fn call(singleflight: &SingleFlight, state: &AppState, prompt: &str) -> Value {
+        let req = state
+            .client
+            .post(state.model_url("anthropic.claude-3-haiku-20240307-v1:0", "invoke"))
+            .header("Content-Type", "application/json")
+            .body(json!({"messages": [{"role": "user", "content": prompt}]}).to_string())
+            .build()
+            .unwrap();
+        let resp = singleflight.execute(state, req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn identical_concurrent_requests_share_one_call() {
+        let mut state = test_state();
+        let calls = counting_bedrock(&mut state).await;
+        let singleflight = SingleFlight::default();
+
+        let (first, second) = tokio::join!(
+            call(&singleflight, &state, "hi"),
+            call(&singleflight, &state, "hi")
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(first, second);
+        assert!(singleflight.inflight.lock().unwrap().is_empty());
+
+        // Nothing is cached once the call completes
+        call(&singleflight, &state, "hi").await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn different_bodies_are_not_coalesced() {
+        let mut state = test_state();
+        let calls = counting_bedrock(&mut state).await;
+        let singleflight = SingleFlight::default();
+
+        let (first, second) = tokio::join!(
+            call(&singleflight, &state, "hi"),
+            call(&singleflight, &state, "bye")
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(first["echo"]["messages"][0]["content"], "hi");
+        assert_eq!(second["echo"]["messages"][0]["content"], "bye");
+    }
+}
@@ -1,26 +1,959 @@
+use aws_credential_types::Credentials;
+use axum::{extract::FromRef, response::sse::KeepAlive};
 use reqwest::Client;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::{
+    accounts::CredentialSets, api_keys::ApiKeys, audit::AuditLogger,
+    circuit_breaker::CircuitBreaker, latency::LatencyStats, moderation::Moderation,
+    pricing::PriceTable, recording, sessions::StickySessions, singleflight::SingleFlight,
+    stats::RequestStats, sts::AssumeRoleConfig, transform::Clock,
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub client: Client,
     pub region: String,
+    // Bedrock runtime base URL; BEDROCK_ENDPOINT_URL overrides the regional default
+    pub bedrock_endpoint: String,
     pub access_key: String,
     pub secret_key: String,
     pub session_token: Option<String>,
     pub inference_profile: String,
+    pub truncate_context_tokens: Option<usize>,
+    pub default_max_tokens: u32,
+    pub model_context_window: Option<usize>,
+    pub response_content_parts: bool,
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
+    pub emit_empty_stream_chunk: bool,
+    // Escape line breaks so every SSE event is a single `data:` line
+    pub sse_single_line_data: bool,
+    pub assume_role: Option<AssumeRoleConfig>,
+    // Temporary credentials from STS AssumeRole, refreshed in the background
+    pub assumed_credentials: Arc<RwLock<Option<Credentials>>>,
+    pub audit_log: Option<Arc<AuditLogger>>,
+    pub connect_retry_attempts: u32,
+    pub allowed_model_ids: Vec<String>,
+    pub sse_keepalive_secs: u64,
+    pub sse_keepalive_text: String,
+    pub max_tool_turns: Option<usize>,
+    pub reject_tool_turns: bool,
+    pub price_table: Option<Arc<PriceTable>>,
+    pub latency_stats: Option<Arc<LatencyStats>>,
+    // Counters for GET /admin/stats
+    pub request_stats: Arc<RequestStats>,
+    pub request_timeout_ms: Option<u64>,
+    pub max_request_timeout_ms: u64,
+    // Streams give up when Bedrock sends no body bytes within this window
+    pub stream_ttfb_timeout_ms: Option<u64>,
+    // Retries for a stream that fails before its first chunk (throttling, transient 5xx)
+    pub stream_retry_attempts: u32,
+    pub estimate_missing_usage: bool,
+    pub use_converse_api: bool,
+    pub response_excluded_fields: Vec<String>,
+    pub batch_concurrency: usize,
+    pub default_model_name: String,
+    pub enable_http2: bool,
+    pub http2_keepalive_secs: u64,
+    pub created_from_upstream_date: bool,
+    // Clock for `created` (FIXED_CREATED_TIMESTAMP freezes it)
+    pub clock: Clock,
+    pub moderation: Option<Arc<Moderation>>,
+    // Extended-thinking budget_tokens for each OpenAI reasoning_effort level
+    pub reasoning_budget_low: u32,
+    pub reasoning_budget_medium: u32,
+    pub reasoning_budget_high: u32,
+    // Lowercased inbound header names copied (and signed) onto Bedrock requests
+    pub forward_headers: Vec<String>,
+    pub validate_bedrock_responses: bool,
+    // Bedrock exchange fixtures: written under RECORD_DIR, served from REPLAY_DIR
+    pub record_dir: Option<PathBuf>,
+    pub replay_dir: Option<PathBuf>,
+    pub credential_sets: Option<Arc<CredentialSets>>,
+    pub expose_bedrock_stop_reason: bool,
+    pub singleflight: Option<Arc<SingleFlight>>,
+    pub json_retry_max_tokens: Option<u32>,
+    // MAX_TOKENS_CEILING=clamp|beta: hold max_tokens to the model's output ceiling,
+    // with `beta` enabling the extended-output beta where the model has one
+    pub enforce_max_tokens_ceiling: bool,
+    pub extended_output_beta: bool,
+    pub stream_flush_on_sentence: bool,
+    // VALIDATE_TOOL_ARGUMENTS=warn|error: check streamed tool call arguments parse as JSON,
+    // with `error` also sending an error chunk to the client
+    pub validate_tool_arguments: bool,
+    pub tool_arguments_error_chunk: bool,
+    // Text wrapped around the latest user message on the Anthropic path
+    pub user_prompt_prefix: Option<String>,
+    pub user_prompt_suffix: Option<String>,
+    pub allow_debug_trace: bool,
+    // Overloaded Bedrock errors become 429 with this Retry-After (REMAP_OVERLOADED_TO_429)
+    pub remap_overloaded_to_429: bool,
+    pub overloaded_retry_after_secs: u64,
+    // Bearer token for /admin endpoints; they answer 404 when unset
+    pub admin_token: Option<String>,
+    // Client API keys (API_KEYS); None leaves the Bedrock routes open
+    pub api_keys: Option<Arc<ApiKeys>>,
+    // Cap on request/response bodies in debug logs
+    pub log_body_max_bytes: Option<usize>,
+    pub strict_sampling_params: bool,
+    // Explicit `Accept: application/json` turns a stream: true request into a JSON response
+    pub accept_json_overrides_stream: bool,
+    // INFERENCE_TAGS key/value pairs sent as Converse requestMetadata
+    pub inference_tags: Vec<(String, String)>,
+    // Reject requests whose profile can't be called from AWS_REGION before signing
+    pub validate_profile_region: bool,
+    // Image content parts allowed in one chat request; Bedrock rejects more
+    pub max_images_per_request: usize,
+    // Take the client IP from X-Forwarded-For; only safe behind a proxy that sets it
+    pub trust_forwarded_for: bool,
+    // Stop sequences allowed per request; over the limit they are rejected, or with
+    // STOP_SEQUENCES_MODE=truncate only the first MAX_STOP_SEQUENCES are sent
+    pub max_stop_sequences: usize,
+    pub truncate_stop_sequences: bool,
+    // LOGPROBS_UNSUPPORTED_BEHAVIOR=error: refuse logprobs requests instead of returning null
+    pub reject_logprobs: bool,
+    // X-Session-Id model stickiness (SESSION_TTL_SECS)
+    pub sticky_sessions: Option<Arc<StickySessions>>,
 }
 
-impl AppState {
+// Router state: the current AppState behind a lock so /admin/reload can swap it.
+// Handlers extract `State<Arc<AppState>>` and keep that snapshot for the whole request
+#[derive(Clone)]
+pub struct SharedState(Arc<RwLock<Arc<AppState>>>);
+
+impl SharedState {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self(Arc::new(RwLock::new(state)))
+    }
+
+    pub fn load(&self) -> Arc<AppState> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn store(&self, state: Arc<AppState>) {
+        *self.0.write().unwrap() = state;
+    }
+}
+
+impl FromRef<SharedState> for Arc<AppState> {
+    fn from_ref(shared: &SharedState) -> Self {
+        shared.load()
+    }
+}
+
+// Where config variables are read from: the process environment, overlaid with the
+// `.env` values POST /admin/reload parsed, so a reload never has to call `set_var`
+#[derive(Default)]
+pub struct Env {
+    overrides: HashMap<String, String>,
+}
+
+impl Env {
+    pub fn new(overrides: HashMap<String, String>) -> Self {
+        Self { overrides }
+    }
+
+    // Like `std::env::var`, with the overrides taking precedence
+    pub fn var(&self, key: &str) -> Result<String, std::env::VarError> {
+        match self.overrides.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => std::env::var(key),
+        }
+    }
+}
+
+// Parts of AppState owned by background tasks or holding runtime history (assumed role,
+// latency samples, breaker, audit log writer, in-flight calls, sticky sessions). Built once
+// at startup; POST /admin/reload carries them over instead of building new ones
+#[derive(Clone)]
+pub struct Runtime {
+    pub assume_role: Option<AssumeRoleConfig>,
+    pub assumed_credentials: Arc<RwLock<Option<Credentials>>>,
+    pub latency_stats: Option<Arc<LatencyStats>>,
+    pub request_stats: Arc<RequestStats>,
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
+    pub audit_log: Option<Arc<AuditLogger>>,
+    pub singleflight: Option<Arc<SingleFlight>>,
+    pub sticky_sessions: Option<Arc<StickySessions>>,
+}
+
+impl Runtime {
     pub fn from_env() -> Self {
         Self {
-            client: Client::new(),
-            region: std::env::var("AWS_REGION").expect("AWS_REGION must be set"),
-            access_key: std::env::var("AWS_ACCESS_KEY_ID").expect("AWS_ACCESS_KEY_ID must be set"),
-            secret_key: std::env::var("AWS_SECRET_ACCESS_KEY")
-                .expect("AWS_SECRET_ACCESS_KEY must be set"),
-            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
-            inference_profile: std::env::var("INFERENCE_PROFILE")
-                .unwrap_or_else(|_| "apac.anthropic.claude-sonnet-4-20250514-v1:0".to_string()),
+            assume_role: AssumeRoleConfig::from_env(),
+            assumed_credentials: Arc::new(RwLock::new(None)),
+            latency_stats: LatencyStats::from_env().map(Arc::new),
+            request_stats: Arc::new(RequestStats::default()),
+            circuit_breaker: CircuitBreaker::from_env().map(Arc::new),
+            audit_log: AuditLogger::from_env().map(Arc::new),
+            singleflight: SingleFlight::from_env().map(Arc::new),
+            sticky_sessions: StickySessions::from_env().map(Arc::new),
+        }
+    }
+}
+
+impl AppState {
+    // Startup config; an invalid value stops the proxy
+    pub fn from_env() -> Self {
+        Self::from_vars(&Env::default(), Runtime::from_env()).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Config read from `env` around existing runtime parts. Err names the first
+    // missing or invalid variable
+    pub fn from_vars(env: &Env, runtime: Runtime) -> Result<Self, String> {
+        let region = env
+            .var("AWS_REGION")
+            .map_err(|_| "AWS_REGION must be set")?;
+        let mut inference_profile = env
+            .var("INFERENCE_PROFILE")
+            .unwrap_or_else(|_| "apac.anthropic.claude-sonnet-4-20250514-v1:0".to_string());
+        if env
+            .var("AUTO_PROFILE_PREFIX")
+            .map(|v| v == "true")
+            .unwrap_or(false)
+        {
+            inference_profile = with_profile_prefix(&inference_profile, &region);
+        }
+        if let Some(mismatch) = profile_region_mismatch(&inference_profile, &region) {
+            warn!("🌍 {}", mismatch);
+        }
+
+        let bedrock_endpoint = match env.var("BEDROCK_ENDPOINT_URL") {
+            Ok(url) => {
+                info!("🔗 Bedrock endpoint: {}", url);
+                url.trim_end_matches('/').to_string()
+            }
+            Err(_) => format!("https://bedrock-runtime.{}.amazonaws.com", region),
+        };
+
+        Ok(Self {
+            client: build_client(env)?,
+            region,
+            bedrock_endpoint,
+            access_key: env
+                .var("AWS_ACCESS_KEY_ID")
+                .map_err(|_| "AWS_ACCESS_KEY_ID must be set")?,
+            secret_key: env
+                .var("AWS_SECRET_ACCESS_KEY")
+                .map_err(|_| "AWS_SECRET_ACCESS_KEY must be set")?,
+            session_token: env.var("AWS_SESSION_TOKEN").ok(),
+            default_model_name: env
+                .var("DEFAULT_MODEL_NAME")
+                .unwrap_or_else(|_| model_name_from_id(&inference_profile)),
+            inference_profile,
+            truncate_context_tokens: env
+                .var("TRUNCATE_CONTEXT_TOKENS")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            default_max_tokens: env
+                .var("DEFAULT_MAX_TOKENS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(512),
+            model_context_window: env
+                .var("MODEL_CONTEXT_WINDOW")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            response_content_parts: env
+                .var("RESPONSE_CONTENT_PARTS")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            circuit_breaker: runtime.circuit_breaker,
+            emit_empty_stream_chunk: env
+                .var("EMIT_EMPTY_STREAM_CHUNK")
+                .map(|v| v != "false")
+                .unwrap_or(true),
+            sse_single_line_data: env
+                .var("SSE_SINGLE_LINE_DATA")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            assume_role: runtime.assume_role,
+            assumed_credentials: runtime.assumed_credentials,
+            audit_log: runtime.audit_log,
+            connect_retry_attempts: env
+                .var("CONNECT_RETRY_ATTEMPTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(2),
+            allowed_model_ids: env
+                .var("ALLOWED_MODEL_IDS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|id| id.trim().to_string())
+                        .filter(|id| !id.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            sse_keepalive_secs: env
+                .var("SSE_KEEPALIVE_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|secs| *secs > 0)
+                .unwrap_or(15),
+            sse_keepalive_text: env.var("SSE_KEEPALIVE_TEXT").unwrap_or_default(),
+            max_tool_turns: env.var("MAX_TOOL_TURNS").ok().and_then(|v| v.parse().ok()),
+            reject_tool_turns: env
+                .var("MAX_TOOL_TURNS_MODE")
+                .map(|v| v == "reject")
+                .unwrap_or(false),
+            price_table: PriceTable::from_env(env).map(Arc::new),
+            latency_stats: runtime.latency_stats,
+            request_stats: runtime.request_stats,
+            request_timeout_ms: env
+                .var("REQUEST_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|ms| *ms > 0),
+            max_request_timeout_ms: env
+                .var("MAX_REQUEST_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(600_000),
+            stream_ttfb_timeout_ms: env
+                .var("STREAM_TTFB_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|ms| *ms > 0),
+            stream_retry_attempts: env
+                .var("STREAM_RETRY_ATTEMPTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            estimate_missing_usage: env
+                .var("ESTIMATE_MISSING_USAGE")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            use_converse_api: env
+                .var("USE_CONVERSE_API")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            response_excluded_fields: excluded_response_fields(env),
+            batch_concurrency: env
+                .var("BATCH_CONCURRENCY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(4),
+            enable_http2: env
+                .var("ENABLE_HTTP2")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            http2_keepalive_secs: env
+                .var("HTTP2_KEEPALIVE_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(20),
+            created_from_upstream_date: env
+                .var("CREATED_FROM_UPSTREAM_DATE")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            clock: env
+                .var("FIXED_CREATED_TIMESTAMP")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(Clock::Fixed)
+                .unwrap_or_default(),
+            moderation: Moderation::from_env(env)?.map(Arc::new),
+            reasoning_budget_low: env
+                .var("REASONING_BUDGET_LOW")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1024),
+            reasoning_budget_medium: env
+                .var("REASONING_BUDGET_MEDIUM")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(4096),
+            reasoning_budget_high: env
+                .var("REASONING_BUDGET_HIGH")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(16384),
+            forward_headers: forward_headers(env),
+            validate_bedrock_responses: env
+                .var("VALIDATE_BEDROCK_RESPONSES")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            record_dir: recording::dir_from_env(env, "RECORD_DIR")?,
+            replay_dir: recording::dir_from_env(env, "REPLAY_DIR")?,
+            credential_sets: CredentialSets::from_env(env)?.map(Arc::new),
+            expose_bedrock_stop_reason: env
+                .var("EXPOSE_BEDROCK_STOP_REASON")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            singleflight: runtime.singleflight,
+            json_retry_max_tokens: env
+                .var("JSON_RETRY_MAX_TOKENS")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            enforce_max_tokens_ceiling: env
+                .var("MAX_TOKENS_CEILING")
+                .map(|v| v == "clamp" || v == "beta")
+                .unwrap_or(false),
+            extended_output_beta: env
+                .var("MAX_TOKENS_CEILING")
+                .map(|v| v == "beta")
+                .unwrap_or(false),
+            stream_flush_on_sentence: env
+                .var("STREAM_FLUSH_ON_SENTENCE")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            validate_tool_arguments: env
+                .var("VALIDATE_TOOL_ARGUMENTS")
+                .map(|v| v == "warn" || v == "error")
+                .unwrap_or(false),
+            tool_arguments_error_chunk: env
+                .var("VALIDATE_TOOL_ARGUMENTS")
+                .map(|v| v == "error")
+                .unwrap_or(false),
+            user_prompt_prefix: env.var("USER_PROMPT_PREFIX").ok().filter(|v| !v.is_empty()),
+            user_prompt_suffix: env.var("USER_PROMPT_SUFFIX").ok().filter(|v| !v.is_empty()),
+            allow_debug_trace: env
+                .var("ALLOW_DEBUG_TRACE")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            remap_overloaded_to_429: env
+                .var("REMAP_OVERLOADED_TO_429")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            overloaded_retry_after_secs: env
+                .var("OVERLOADED_RETRY_AFTER_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5),
+            admin_token: env.var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+            api_keys: ApiKeys::from_env(env)?.map(Arc::new),
+            log_body_max_bytes: env
+                .var("LOG_BODY_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|max| *max > 0),
+            strict_sampling_params: env
+                .var("STRICT_SAMPLING_PARAMS")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            accept_json_overrides_stream: env
+                .var("ACCEPT_JSON_OVERRIDES_STREAM")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            inference_tags: match env.var("INFERENCE_TAGS") {
+                Ok(v) => parse_tags(&v).map_err(|e| format!("INFERENCE_TAGS: {}", e))?,
+                Err(_) => Vec::new(),
+            },
+            validate_profile_region: env
+                .var("VALIDATE_PROFILE_REGION")
+                .map(|v| v != "false")
+                .unwrap_or(true),
+            max_images_per_request: env
+                .var("MAX_IMAGES_PER_REQUEST")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(20),
+            trust_forwarded_for: env
+                .var("TRUST_FORWARDED_FOR")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            // Anthropic models on Bedrock accept up to 8191 stop sequences
+            max_stop_sequences: env
+                .var("MAX_STOP_SEQUENCES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(8191),
+            truncate_stop_sequences: env
+                .var("STOP_SEQUENCES_MODE")
+                .map(|v| v == "truncate")
+                .unwrap_or(false),
+            reject_logprobs: env
+                .var("LOGPROBS_UNSUPPORTED_BEHAVIOR")
+                .map(|v| v == "error")
+                .unwrap_or(false),
+            sticky_sessions: runtime.sticky_sessions,
+        })
+    }
+
+    // Runtime parts to carry over into the config re-read by POST /admin/reload
+    pub fn runtime(&self) -> Runtime {
+        Runtime {
+            assume_role: self.assume_role.clone(),
+            assumed_credentials: self.assumed_credentials.clone(),
+            latency_stats: self.latency_stats.clone(),
+            request_stats: self.request_stats.clone(),
+            circuit_breaker: self.circuit_breaker.clone(),
+            audit_log: self.audit_log.clone(),
+            singleflight: self.singleflight.clone(),
+            sticky_sessions: self.sticky_sessions.clone(),
+        }
+    }
+
+    // Keep-alive comment sent on idle SSE streams
+    pub fn sse_keep_alive(&self) -> KeepAlive {
+        KeepAlive::new()
+            .interval(Duration::from_secs(self.sse_keepalive_secs))
+            .text(self.sse_keepalive_text.as_str())
+    }
+
+    // Static credentials from the environment
+    pub fn base_credentials(&self) -> Credentials {
+        Credentials::new(
+            &self.access_key,
+            &self.secret_key,
+            self.session_token.clone(),
+            None,
+            "hardcoded-credentials",
+        )
+    }
+
+    // Bedrock runtime URL for `action` (e.g. "invoke") on a model or inference profile
+    pub fn model_url(&self, model_id: &str, action: &str) -> String {
+        format!("{}/model/{}/{}", self.bedrock_endpoint, model_id, action)
+    }
+
+    // Credentials used to sign Bedrock requests (assumed role when configured)
+    pub fn credentials(&self) -> Credentials {
+        match self.assumed_credentials.read().unwrap().as_ref() {
+            Some(creds) => creds.clone(),
+            None => self.base_credentials(),
+        }
+    }
+
+    // Credentials for a specific model: its MODEL_CREDENTIALS set, if any
+    pub fn credentials_for_model(&self, model_id: &str) -> Credentials {
+        self.credential_sets
+            .as_ref()
+            .and_then(|sets| sets.for_model(model_id))
+            .unwrap_or_else(|| self.credentials())
+    }
+
+    // False while the upstream circuit breaker is open
+    pub fn upstream_available(&self) -> bool {
+        self.circuit_breaker
+            .as_ref()
+            .is_none_or(|b| b.allow_request())
+    }
+
+    // Feed an upstream outcome (5xx/connection failure = unhealthy) to the breaker
+    pub fn record_upstream(&self, healthy: bool) {
+        if let Some(breaker) = &self.circuit_breaker {
+            if healthy {
+                breaker.record_success();
+            } else {
+                breaker.record_failure();
+            }
+        }
+    }
+
+    pub fn record_latency(&self, latency: std::time::Duration) {
+        if let Some(stats) = &self.latency_stats {
+            stats.record(latency);
+        }
+    }
+
+    // Drop RESPONSE_FIELDS exclusions from a serialized completion or chunk
+    pub fn filter_response_fields(&self, response: &mut Value) {
+        for field in &self.response_excluded_fields {
+            if field == "logprobs" {
+                let choices = response.get_mut("choices").and_then(|c| c.as_array_mut());
+                for choice in choices.into_iter().flatten() {
+                    if let Some(choice) = choice.as_object_mut() {
+                        choice.remove("logprobs");
+                    }
+                }
+            } else if let Some(response) = response.as_object_mut() {
+                response.remove(field);
+            }
+        }
+    }
+
+    // Approximate request cost from MODEL_PRICES, if the model is priced
+    pub fn estimate_cost(
+        &self,
+        model_id: &str,
+        prompt_tokens: i32,
+        completion_tokens: i32,
+    ) -> Option<f64> {
+        self.price_table
+            .as_ref()?
+            .estimate(model_id, prompt_tokens, completion_tokens)
+    }
+}
+
+// Bedrock requestMetadata limits
+const MAX_TAGS: usize = 16;
+const MAX_TAG_LEN: usize = 256;
+
+// "team=search,env=prod" → [("team", "search"), ("env", "prod")], used for
+// INFERENCE_TAGS and X-Bedrock-Request-Tags. Err describes the first bad pair
+pub fn parse_tags(value: &str) -> Result<Vec<(String, String)>, String> {
+    let mut tags = Vec::new();
+    for pair in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, tag_value) = pair
+            .split_once('=')
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .filter(|(k, _)| !k.is_empty())
+            .ok_or_else(|| format!("Invalid tag '{}': expected key=value", pair))?;
+        if key.len() > MAX_TAG_LEN || tag_value.len() > MAX_TAG_LEN {
+            return Err(format!(
+                "Tag '{}' is too long: keys and values are limited to {} characters",
+                key, MAX_TAG_LEN
+            ));
+        }
+        tags.push((key.to_string(), tag_value.to_string()));
+    }
+    if tags.len() > MAX_TAGS {
+        return Err(format!(
+            "Too many tags: {} (at most {})",
+            tags.len(),
+            MAX_TAGS
+        ));
+    }
+    Ok(tags)
+}
+
+// Geography prefixes of cross-region inference profile IDs
+const PROFILE_PREFIXES: [&str; 8] = ["us", "us-gov", "eu", "apac", "jp", "au", "ca", "global"];
+
+// AUTO_PROFILE_PREFIX: turn a bare model ID into the cross-region inference profile
+// for the region's geography ("anthropic.claude-…" in eu-west-1 → "eu.anthropic.claude-…").
+// ARNs and IDs that already carry a geography prefix are left alone
+fn with_profile_prefix(profile: &str, region: &str) -> String {
+    let first_segment = profile.split('.').next().unwrap_or_default();
+    if profile.starts_with("arn:") || PROFILE_PREFIXES.contains(&first_segment) {
+        return profile.to_string();
+    }
+
+    let geography = if region.starts_with("us-gov-") {
+        "us-gov"
+    } else if region.starts_with("us-") {
+        "us"
+    } else if region.starts_with("eu-") {
+        "eu"
+    } else if region.starts_with("ap-") {
+        "apac"
+    } else {
+        warn!(
+            "⚠️ AUTO_PROFILE_PREFIX: no inference profile geography for region {}, keeping {}",
+            region, profile
+        );
+        return profile.to_string();
+    };
+
+    let corrected = format!("{}.{}", geography, profile);
+    info!(
+        "🌍 AUTO_PROFILE_PREFIX: using inference profile {} instead of {}",
+        corrected, profile
+    );
+    corrected
+}
+
+// Whether a cross-region profile's geography can be called from `region`
+fn geography_serves_region(geography: &str, region: &str) -> bool {
+    match geography {
+        "us" => region.starts_with("us-") && !region.starts_with("us-gov-"),
+        "us-gov" => region.starts_with("us-gov-"),
+        "eu" => region.starts_with("eu-"),
+        "apac" => region.starts_with("ap-"),
+        "jp" => matches!(region, "ap-northeast-1" | "ap-northeast-3"),
+        "au" => matches!(region, "ap-southeast-2" | "ap-southeast-4"),
+        "ca" => region.starts_with("ca-"),
+        _ => true,
+    }
+}
+
+// Explain why `model_id` can't be invoked through the `region` endpoint, if it
+// clearly can't: a geography prefix for another geography, or an ARN in another region
+pub fn profile_region_mismatch(model_id: &str, region: &str) -> Option<String> {
+    if let Some(arn_region) = model_id
+        .strip_prefix("arn:")
+        .and_then(|arn| arn.split(':').nth(2))
+    {
+        if !arn_region.is_empty() && arn_region != region {
+            return Some(format!(
+                "Model ARN '{}' is in region {}, but AWS_REGION is {}. Use an ARN from {} or set AWS_REGION={}",
+                model_id, arn_region, region, region, arn_region
+            ));
+        }
+        return None;
+    }
+
+    let geography = model_id.split('.').next().unwrap_or_default();
+    if PROFILE_PREFIXES.contains(&geography) && !geography_serves_region(geography, region) {
+        return Some(format!(
+            "Inference profile '{}' is for the '{}' geography and can't be called from AWS_REGION={}. \
+             Use the profile prefix for {} (or AUTO_PROFILE_PREFIX=true with the bare model ID), or set AWS_REGION to a region in the '{}' geography",
+            model_id, geography, region, region, geography
+        ));
+    }
+    None
+}
+
+// Client-facing model name for a Bedrock model / inference profile ID:
+// "apac.anthropic.claude-sonnet-4-20250514-v1:0" → "claude-sonnet-4-20250514"
+fn model_name_from_id(model_id: &str) -> String {
+    let name = model_id.rsplit('.').next().unwrap_or(model_id);
+    match name.rsplit_once("-v") {
+        Some((base, version)) if version.chars().all(|c| c.is_ascii_digit() || c == ':') => {
+            base.to_string()
         }
+        _ => name.to_string(),
     }
-}
\ No newline at end of file
+}
+
+// Optional chat completion fields that RESPONSE_FIELDS may drop
+const OPTIONAL_RESPONSE_FIELDS: [&str; 4] = ["usage", "created", "model", "logprobs"];
+
+// RESPONSE_FIELDS="-usage,-logprobs": fields prefixed with `-` are dropped from responses
+fn excluded_response_fields(env: &Env) -> Vec<String> {
+    let Ok(raw) = env.var("RESPONSE_FIELDS") else {
+        return Vec::new();
+    };
+
+    raw.split(',')
+        .filter_map(|f| f.trim().strip_prefix('-'))
+        .filter(|f| {
+            let optional = OPTIONAL_RESPONSE_FIELDS.contains(f);
+            if !optional {
+                warn!(
+                    "⚠️ RESPONSE_FIELDS: '{}' is not an optional field, keeping it",
+                    f
+                );
+            }
+            optional
+        })
+        .map(|f| f.to_string())
+        .collect()
+}
+
+// Headers the proxy sets or signs itself; forwarding a client value would
+// break SigV4 or leak the client's own credentials upstream
+const PROTECTED_HEADERS: [&str; 9] = [
+    "host",
+    "authorization",
+    "content-type",
+    "content-length",
+    "accept",
+    "x-amz-date",
+    "x-amz-security-token",
+    "x-amz-content-sha256",
+    "x-amzn-bedrock-accept",
+];
+
+// FORWARD_HEADERS="anthropic-beta,x-custom": allowlist of inbound headers to pass through
+fn forward_headers(env: &Env) -> Vec<String> {
+    let Ok(raw) = env.var("FORWARD_HEADERS") else {
+        return Vec::new();
+    };
+
+    raw.split(',')
+        .map(|h| h.trim().to_lowercase())
+        .filter(|h| !h.is_empty())
+        .filter(|h| {
+            let protected = PROTECTED_HEADERS.contains(&h.as_str());
+            if protected {
+                warn!(
+                    "⚠️ FORWARD_HEADERS: '{}' cannot be forwarded, ignoring it",
+                    h
+                );
+            }
+            !protected
+        })
+        .collect()
+}
+
+// First set of HTTPS_PROXY / HTTP_PROXY (either case) wins
+fn proxy_from_env(env: &Env) -> Option<String> {
+    ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"]
+        .iter()
+        .find_map(|key| env.var(key).ok().filter(|v| !v.is_empty()))
+}
+
+// Hide any password embedded in the proxy URL before logging it
+fn redact_proxy_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut parsed) if parsed.password().is_some() => {
+            let _ = parsed.set_password(Some("***"));
+            parsed.to_string()
+        }
+        _ => url.to_string(),
+    }
+}
+
+fn build_client(env: &Env) -> Result<Client, String> {
+    let mut builder = Client::builder().no_proxy();
+
+    match proxy_from_env(env) {
+        Some(url) => {
+            let no_proxy = env
+                .var("NO_PROXY")
+                .or_else(|_| env.var("no_proxy"))
+                .unwrap_or_default();
+            let proxy = reqwest::Proxy::all(&url)
+                .map_err(|_| "HTTPS_PROXY/HTTP_PROXY must be a valid URL")?
+                .no_proxy(reqwest::NoProxy::from_string(&no_proxy));
+            builder = builder.proxy(proxy);
+            info!(
+                "🌐 Upstream proxy: {} (NO_PROXY: {})",
+                redact_proxy_url(&url),
+                no_proxy
+            );
+        }
+        None => info!("🌐 Upstream proxy: none"),
+    }
+
+    // Only for testing against mock Bedrock endpoints with self-signed certificates
+    if env
+        .var("DANGER_ACCEPT_INVALID_CERTS")
+        .map(|v| v == "true")
+        .unwrap_or(false)
+    {
+        warn!("⚠️ DANGER_ACCEPT_INVALID_CERTS=true: upstream TLS certificates are NOT verified. Never use this outside tests");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))
+}
+
+// Held by tests that set environment variables, so no other test's `test_state()` sees them
+#[cfg(test)]
+pub static TEST_ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// AppState for unit tests, built from fixed AWS example credentials. Every test sets the
+// same values, so tests running in parallel agree on them; adjust fields on the result
+#[cfg(test)]
+pub fn test_state() -> AppState {
+    test_state_with(&[]).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// `test_state()` with `vars` read as config on top of it, for tests of env parsing
+#[cfg(test)]
+pub fn test_state_with(vars: &[(&str, &str)]) -> Result<AppState, String> {
+    let _env = TEST_ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    std::env::set_var("AWS_REGION", "us-east-1");
+    std::env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    std::env::set_var(
+        "AWS_SECRET_ACCESS_KEY",
+        "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+    );
+    std::env::set_var(
+        "INFERENCE_PROFILE",
+        "anthropic.claude-3-haiku-20240307-v1:0",
+    );
+    let overrides = vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    AppState::from_vars(&Env::new(overrides), Runtime::from_env())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BARE: &str = "anthropic.claude-sonnet-4-20250514-v1:0";
+
+    fn profile_for(region: &str, profile: &str, auto_prefix: &str) -> String {
+        test_state_with(&[
+            ("AWS_REGION", region),
+            ("INFERENCE_PROFILE", profile),
+            ("AUTO_PROFILE_PREFIX", auto_prefix),
+        ])
+        .ok()
+        .unwrap()
+        .inference_profile
+    }
+
+    #[test]
+    fn auto_profile_prefix_follows_the_region_geography() {
+        for (region, expected) in [
+            ("us-east-1", "us."),
+            ("us-west-2", "us."),
+            ("us-gov-west-1", "us-gov."),
+            ("eu-central-1", "eu."),
+            ("eu-west-3", "eu."),
+            ("ap-southeast-1", "apac."),
+            ("ap-northeast-1", "apac."),
+        ] {
+            assert_eq!(
+                profile_for(region, BARE, "true"),
+                format!("{}{}", expected, BARE),
+                "{}",
+                region
+            );
+        }
+    }
+
+    #[test]
+    fn auto_profile_prefix_leaves_other_profiles_alone() {
+        // Off by default
+        assert_eq!(profile_for("eu-west-1", BARE, "false"), BARE);
+        // No geography for the region
+        assert_eq!(profile_for("sa-east-1", BARE, "true"), BARE);
+        // Already prefixed, or an ARN
+        let prefixed = format!("apac.{}", BARE);
+        assert_eq!(profile_for("eu-west-1", &prefixed, "true"), prefixed);
+        let arn = "arn:aws:bedrock:eu-west-1:123456789012:application-inference-profile/abc123";
+        assert_eq!(profile_for("eu-west-1", arn, "true"), arn);
+    }
+
+    // HTTPS server on 127.0.0.1 with the self-signed certificate in tests/fixtures/tls,
+    // answering every request with "ok"
+    async fn self_signed_server() -> std::net::SocketAddr {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+        use tokio_rustls::rustls::{crypto::ring, ServerConfig};
+
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+        let cert = CertificateDer::from_pem_file(dir.join("cert.pem")).unwrap();
+        let key = PrivateKeyDer::from_pem_file(dir.join("key.pem")).unwrap();
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key)
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut tls) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let mut request = [0; 4096];
+                    let _ = tls.read(&mut request).await;
+                    let response =
+                        "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok";
+                    let _ = tls.write_all(response.as_bytes()).await;
+                    let _ = tls.shutdown().await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn invalid_certs_are_accepted_only_when_enabled() {
+        let addr = self_signed_server().await;
+        let url = format!("https://{}/model/test/invoke", addr);
+
+        let state = test_state_with(&[("DANGER_ACCEPT_INVALID_CERTS", "true")])
+            .ok()
+            .unwrap();
+        let response = state.client.post(&url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        // Verification stays on by default
+        let error = test_state().client.post(&url).send().await.err().unwrap();
+        assert!(error.is_connect(), "{:?}", error);
+    }
+}
@@ -0,0 +1,134 @@
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Process-wide request counters for GET /admin/stats. Totals, error statuses and
+// in-flight requests are counted by `stats_middleware` on the Bedrock routes;
+// handlers add the per-model count once they've resolved the model, and each
+// response's token usage for the caller's API key (when API_KEYS is set)
+pub struct RequestStats {
+    started: Instant,
+    total: AtomicU64,
+    in_flight: AtomicU64,
+    per_model: Mutex<BTreeMap<String, u64>>,
+    errors_by_status: Mutex<BTreeMap<u16, u64>>,
+    // API key name -> (prompt tokens, completion tokens)
+    tokens_by_key: Mutex<BTreeMap<String, (u64, u64)>>,
+}
+
+impl Default for RequestStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            total: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            per_model: Mutex::new(BTreeMap::new()),
+            errors_by_status: Mutex::new(BTreeMap::new()),
+            tokens_by_key: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl RequestStats {
+    // Count a new request; it stays in flight until the guard is dropped
+    pub fn begin(self: &Arc<Self>) -> InFlight {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    pub fn record_model(&self, model_id: &str) {
+        *self
+            .per_model
+            .lock()
+            .unwrap()
+            .entry(model_id.to_string())
+            .or_default() += 1;
+    }
+
+    // Count a 4xx/5xx response; other statuses are ignored
+    pub fn record_status(&self, status: u16) {
+        if status >= 400 {
+            *self
+                .errors_by_status
+                .lock()
+                .unwrap()
+                .entry(status)
+                .or_default() += 1;
+        }
+    }
+
+    pub fn record_tokens(&self, key_name: &str, prompt_tokens: i32, completion_tokens: i32) {
+        let mut tokens_by_key = self.tokens_by_key.lock().unwrap();
+        let (prompt, completion) = tokens_by_key.entry(key_name.to_string()).or_default();
+        *prompt += prompt_tokens.max(0) as u64;
+        *completion += completion_tokens.max(0) as u64;
+    }
+
+    pub fn snapshot(&self) -> Value {
+        let errors_by_status = self.errors_by_status.lock().unwrap();
+        let errors: BTreeMap<String, u64> = errors_by_status
+            .iter()
+            .map(|(status, count)| (status.to_string(), *count))
+            .collect();
+        let tokens_by_key: BTreeMap<String, Value> = self
+            .tokens_by_key
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, (prompt, completion))| {
+                let usage = json!({
+                    "prompt_tokens": prompt,
+                    "completion_tokens": completion,
+                    "total_tokens": prompt + completion,
+                });
+                (name.clone(), usage)
+            })
+            .collect();
+        json!({
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "total_requests": self.total.load(Ordering::Relaxed),
+            "in_flight": self.in_flight.load(Ordering::Relaxed),
+            "requests_by_model": *self.per_model.lock().unwrap(),
+            "total_errors": errors_by_status.values().sum::<u64>(),
+            "errors_by_status": errors,
+            "tokens_by_api_key": tokens_by_key,
+        })
+    }
+}
+
+// In-flight marker for one request, held until its response body has been sent
+pub struct InFlight(Arc<RequestStats>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulates_tokens_per_api_key() {
+        let stats = RequestStats::default();
+        stats.record_tokens("team-a", 10, 5);
+        stats.record_tokens("team-a", 7, 3);
+        stats.record_tokens("team-b", 1, 1);
+        // Negative counts add nothing
+        stats.record_tokens("team-b", -1, -1);
+
+        let tokens = &stats.snapshot()["tokens_by_api_key"];
+        assert_eq!(
+            tokens["team-a"],
+            json!({"prompt_tokens": 17, "completion_tokens": 8, "total_tokens": 25})
+        );
+        assert_eq!(
+            tokens["team-b"],
+            json!({"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2})
+        );
+    }
+}
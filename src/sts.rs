@@ -0,0 +1,227 @@
+use aws_credential_types::Credentials;
+use aws_sdk_sts::config::{BehaviorVersion, Region};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+
+use crate::state::AppState;
+
+// Refresh this long before the assumed credentials expire
+const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub struct AssumeRoleConfig {
+    pub role_arn: String,
+    pub session_name: String,
+    pub external_id: Option<String>,
+    // STS endpoint override, e.g. a VPC interface endpoint
+    pub endpoint_url: Option<String>,
+}
+
+impl AssumeRoleConfig {
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            role_arn: std::env::var("ASSUME_ROLE_ARN").ok()?,
+            session_name: std::env::var("ASSUME_ROLE_SESSION_NAME")
+                .unwrap_or_else(|_| "bedrock-proxy".to_string()),
+            external_id: std::env::var("ASSUME_ROLE_EXTERNAL_ID").ok(),
+            endpoint_url: std::env::var("ASSUME_ROLE_STS_ENDPOINT").ok(),
+        })
+    }
+}
+
+// Call STS AssumeRole in the configured region using the base credentials
+async fn assume_role(
+    state: &AppState,
+    config: &AssumeRoleConfig,
+) -> Result<(Credentials, SystemTime), Box<dyn std::error::Error + Send + Sync>> {
+    let mut sts_config = aws_sdk_sts::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new(state.region.clone()))
+        .credentials_provider(state.base_credentials());
+    sts_config.set_endpoint_url(config.endpoint_url.clone());
+
+    let output = aws_sdk_sts::Client::from_conf(sts_config.build())
+        .assume_role()
+        .role_arn(&config.role_arn)
+        .role_session_name(&config.session_name)
+        .set_external_id(config.external_id.clone())
+        .send()
+        .await?;
+
+    let creds = output
+        .credentials()
+        .ok_or("AssumeRole response did not include credentials")?;
+    let expiry = SystemTime::try_from(*creds.expiration())?;
+
+    Ok((
+        Credentials::new(
+            creds.access_key_id(),
+            creds.secret_access_key(),
+            Some(creds.session_token().to_string()),
+            Some(expiry),
+            "sts-assume-role",
+        ),
+        expiry,
+    ))
+}
+
+// Assume the role and sign with the new credentials from now on; returns their expiry
+async fn refresh(
+    state: &AppState,
+    config: &AssumeRoleConfig,
+) -> Result<SystemTime, Box<dyn std::error::Error + Send + Sync>> {
+    let (creds, expiry) = assume_role(state, config).await?;
+    *state.assumed_credentials.write().unwrap() = Some(creds);
+    Ok(expiry)
+}
+
+// How long to wait before refreshing credentials that expire at `expiry`
+fn refresh_delay(expiry: SystemTime, now: SystemTime) -> Duration {
+    let wait = expiry
+        .duration_since(now)
+        .unwrap_or_default()
+        .saturating_sub(REFRESH_MARGIN);
+    wait.max(RETRY_DELAY)
+}
+
+// Assume the role once (failing startup on error), then keep the
+// credentials fresh from a background task
+pub async fn start_credential_refresh(state: Arc<AppState>) {
+    let Some(config) = state.assume_role.clone() else {
+        return;
+    };
+
+    let mut expiry = refresh(&state, &config)
+        .await
+        .expect("ASSUME_ROLE_ARN is set but AssumeRole failed");
+    info!("🔑 Assumed role {}", config.role_arn);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(refresh_delay(expiry, SystemTime::now())).await;
+
+            match refresh(&state, &config).await {
+                Ok(new_expiry) => {
+                    expiry = new_expiry;
+                    info!("🔑 Refreshed assumed role credentials");
+                }
+                Err(e) => error!("❌ Failed to refresh assumed role credentials: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::test_state;
+    use std::sync::Mutex;
+
+    type SeenForms = Arc<Mutex<Vec<String>>>;
+
+    // STS mock answering the Nth AssumeRole call with access key ASIAMOCKN
+    async fn mock_sts() -> (String, SeenForms) {
+        let seen = SeenForms::default();
+        let captured = seen.clone();
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post(move |form: String| {
+                let call = {
+                    let mut seen = captured.lock().unwrap();
+                    seen.push(form);
+                    seen.len()
+                };
+                async move {
+                    (
+                        [("content-type", "text/xml")],
+                        format!(
+                            r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <Credentials>
+      <AccessKeyId>ASIAMOCK{call}</AccessKeyId>
+      <SecretAccessKey>secret{call}</SecretAccessKey>
+      <SessionToken>token{call}</SessionToken>
+      <Expiration>2099-01-0{call}T00:00:00Z</Expiration>
+    </Credentials>
+    <AssumedRoleUser>
+      <AssumedRoleId>AROAMOCK:bedrock-proxy</AssumedRoleId>
+      <Arn>arn:aws:sts::123456789012:assumed-role/bedrock-invoke/bedrock-proxy</Arn>
+    </AssumedRoleUser>
+  </AssumeRoleResult>
+  <ResponseMetadata><RequestId>c6104cbe-af31-11e0-8154-cbc7ccf896c7</RequestId></ResponseMetadata>
+</AssumeRoleResponse>"#
+                        ),
+                    )
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        (url, seen)
+    }
+
+    fn role_config(endpoint_url: String) -> AssumeRoleConfig {
+        AssumeRoleConfig {
+            role_arn: "arn:aws:iam::123456789012:role/bedrock-invoke".to_string(),
+            session_name: "bedrock-proxy".to_string(),
+            external_id: Some("ext-42".to_string()),
+            endpoint_url: Some(endpoint_url),
+        }
+    }
+
+    #[tokio::test]
+    async fn assumes_the_role_and_refreshes_credentials() {
+        let (url, seen) = mock_sts().await;
+        let mut state = test_state();
+        state.assume_role = Some(role_config(url));
+        let state = Arc::new(state);
+        let config = state.assume_role.clone().unwrap();
+
+        start_credential_refresh(state.clone()).await;
+        let creds = state.credentials();
+        assert_eq!(creds.access_key_id(), "ASIAMOCK1");
+        assert_eq!(creds.secret_access_key(), "secret1");
+        assert_eq!(creds.session_token(), Some("token1"));
+        let form = seen.lock().unwrap()[0].clone();
+        assert!(form.contains("Action=AssumeRole"), "{}", form);
+        assert!(form.contains("RoleSessionName=bedrock-proxy"), "{}", form);
+        assert!(form.contains("ExternalId=ext-42"), "{}", form);
+
+        // The background task's refresh step swaps in the new credentials
+        let expiry = refresh(&state, &config).await.unwrap();
+        assert_eq!(state.credentials().access_key_id(), "ASIAMOCK2");
+        assert_eq!(state.credentials().session_token(), Some("token2"));
+        assert_eq!(
+            expiry,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(4_070_995_200)
+        );
+        // Base credentials are kept for calling STS itself
+        assert_eq!(state.base_credentials().access_key_id(), "AKIDEXAMPLE");
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_keeps_current_credentials() {
+        let (url, _) = mock_sts().await;
+        let mut state = test_state();
+        state.assume_role = Some(role_config(url));
+        start_credential_refresh(Arc::new(state.clone())).await;
+        assert_eq!(state.credentials().access_key_id(), "ASIAMOCK1");
+
+        let unreachable = role_config("http://127.0.0.1:1".to_string());
+        assert!(refresh(&state, &unreachable).await.is_err());
+        assert_eq!(state.credentials().access_key_id(), "ASIAMOCK1");
+    }
+
+    #[test]
+    fn refreshes_ahead_of_expiry() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let hour = Duration::from_secs(3600);
+        assert_eq!(refresh_delay(now + hour, now), hour - REFRESH_MARGIN);
+        // Expired or nearly expired credentials are retried after a short pause
+        assert_eq!(refresh_delay(now + REFRESH_MARGIN, now), RETRY_DELAY);
+        assert_eq!(refresh_delay(now - hour, now), RETRY_DELAY);
+    }
+}
@@ -1,5 +1,10 @@
-use serde::{Deserialize, Serialize};
+use base64::Engine;
+use serde::{de, Deserialize, Deserializer, Serialize};
 use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::state::AppState;
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct OpenAIMessage {
@@ -7,6 +12,8 @@ pub struct OpenAIMessage {
     pub content: Option<Value>, // Can be string, array, or object
     pub tool_calls: Option<Vec<ToolCall>>,
     pub tool_call_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -35,31 +42,133 @@ pub struct FunctionDefinition {
     pub parameters: Option<Value>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct OpenAIRequest {
     pub messages: Vec<OpenAIMessage>,
     pub model: Option<String>,
+    #[serde(default, deserialize_with = "whole_number")]
     pub max_tokens: Option<u32>,
     pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub stop: Option<Value>, // Single string or array of strings
     pub stream: Option<bool>,
-    #[allow(dead_code)]
+    pub stream_options: Option<StreamOptions>,
+    pub user: Option<String>,
+    pub metadata: Option<Value>,
     pub tools: Option<Vec<Tool>>,
-    #[allow(dead_code)]
     pub tool_choice: Option<Value>,
+    pub logprobs: Option<bool>,
+    #[serde(default, deserialize_with = "whole_number")]
+    pub top_logprobs: Option<u32>,
+    pub reasoning_effort: Option<String>,
+    pub response_format: Option<Value>,
+    // Number of choices; above 1 only with stream: true
+    #[serde(default, deserialize_with = "whole_number")]
+    pub n: Option<u32>,
+}
+
+// Integer request fields also accept whole-number floats such as `1024.0`, which some
+// clients send for every numeric param. Fractional or negative values are still rejected
+fn whole_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    let Some(value) = Option::<f64>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    if value.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&value) {
+        return Err(de::Error::custom(format!(
+            "expected a non-negative whole number, got {}",
+            value
+        )));
+    }
+    Ok(Some(value as u32))
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StreamOptions {
+    pub include_usage: Option<bool>,
 }
 
 #[derive(Serialize)]
 pub struct OpenAIChoice {
     pub index: i32,
     pub message: OpenAIMessage,
+    // Token logprobs from families that return them (`ModelFamily::returns_logprobs`),
+    // otherwise null
+    pub logprobs: Option<Value>,
     pub finish_reason: String,
+    // Untranslated Bedrock stop reason, only with EXPOSE_BEDROCK_STOP_REASON=true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x_bedrock_stop_reason: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct OpenAIUsage {
     pub prompt_tokens: i32,
     pub completion_tokens: i32,
     pub total_tokens: i32,
+    // Proxy extension, set on the streaming usage chunk when MODEL_PRICES covers the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost_usd: Option<f64>,
+    // Set when Bedrock reports prompt cache reads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+    // Set when the response contains extended thinking
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PromptTokensDetails {
+    // Prompt tokens served from the prompt cache (included in prompt_tokens)
+    pub cached_tokens: i32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CompletionTokensDetails {
+    // Bedrock counts thinking as output without breaking it out, so this is estimated
+    // from the thinking text (~4 chars per token), capped at completion_tokens
+    pub reasoning_tokens: i32,
+}
+
+impl OpenAIUsage {
+    fn new(
+        prompt_tokens: i32,
+        completion_tokens: i32,
+        cached_tokens: Option<i32>,
+        reasoning_chars: usize,
+    ) -> Self {
+        OpenAIUsage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            estimated_cost_usd: None,
+            prompt_tokens_details: cached_tokens
+                .map(|cached_tokens| PromptTokensDetails { cached_tokens }),
+            completion_tokens_details: (reasoning_chars > 0).then(|| CompletionTokensDetails {
+                reasoning_tokens: (reasoning_chars.div_ceil(4) as i32).min(completion_tokens),
+            }),
+        }
+    }
+}
+
+// Anthropic and Converse report prompt cache reads and writes apart from the uncached
+// input count; OpenAI's prompt_tokens includes them. Returns the OpenAI prompt_tokens and
+// the cache read count, when reported
+fn prompt_usage(
+    usage: Option<&Value>,
+    input_key: &str,
+    cache_read_key: &str,
+    cache_write_key: &str,
+) -> (i32, Option<i32>) {
+    let count = |key: &str| {
+        usage
+            .and_then(|u| u.get(key))
+            .and_then(|t| t.as_i64())
+            .map(|t| t as i32)
+    };
+    let cached = count(cache_read_key);
+    let prompt_tokens =
+        count(input_key).unwrap_or(0) + cached.unwrap_or(0) + count(cache_write_key).unwrap_or(0);
+    (prompt_tokens, cached)
 }
 
 #[derive(Serialize)]
@@ -87,44 +196,426 @@ pub struct OpenAIStreamResponse {
     pub created: i64,
     pub model: String,
     pub choices: Vec<OpenAIStreamChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<OpenAIUsage>,
+}
+
+// Sampling temperature sent when the request doesn't set one
+pub const DEFAULT_TEMPERATURE: f32 = 0.7;
+
+// `object` values on chat completions and stream chunks. Clients match these exactly,
+// so a compatibility shim that needs a different value only has to change them here
+pub const CHAT_COMPLETION_OBJECT: &str = "chat.completion";
+pub const CHAT_COMPLETION_CHUNK_OBJECT: &str = "chat.completion.chunk";
+
+// Bedrock model families with distinct request/response schemas; each has a
+// `ModelAdapter` (see adapters.rs) doing the conversion
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ModelFamily {
+    Anthropic,
+    Titan,
+    // Cohere Command text models (not Command R, which has a chat schema)
+    Cohere,
+    // Any model through the unified Converse API (USE_CONVERSE_API=true)
+    Converse,
+}
+
+impl ModelFamily {
+    pub fn from_model_id(model_id: &str) -> Self {
+        if model_id.contains("amazon.titan-text") {
+            ModelFamily::Titan
+        } else if model_id.contains("cohere.command-text")
+            || model_id.contains("cohere.command-light-text")
+        {
+            ModelFamily::Cohere
+        } else {
+            ModelFamily::Anthropic
+        }
+    }
+
+    // Schema to use for a chat request
+    pub fn for_request(state: &AppState, model_id: &str) -> Self {
+        if state.use_converse_api {
+            ModelFamily::Converse
+        } else {
+            Self::from_model_id(model_id)
+        }
+    }
+
+    // Bedrock runtime action appended to /model/{id}/
+    pub fn action(self, streaming: bool) -> &'static str {
+        match (self, streaming) {
+            (ModelFamily::Converse, false) => "converse",
+            (ModelFamily::Converse, true) => "converse-stream",
+            (_, false) => "invoke",
+            (_, true) => "invoke-with-response-stream",
+        }
+    }
+
+    // Whether this family's responses can carry token logprobs
+    pub fn returns_logprobs(self) -> bool {
+        self == ModelFamily::Cohere
+    }
+
+    // Response as this family's transform reads it (Anthropic bodies may be nested)
+    fn unwrap_response(self, resp: &Value) -> Cow<'_, Value> {
+        match self {
+            ModelFamily::Anthropic => unwrap_nested_body(resp),
+            _ => Cow::Borrowed(resp),
+        }
+    }
+
+    // Raw stop reason of a non-streaming response, before finish_reason mapping
+    pub fn raw_stop_reason(self, resp: &Value) -> Option<String> {
+        let resp = self.unwrap_response(resp);
+        let pointer = match self {
+            ModelFamily::Anthropic => "/stop_reason",
+            ModelFamily::Titan => "/results/0/completionReason",
+            ModelFamily::Cohere => "/generations/0/finish_reason",
+            ModelFamily::Converse => "/stopReason",
+        };
+        resp.pointer(pointer)
+            .and_then(|r| r.as_str())
+            .map(|r| r.to_string())
+    }
+
+    // Check a non-streaming response has the shape this family's transform reads
+    // (VALIDATE_BEDROCK_RESPONSES). Err describes what is missing
+    pub fn validate_response(self, resp: &Value) -> Result<(), String> {
+        let resp = self.unwrap_response(resp);
+        let (path, array) = match self {
+            ModelFamily::Anthropic => ("content", resp.get("content")),
+            ModelFamily::Titan => ("results", resp.get("results")),
+            ModelFamily::Cohere => ("generations", resp.get("generations")),
+            ModelFamily::Converse => (
+                "output.message.content",
+                resp.pointer("/output/message/content"),
+            ),
+        };
+        if array.is_some_and(|a| a.is_array()) {
+            return Ok(());
+        }
+
+        let keys: Vec<&str> = resp
+            .as_object()
+            .map(|o| o.keys().map(|k| k.as_str()).collect())
+            .unwrap_or_default();
+        Err(format!(
+            "Unrecognized Bedrock response: expected a `{}` array, got top-level keys [{}]",
+            path,
+            keys.join(", ")
+        ))
+    }
+}
+
+// OpenAI's `developer` role is the newer name for `system`
+fn is_system_role(role: &str) -> bool {
+    matches!(role, "system" | "developer")
+}
+
+// --------------------------------------------------
+// Map legacy roles onto ones Bedrock understands and reject the rest.
+// `function` (pre-tools OpenAI SDKs) becomes `tool`, keyed by the function name.
+// Err carries a client-facing message for a 400 response.
+// --------------------------------------------------
+pub fn normalize_roles(messages: &mut [OpenAIMessage]) -> Result<(), String> {
+    for (i, m) in messages.iter_mut().enumerate() {
+        match m.role.as_str() {
+            "system" | "developer" | "user" | "assistant" | "tool" => {}
+            "function" => {
+                m.role = "tool".to_string();
+                if m.tool_call_id.is_none() {
+                    m.tool_call_id = m.name.clone();
+                }
+            }
+            other => {
+                return Err(format!(
+                    "Unsupported role '{}' in messages[{}]; expected one of system, developer, user, assistant, tool, function",
+                    other, i
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+// A legacy /invoke body, sorted by format
+pub enum LegacyPayload {
+    // OpenAI chat format, still to be checked and converted like a chat completion
+    OpenAI(Box<OpenAIRequest>),
+    // Anthropic-native body, ready for Bedrock
+    Native(serde_json::Map<String, Value>),
 }
 
 // --------------------------------------------------
 // Transform raw payload into Bedrock-compatible format
 // --------------------------------------------------
-pub fn transform_payload(mut payload: Value) -> Value {
-    if let Some(prompt) = payload.get("prompt").and_then(|p| p.as_str()) {
-        payload = json!({
-            "messages": [{"role": "user", "content": prompt}],
-            "max_tokens": payload.get("max_tokens_to_sample").unwrap_or(&json!(200))
-        });
+pub fn transform_payload(payload: Value) -> Result<LegacyPayload, String> {
+    let Value::Object(mut payload) = payload else {
+        return Err("Request body must be a JSON object".to_string());
+    };
+
+    let prompt = match payload.get("prompt") {
+        None => None,
+        Some(Value::String(p)) => Some(p.clone()),
+        // Multiple prompts are joined into a single user turn
+        Some(Value::Array(items)) => Some(
+            items
+                .iter()
+                .map(|p| p.as_str())
+                .collect::<Option<Vec<_>>>()
+                .ok_or("`prompt` array must contain only strings")?
+                .join("\n\n"),
+        ),
+        Some(_) => return Err("`prompt` must be a string or an array of strings".to_string()),
+    };
+
+    if let Some(prompt) = prompt {
+        let max_tokens = payload
+            .get("max_tokens_to_sample")
+            .cloned()
+            .unwrap_or(json!(200));
+        payload = serde_json::Map::new();
+        payload.insert(
+            "messages".to_string(),
+            json!([{"role": "user", "content": prompt}]),
+        );
+        payload.insert("max_tokens".to_string(), max_tokens);
     }
 
-    if payload.get("max_tokens_to_sample").is_some() && payload.get("max_tokens").is_none() {
-        if let Some(max_tokens) = payload.get("max_tokens_to_sample") {
-            payload["max_tokens"] = max_tokens.clone();
-            payload
-                .as_object_mut()
-                .unwrap()
-                .remove("max_tokens_to_sample");
+    // Structured messages are passed through as-is
+    if let Some(messages) = payload.get("messages") {
+        if !messages.is_array() {
+            return Err("`messages` must be an array".to_string());
         }
     }
 
-    payload["anthropic_version"] = json!("bedrock-2023-05-31");
-    payload
+    // OpenAI-style bodies go through the chat completions transform
+    if is_openai_format(&payload) {
+        let req: Box<OpenAIRequest> = serde_json::from_value(Value::Object(payload))
+            .map_err(|e| format!("Invalid OpenAI-style request: {}", e))?;
+        return Ok(LegacyPayload::OpenAI(req));
+    }
+
+    if !payload.contains_key("max_tokens") {
+        if let Some(max_tokens) = payload.remove("max_tokens_to_sample") {
+            payload.insert("max_tokens".to_string(), max_tokens);
+        }
+    }
+
+    if let Some(stops) = payload.get_mut("stop_sequences") {
+        if let Value::Array(items) = stops {
+            items.retain(|s| s.as_str() != Some(""));
+        }
+        if stops.as_array().is_some_and(|a| a.is_empty()) {
+            payload.remove("stop_sequences");
+        }
+    }
+
+    payload.insert("anthropic_version".to_string(), json!("bedrock-2023-05-31"));
+    Ok(LegacyPayload::Native(payload))
+}
+
+// OpenAI chat format is detected by what Anthropic would reject: system/developer/tool
+// role messages, assistant `tool_calls`, `image_url` parts or OpenAI-only fields.
+// A plain OpenAI body (only `model`/`stream`/`temperature` next to `messages`, all with
+// string content) counts too, as Anthropic would reject it for the missing `max_tokens`.
+// An explicit `anthropic_version` or top-level `system` marks the body as Anthropic-native.
+fn is_openai_format(payload: &serde_json::Map<String, Value>) -> bool {
+    const OPENAI_FIELDS: [&str; 5] = [
+        "stop",
+        "n",
+        "max_completion_tokens",
+        "stream_options",
+        "user",
+    ];
+    const PLAIN_OPENAI_FIELDS: [&str; 4] = ["messages", "model", "stream", "temperature"];
+
+    if payload.contains_key("anthropic_version") || payload.contains_key("system") {
+        return false;
+    }
+    if OPENAI_FIELDS
+        .iter()
+        .any(|field| payload.contains_key(*field))
+    {
+        return true;
+    }
+
+    let messages = payload.get("messages").and_then(|m| m.as_array());
+    let plain = payload
+        .keys()
+        .all(|key| PLAIN_OPENAI_FIELDS.contains(&key.as_str()));
+    if plain
+        && messages.is_some_and(|messages| {
+            messages
+                .iter()
+                .all(|m| m.get("content").is_some_and(Value::is_string))
+        })
+    {
+        return true;
+    }
+
+    messages.into_iter().flatten().any(|m| {
+        let role = m.get("role").and_then(|r| r.as_str());
+        let has_image_url = m
+            .get("content")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+            .any(|p| p.get("type").and_then(|t| t.as_str()) == Some("image_url"));
+
+        matches!(role, Some("system" | "developer" | "tool" | "function"))
+            || m.get("tool_calls").is_some()
+            || has_image_url
+    })
+}
+
+// --------------------------------------------------
+// Rough token estimate (~4 chars per token) for budgeting
+// --------------------------------------------------
+pub fn estimate_message_tokens(m: &OpenAIMessage) -> usize {
+    let content_len = match &m.content {
+        Some(Value::String(s)) => s.len(),
+        Some(c) => c.to_string().len(),
+        None => 0,
+    };
+    // Small per-message overhead for role and framing
+    content_len / 4 + 4
+}
+
+// --------------------------------------------------
+// Resolve max_tokens, shrinking it so the estimated prompt plus output
+// stays within the configured model context window
+// --------------------------------------------------
+pub fn effective_max_tokens(req: &OpenAIRequest, state: &AppState) -> u32 {
+    let requested = req.max_tokens.unwrap_or(state.default_max_tokens);
+
+    let Some(window) = state.model_context_window else {
+        return requested;
+    };
+
+    let input_estimate: usize = req.messages.iter().map(estimate_message_tokens).sum();
+    let available = window.saturating_sub(input_estimate).max(1) as u32;
+
+    if requested > available {
+        tracing::info!(
+            "✂️ Reducing max_tokens from {} to {} (estimated input {} tokens, context window {})",
+            requested,
+            available,
+            input_estimate,
+            window
+        );
+        available
+    } else {
+        requested
+    }
+}
+
+// Output tokens left in MODEL_CONTEXT_WINDOW after the estimated prompt, if one is set
+pub fn context_window_room(req: &OpenAIRequest, state: &AppState) -> Option<u32> {
+    let window = state.model_context_window?;
+    let input_estimate: usize = req.messages.iter().map(estimate_message_tokens).sum();
+    Some(window.saturating_sub(input_estimate).max(1) as u32)
+}
+
+// --------------------------------------------------
+// Drop oldest non-system messages until history fits the token budget.
+// System messages and the latest user turn are always kept.
+// Returns the number of messages dropped.
+// --------------------------------------------------
+pub fn truncate_history(messages: &mut Vec<OpenAIMessage>, budget: usize) -> usize {
+    let mut dropped = 0;
+
+    loop {
+        let total: usize = messages.iter().map(estimate_message_tokens).sum();
+        if total <= budget {
+            break;
+        }
+
+        // The latest turn starts at the last user message
+        let Some(latest_turn) = messages.iter().rposition(|m| m.role == "user") else {
+            break;
+        };
+        let Some(oldest) = messages[..latest_turn]
+            .iter()
+            .position(|m| !is_system_role(&m.role))
+        else {
+            break;
+        };
+
+        messages.remove(oldest);
+        dropped += 1;
+
+        // Keep the history starting on a user message so assistant replies
+        // and tool results are removed together with the turn they belong to
+        while let Some(next) = messages.iter().position(|m| !is_system_role(&m.role)) {
+            if messages[next].role == "user" {
+                break;
+            }
+            messages.remove(next);
+            dropped += 1;
+        }
+    }
+
+    dropped
+}
+
+// --------------------------------------------------
+// Count tool-call round trips: each run of consecutive tool
+// result messages answers one assistant tool-calling turn
+// --------------------------------------------------
+pub fn count_tool_turns(messages: &[OpenAIMessage]) -> usize {
+    messages
+        .iter()
+        .enumerate()
+        .filter(|(i, m)| m.role == "tool" && (*i == 0 || messages[i - 1].role != "tool"))
+        .count()
+}
+
+// --------------------------------------------------
+// Fill in usage from text length when Bedrock reported none.
+// Returns true if the usage was estimated.
+// --------------------------------------------------
+pub fn estimate_missing_usage(resp: &mut OpenAIResponse, req: &OpenAIRequest) -> bool {
+    if resp.usage.total_tokens > 0 {
+        return false;
+    }
+
+    let prompt_tokens: usize = req.messages.iter().map(estimate_message_tokens).sum();
+    let completion_chars: usize = resp
+        .choices
+        .iter()
+        .map(|c| {
+            let tool_args: usize = c
+                .message
+                .tool_calls
+                .iter()
+                .flatten()
+                .map(|tc| tc.function.arguments.len())
+                .sum();
+            content_to_text(&c.message.content).len() + tool_args
+        })
+        .sum();
+
+    resp.usage.prompt_tokens = prompt_tokens as i32;
+    resp.usage.completion_tokens = (completion_chars / 4) as i32;
+    resp.usage.total_tokens = resp.usage.prompt_tokens + resp.usage.completion_tokens;
+    true
 }
 
 // --------------------------------------------------
 // Convert OpenAIRequest → Bedrock JSON
 // --------------------------------------------------
-pub fn openai_to_bedrock(req: &OpenAIRequest) -> Value {
+pub fn openai_to_bedrock(req: &OpenAIRequest, model_id: &str, state: &AppState) -> Value {
     let mut system_prompts: Vec<String> = Vec::new();
+    let latest_user = req.messages.iter().rposition(|m| m.role == "user");
 
     let messages: Vec<Value> = req
         .messages
         .iter()
-        .filter_map(|m| {
-            if m.role == "system" {
+        .enumerate()
+        .filter_map(|(i, m)| {
+            if is_system_role(&m.role) {
                 if let Some(c) = &m.content {
                     match c {
                         Value::String(s) => system_prompts.push(s.clone()),
@@ -133,43 +624,10 @@ pub fn openai_to_bedrock(req: &OpenAIRequest) -> Value {
                 }
                 None
             } else if m.role == "assistant" {
-                // Handle assistant tool calls
-                if let Some(Value::Object(map)) = &m.content {
-                    if let Some(tool_calls) = map.get("tool_calls") {
-                        let default_tool_calls = vec![];
-                        let tool_calls_array = tool_calls.as_array().unwrap_or(&default_tool_calls);
-
-                        let tool_use_contents: Vec<Value> = tool_calls_array
-                            .iter()
-                            .map(|tc| {
-                                let func_default = json!({});
-                                let func = tc.get("function").unwrap_or(&func_default);
-
-                                json!({
-                                    "type": "tool_use",
-                                    "id": tc.get("id").and_then(|v| v.as_str()).unwrap_or("tool_call_1"),
-                                    "name": func.get("name").and_then(|v| v.as_str()).unwrap_or("unknown_tool"),
-                                    "input": func.get("arguments").unwrap_or(&json!({}))
-                                })
-                            })
-                            .collect();
-
-                        Some(json!({
-                            "role": "assistant",
-                            "content": tool_use_contents
-                        }))
-                    } else {
-                        Some(json!({
-                            "role": "assistant",
-                            "content": m.content.clone().unwrap_or(Value::String("".to_string()))
-                        }))
-                    }
-                } else {
-                    Some(json!({
-                        "role": "assistant",
-                        "content": m.content.clone().unwrap_or(Value::String("".to_string()))
-                    }))
-                }
+                Some(json!({
+                    "role": "assistant",
+                    "content": assistant_content(m)
+                }))
             } else if m.role == "tool" {
                 Some(json!({
                     "role": "user",
@@ -180,14 +638,20 @@ pub fn openai_to_bedrock(req: &OpenAIRequest) -> Value {
                     }]
                 }))
             } else {
+                let mut content = with_document_blocks(content_with_name(&m.content, &m.name));
+                if Some(i) == latest_user {
+                    content = wrap_user_prompt(content, state);
+                }
                 Some(json!({
                     "role": m.role,
-                    "content": m.content.clone().unwrap_or(Value::String("".to_string()))
+                    "content": content
                 }))
             }
         })
         .collect();
 
+    system_prompts.extend(json_mode_instruction(req));
+
     let merged_system = if !system_prompts.is_empty() {
         Some(system_prompts.join("\n\n"))
     } else {
@@ -197,145 +661,2920 @@ pub fn openai_to_bedrock(req: &OpenAIRequest) -> Value {
     let mut payload = json!({
         "anthropic_version": "bedrock-2023-05-31",
         "messages": messages,
-        "max_tokens": req.max_tokens.unwrap_or(512),
-        "temperature": req.temperature.unwrap_or(0.7),
+        "max_tokens": effective_max_tokens(req, state),
+        "temperature": req.temperature.unwrap_or(DEFAULT_TEMPERATURE),
     });
 
     if let Some(sys) = merged_system {
         payload["system"] = Value::String(sys);
     }
 
-    payload
-}
-
-// --------------------------------------------------
-// Convert Bedrock JSON → OpenAIResponse
-// --------------------------------------------------
-pub fn bedrock_to_openai(resp: &Value, model: &str) -> OpenAIResponse {
-    let mut message_content: Option<String> = None;
-    let mut tool_calls: Vec<ToolCall> = Vec::new();
-    let mut finish_reason = "stop";
+    let stops = stop_sequences(&req.stop, state);
+    if !stops.is_empty() {
+        payload["stop_sequences"] = json!(stops);
+    }
 
-    if let Some(content_array) = resp.get("content").and_then(|c| c.as_array()) {
-        for block in content_array {
-            if let Some(block_type) = block.get("type").and_then(|t| t.as_str()) {
-                match block_type {
-                    "text" => {
-                        if let Some(text) = block.get("text").and_then(|t| t.as_str()) {
-                            message_content = Some(text.to_string());
-                        }
-                    }
-                    "tool_use" => {
-                        if let (Some(id), Some(name), Some(input)) = (
-                            block.get("id").and_then(|i| i.as_str()),
-                            block.get("name").and_then(|n| n.as_str()),
-                            block.get("input"),
-                        ) {
-                            tool_calls.push(ToolCall {
-                                id: id.to_string(),
-                                r#type: "function".to_string(),
-                                function: FunctionCall {
-                                    name: name.to_string(),
-                                    arguments: serde_json::to_string(input)
-                                        .unwrap_or_else(|_| "{}".to_string()),
-                                },
-                            });
-                            finish_reason = "tool_calls";
-                        }
-                    }
-                    _ => {}
+    if let Some(tools) = req.tools.as_ref().filter(|t| !t.is_empty()) {
+        let tools: Vec<Value> = tools
+            .iter()
+            .map(|t| {
+                let mut tool = json!({
+                    "name": t.function.name,
+                    "input_schema": t.function.parameters.clone()
+                        .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+                });
+                if let Some(description) = &t.function.description {
+                    tool["description"] = json!(description);
                 }
+                tool
+            })
+            .collect();
+        payload["tools"] = Value::Array(tools);
+
+        // Invalid values were rejected by the handler; treat them as absent here
+        match parse_tool_choice(req).ok().flatten() {
+            Some(ToolChoice::Auto) => payload["tool_choice"] = json!({"type": "auto"}),
+            Some(ToolChoice::None) => payload["tool_choice"] = json!({"type": "none"}),
+            Some(ToolChoice::Required) => payload["tool_choice"] = json!({"type": "any"}),
+            Some(ToolChoice::Function(name)) => {
+                payload["tool_choice"] = json!({"type": "tool", "name": name})
             }
+            None => {}
         }
     }
 
-    let prompt_tokens = resp
-        .get("usage")
-        .and_then(|u| u.get("input_tokens"))
-        .and_then(|t| t.as_i64())
-        .unwrap_or(0) as i32;
+    // Anthropic metadata.user_id for AWS-side abuse monitoring;
+    // an explicit metadata.user_id wins over the OpenAI `user` field
+    let user_id = req
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get("user_id"))
+        .and_then(|u| u.as_str())
+        .or(req.user.as_deref());
+    if let Some(user_id) = user_id {
+        payload["metadata"] = json!({ "user_id": user_id });
+    }
 
-    let completion_tokens = resp
-        .get("usage")
-        .and_then(|u| u.get("output_tokens"))
-        .and_then(|t| t.as_i64())
-        .unwrap_or(0) as i32;
+    if let Some(budget) = thinking_budget(req, model_id, state) {
+        // Extended thinking requires temperature 1 and max_tokens above the budget
+        payload["thinking"] = json!({"type": "enabled", "budget_tokens": budget});
+        payload["temperature"] = json!(1);
+        let max_tokens = payload["max_tokens"].as_u64().unwrap_or(0) as u32;
+        if max_tokens <= budget {
+            payload["max_tokens"] = json!(budget + max_tokens);
+        }
+    }
+
+    if state.enforce_max_tokens_ceiling {
+        apply_output_ceiling(&mut payload, model_id, state);
+    }
 
-    let total_tokens = prompt_tokens + completion_tokens;
+    payload
+}
 
-    let message = OpenAIMessage {
-        role: "assistant".to_string(),
-        content: message_content.map(Value::String),
-        tool_calls: if tool_calls.is_empty() {
-            None
-        } else {
-            Some(tool_calls)
-        },
-        tool_call_id: None,
+// anthropic_beta flag and the max output tokens it allows
+type ExtendedOutputBeta = (&'static str, u32);
+
+// Model ID fragment → (max output tokens, extended-output beta).
+// More specific fragments come first; unknown models are left alone
+const OUTPUT_CEILINGS: &[(&str, u32, Option<ExtendedOutputBeta>)] = &[
+    (
+        "claude-3-5-sonnet-20240620",
+        4096,
+        Some(("max-tokens-3-5-sonnet-2024-07-15", 8192)),
+    ),
+    ("claude-3-5-sonnet", 8192, None),
+    ("claude-3-5-haiku", 8192, None),
+    (
+        "claude-3-7-sonnet",
+        64000,
+        Some(("output-128k-2025-02-19", 128000)),
+    ),
+    ("claude-3-haiku", 4096, None),
+    ("claude-3-sonnet", 4096, None),
+    ("claude-3-opus", 4096, None),
+    ("claude-opus-4", 32000, None),
+    ("claude-sonnet-4", 64000, None),
+    ("claude-haiku-4", 64000, None),
+];
+
+// --------------------------------------------------
+// Keep max_tokens within the model's output ceiling: enable the
+// extended-output beta (MAX_TOKENS_CEILING=beta) when the model has
+// one, otherwise clamp. A thinking budget the clamp would leave at or
+// above max_tokens is cut to half of it, leaving the rest for the answer
+// --------------------------------------------------
+fn apply_output_ceiling(payload: &mut Value, model_id: &str, state: &AppState) {
+    let Some(&(_, ceiling, beta)) = OUTPUT_CEILINGS
+        .iter()
+        .find(|(fragment, _, _)| model_id.contains(fragment))
+    else {
+        return;
     };
+    let requested = payload["max_tokens"].as_u64().unwrap_or(0) as u32;
+    if requested <= ceiling {
+        return;
+    }
 
-    let choice = OpenAIChoice {
-        index: 0,
-        message,
-        finish_reason: finish_reason.to_string(),
+    let mut limit = ceiling;
+    if let Some((beta, extended)) = beta.filter(|_| state.extended_output_beta) {
+        payload["anthropic_beta"] = json!([beta]);
+        limit = extended;
+        tracing::info!(
+            "📏 max_tokens={} exceeds {} for {}, enabling {}",
+            requested,
+            ceiling,
+            model_id,
+            beta
+        );
+    }
+
+    if requested > limit {
+        tracing::info!(
+            "📏 Clamping max_tokens from {} to {} for {}",
+            requested,
+            limit,
+            model_id
+        );
+        payload["max_tokens"] = json!(limit);
+
+        let budget = payload["thinking"]["budget_tokens"].as_u64();
+        if budget.is_some_and(|budget| budget >= limit as u64) {
+            tracing::info!(
+                "📏 Lowering thinking budget_tokens from {} to {} for {}",
+                budget.unwrap_or_default(),
+                limit / 2,
+                model_id
+            );
+            payload["thinking"]["budget_tokens"] = json!(limit / 2);
+        }
+    }
+}
+
+// response_format types the proxy understands; "text" is the default and a no-op.
+// Err carries a client-facing message for a 400 response
+pub fn validate_response_format(req: &OpenAIRequest) -> Result<(), String> {
+    let Some(format) = &req.response_format else {
+        return Ok(());
     };
 
-    OpenAIResponse {
-        id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
-        object: "chat.completion".to_string(),
-        created: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64,
-        model: model.to_string(),
-        choices: vec![choice],
-        usage: OpenAIUsage {
-            prompt_tokens,
-            completion_tokens,
-            total_tokens,
-        },
+    match format.get("type").and_then(|t| t.as_str()) {
+        Some("text" | "json_object" | "json_schema") => Ok(()),
+        Some(other) => Err(format!(
+            "Invalid response_format type '{}'; expected one of text, json_object, json_schema",
+            other
+        )),
+        None => Err("response_format must be an object with a 'type' field".to_string()),
     }
 }
 
-// --------------------------------------------------
-// Convert Bedrock streaming chunk → OpenAI streaming chunk
-// --------------------------------------------------
-pub fn bedrock_chunk_to_openai(chunk: &Value) -> Option<OpenAIStreamResponse> {
-    let mut delta = serde_json::Map::new();
-    let mut finish_reason = None;
+// OpenAI `input_audio` content parts; no Bedrock model the proxy serves accepts audio,
+// and forwarding them only yields an opaque upstream validation error.
+// Err carries a client-facing message for a 400 response
+pub fn reject_audio_input(req: &OpenAIRequest, model_id: &str) -> Result<(), String> {
+    let has_audio = req.messages.iter().any(|m| {
+        m.content
+            .as_ref()
+            .and_then(|c| c.as_array())
+            .is_some_and(|parts| {
+                parts
+                    .iter()
+                    .any(|p| p.get("type").and_then(|t| t.as_str()) == Some("input_audio"))
+            })
+    });
 
-    match chunk.get("type").and_then(|t| t.as_str()) {
-        Some("message_start") => {
-            delta.insert("role".to_string(), Value::String("assistant".to_string()));
-        }
-        Some("content_block_delta") => {
-            if let Some(text) = chunk
-                .get("delta")
-                .and_then(|d| d.get("text"))
-                .and_then(|t| t.as_str())
-            {
-                delta.insert("content".to_string(), Value::String(text.to_string()));
-            }
-        }
-        Some("message_stop") => {
-            finish_reason = Some("stop".to_string());
-        }
-        _ => {}
+    if has_audio {
+        return Err(format!(
+            "Audio input ('input_audio' content parts) is not supported for model '{}'",
+            model_id
+        ));
     }
+    Ok(())
+}
 
-    if delta.is_empty() && finish_reason.is_none() {
-        return None;
+// `logprobs: true` or a non-zero `top_logprobs`
+pub fn requests_logprobs(req: &OpenAIRequest) -> bool {
+    req.logprobs == Some(true) || req.top_logprobs.is_some_and(|n| n > 0)
+}
+
+// Only Cohere Command returns token logprobs. Other families answer with `logprobs: null`
+// unless LOGPROBS_UNSUPPORTED_BEHAVIOR=error, which refuses the request.
+// Err carries a client-facing message for a 501 response
+pub fn validate_logprobs(
+    req: &OpenAIRequest,
+    model_id: &str,
+    state: &AppState,
+) -> Result<(), String> {
+    let supported = ModelFamily::for_request(state, model_id).returns_logprobs();
+    if state.reject_logprobs && requests_logprobs(req) && !supported {
+        return Err(format!(
+            "'logprobs' and 'top_logprobs' are not supported for model '{}': Bedrock does not \
+             return token log probabilities",
+            model_id
+        ));
     }
+    Ok(())
+}
 
-    Some(OpenAIStreamResponse {
-        id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
-        object: "chat.completion.chunk".to_string(),
-        created: chrono::Utc::now().timestamp(),
-        model: "claude-via-bedrock".to_string(),
-        choices: vec![OpenAIStreamChoice {
-            delta: Value::Object(delta),
-            index: 0,
-            finish_reason,
+// Bedrock caps the images in one request (20 for Claude) and 400s past it;
+// MAX_IMAGES_PER_REQUEST lets the proxy reject earlier with a clearer message.
+// Err carries a client-facing message for a 400 response
+pub fn validate_image_count(req: &OpenAIRequest, max: usize) -> Result<(), String> {
+    let images = req
+        .messages
+        .iter()
+        .filter_map(|m| m.content.as_ref().and_then(|c| c.as_array()))
+        .flatten()
+        .filter(|p| p.get("type").and_then(|t| t.as_str()) == Some("image_url"))
+        .count();
+
+    if images > max {
+        return Err(format!(
+            "Request contains {} images, exceeding the limit of {} per request",
+            images, max
+        ));
+    }
+    Ok(())
+}
+
+// Document inputs Claude accepts as base64 `document` blocks, and its per-file size limit
+const DOCUMENT_MEDIA_TYPES: [&str; 1] = ["application/pdf"];
+const MAX_DOCUMENT_BYTES: usize = 32 * 1024 * 1024;
+
+// Inline document carried by a content part
+struct DocumentPart<'a> {
+    media_type: &'a str,
+    data: &'a str,
+    title: Option<&'a str>,
+}
+
+// OpenAI has no standard document part; two shapes are accepted:
+// {"type": "file", "file": {"filename": ..., "file_data": "data:<media type>;base64,<data>"}}
+// {"type": "document", "source": {"type": "base64", "media_type": ..., "data": ...}}
+// Ok(None) for any other part type
+fn document_part(part: &Value) -> Result<Option<DocumentPart<'_>>, String> {
+    match part.get("type").and_then(|t| t.as_str()) {
+        Some("file") => {
+            let file = part.get("file");
+            let Some(url) = file
+                .and_then(|f| f.get("file_data"))
+                .and_then(|d| d.as_str())
+            else {
+                return Err(
+                    "'file' content parts must carry inline 'file_data'; file IDs are not supported"
+                        .to_string(),
+                );
+            };
+            let (media_type, data) = url
+                .strip_prefix("data:")
+                .and_then(|rest| rest.split_once(";base64,"))
+                .ok_or("'file_data' must be a base64 data URL (data:<media type>;base64,...)")?;
+            Ok(Some(DocumentPart {
+                media_type,
+                data,
+                title: file
+                    .and_then(|f| f.get("filename"))
+                    .and_then(|n| n.as_str()),
+            }))
+        }
+        Some("document") => {
+            let source = part.get("source");
+            if source.and_then(|s| s.get("type")).and_then(|t| t.as_str()) != Some("base64") {
+                return Err("'document' content parts need a base64 'source'".to_string());
+            }
+            let field = |name: &str| source.and_then(|s| s.get(name)).and_then(|v| v.as_str());
+            match (field("media_type"), field("data")) {
+                (Some(media_type), Some(data)) => Ok(Some(DocumentPart {
+                    media_type,
+                    data,
+                    title: part.get("title").and_then(|t| t.as_str()),
+                })),
+                _ => Err("'document' source needs 'media_type' and 'data'".to_string()),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+// Document parts must be a supported media type, valid base64 and within the size limit.
+// Err carries a client-facing message for a 400 response
+pub fn validate_documents(req: &OpenAIRequest) -> Result<(), String> {
+    let parts = req
+        .messages
+        .iter()
+        .filter_map(|m| m.content.as_ref().and_then(|c| c.as_array()))
+        .flatten();
+
+    for part in parts {
+        let Some(doc) = document_part(part)? else {
+            continue;
+        };
+        if !DOCUMENT_MEDIA_TYPES.contains(&doc.media_type) {
+            return Err(format!(
+                "Unsupported document media type '{}'; expected one of {}",
+                doc.media_type,
+                DOCUMENT_MEDIA_TYPES.join(", ")
+            ));
+        }
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(doc.data)
+            .map_err(|_| "Document data is not valid base64".to_string())?;
+        if bytes.len() > MAX_DOCUMENT_BYTES {
+            return Err(format!(
+                "Document is {} bytes, exceeding the limit of {} bytes",
+                bytes.len(),
+                MAX_DOCUMENT_BYTES
+            ));
+        }
+    }
+    Ok(())
+}
+
+// OpenAI JSON mode: response_format json_object or json_schema
+pub fn json_mode(req: &OpenAIRequest) -> bool {
+    matches!(
+        req.response_format
+            .as_ref()
+            .and_then(|f| f.get("type"))
+            .and_then(|t| t.as_str()),
+        Some("json_object" | "json_schema")
+    )
+}
+
+// --------------------------------------------------
+// Bedrock models have no JSON mode switch, so JSON mode is requested
+// through the system prompt (with the schema for json_schema)
+// --------------------------------------------------
+fn json_mode_instruction(req: &OpenAIRequest) -> Option<String> {
+    if !json_mode(req) {
+        return None;
+    }
+
+    let mut instruction = "Respond only with a valid JSON object, with no surrounding text or \
+                           code fences."
+        .to_string();
+    let schema = req
+        .response_format
+        .as_ref()
+        .and_then(|f| f.pointer("/json_schema/schema"));
+    if let Some(schema) = schema {
+        instruction.push_str(&format!(
+            " The JSON must conform to this JSON Schema: {}",
+            schema
+        ));
+    }
+    Some(instruction)
+}
+
+// Model ID fragments of Claude models that support extended thinking
+const THINKING_MODELS: &[&str] = &[
+    "claude-3-7-sonnet",
+    "claude-sonnet-4",
+    "claude-opus-4",
+    "claude-haiku-4",
+];
+
+// --------------------------------------------------
+// OpenAI reasoning_effort → Anthropic extended-thinking budget
+// (REASONING_BUDGET_LOW/MEDIUM/HIGH). None for models without
+// extended thinking and for unknown levels
+// --------------------------------------------------
+pub fn thinking_budget(req: &OpenAIRequest, model_id: &str, state: &AppState) -> Option<u32> {
+    let effort = req.reasoning_effort.as_deref()?;
+    if !THINKING_MODELS.iter().any(|m| model_id.contains(m)) {
+        tracing::info!(
+            "🧠 Ignoring reasoning_effort={} for {}: no extended thinking support",
+            effort,
+            model_id
+        );
+        return None;
+    }
+
+    match effort {
+        "low" => Some(state.reasoning_budget_low),
+        "medium" => Some(state.reasoning_budget_medium),
+        "high" => Some(state.reasoning_budget_high),
+        other => {
+            tracing::warn!("🧠 Ignoring unknown reasoning_effort={}", other);
+            None
+        }
+    }
+}
+
+// --------------------------------------------------
+// Anthropic content for an assistant turn: any text first, then one
+// tool_use block per tool call. Tool calls come from the OpenAI
+// `tool_calls` field or a legacy `{"tool_calls": [...]}` content object
+// --------------------------------------------------
+fn assistant_content(m: &OpenAIMessage) -> Value {
+    let mut tool_uses: Vec<Value> = m
+        .tool_calls
+        .iter()
+        .flatten()
+        .map(|tc| {
+            json!({
+                "type": "tool_use",
+                "id": tc.id,
+                "name": tc.function.name,
+                "input": serde_json::from_str::<Value>(&tc.function.arguments)
+                    .unwrap_or_else(|_| json!({})),
+            })
+        })
+        .collect();
+
+    let legacy_calls = m
+        .content
+        .as_ref()
+        .and_then(|c| c.get("tool_calls"))
+        .and_then(|t| t.as_array());
+    if let Some(legacy_calls) = legacy_calls {
+        tool_uses.extend(legacy_calls.iter().map(|tc| {
+            let func_default = json!({});
+            let func = tc.get("function").unwrap_or(&func_default);
+            let input = match func.get("arguments") {
+                Some(Value::String(args)) => {
+                    serde_json::from_str(args).unwrap_or_else(|_| json!({}))
+                }
+                Some(args) => args.clone(),
+                None => json!({}),
+            };
+            json!({
+                "type": "tool_use",
+                "id": tc.get("id").and_then(|v| v.as_str()).unwrap_or("tool_call_1"),
+                "name": func.get("name").and_then(|v| v.as_str()).unwrap_or("unknown_tool"),
+                "input": input,
+            })
+        }));
+        return Value::Array(tool_uses);
+    }
+
+    let content = content_with_name(&m.content, &m.name);
+    if tool_uses.is_empty() {
+        return content;
+    }
+
+    // Anthropic rejects empty text blocks, so a blank narration is dropped
+    let mut blocks = match content {
+        Value::String(text) if text.is_empty() => Vec::new(),
+        Value::String(text) => vec![json!({"type": "text", "text": text})],
+        Value::Array(parts) => parts,
+        _ => Vec::new(),
+    };
+    blocks.extend(tool_uses);
+    Value::Array(blocks)
+}
+
+// --------------------------------------------------
+// USER_PROMPT_PREFIX / USER_PROMPT_SUFFIX around the latest user message:
+// joined with a blank line for string content, added as separate
+// text parts for content arrays so image parts are left untouched
+// --------------------------------------------------
+fn wrap_user_prompt(content: Value, state: &AppState) -> Value {
+    let prefix = state.user_prompt_prefix.as_deref();
+    let suffix = state.user_prompt_suffix.as_deref();
+    if prefix.is_none() && suffix.is_none() {
+        return content;
+    }
+
+    match content {
+        Value::String(text) => {
+            let parts: Vec<&str> = [prefix, Some(text.as_str()), suffix]
+                .into_iter()
+                .flatten()
+                .collect();
+            Value::String(parts.join("\n\n"))
+        }
+        Value::Array(mut parts) => {
+            if let Some(prefix) = prefix {
+                parts.insert(0, json!({"type": "text", "text": prefix}));
+            }
+            if let Some(suffix) = suffix {
+                parts.push(json!({"type": "text", "text": suffix}));
+            }
+            Value::Array(parts)
+        }
+        other => other,
+    }
+}
+
+// --------------------------------------------------
+// Document parts (see `document_part`) become Anthropic document blocks.
+// Invalid ones were rejected by the handler and are passed through
+// --------------------------------------------------
+fn with_document_blocks(content: Value) -> Value {
+    let Value::Array(parts) = content else {
+        return content;
+    };
+
+    let parts = parts
+        .into_iter()
+        .map(|part| {
+            let Ok(Some(doc)) = document_part(&part) else {
+                return part;
+            };
+            let mut block = json!({
+                "type": "document",
+                "source": {"type": "base64", "media_type": doc.media_type, "data": doc.data}
+            });
+            if let Some(title) = doc.title {
+                block["title"] = json!(title);
+            }
+            block
+        })
+        .collect();
+    Value::Array(parts)
+}
+
+// --------------------------------------------------
+// Anthropic has no participant name field, so a message `name`
+// is kept as a "[name]: " prefix on its (first) text content
+// --------------------------------------------------
+fn content_with_name(content: &Option<Value>, name: &Option<String>) -> Value {
+    let content = content.clone().unwrap_or(Value::String("".to_string()));
+    let Some(name) = name else {
+        return content;
+    };
+
+    match content {
+        Value::String(text) => Value::String(format!("[{}]: {}", name, text)),
+        Value::Array(mut parts) => {
+            match parts
+                .iter_mut()
+                .find(|p| p.get("type").and_then(|t| t.as_str()) == Some("text"))
+            {
+                Some(part) => {
+                    let text = part.get("text").and_then(|t| t.as_str()).unwrap_or("");
+                    part["text"] = Value::String(format!("[{}]: {}", name, text));
+                }
+                None => parts.insert(0, json!({"type": "text", "text": format!("[{}]:", name)})),
+            }
+            Value::Array(parts)
+        }
+        other => other,
+    }
+}
+
+// Some SDK/endpoint paths return the Anthropic message wrapped in a `body` field, either
+// as an object or as a JSON string, instead of at the top level. Returns the message
+// itself either way; responses with a top-level `content` are used as they are
+pub fn unwrap_nested_body(resp: &Value) -> Cow<'_, Value> {
+    if resp.get("content").is_some() {
+        return Cow::Borrowed(resp);
+    }
+    match resp.get("body") {
+        Some(body @ Value::Object(_)) => Cow::Borrowed(body),
+        Some(Value::String(text)) => serde_json::from_str(text)
+            .map(Cow::Owned)
+            .unwrap_or(Cow::Borrowed(resp)),
+        _ => Cow::Borrowed(resp),
+    }
+}
+
+// --------------------------------------------------
+// Convert Bedrock JSON → OpenAIResponse
+// --------------------------------------------------
+pub fn bedrock_to_openai(resp: &Value, model: &str, state: &AppState) -> OpenAIResponse {
+    let resp = unwrap_nested_body(resp);
+    let resp = resp.as_ref();
+
+    // OpenAI content parts in Bedrock block order
+    let mut content_parts: Vec<Value> = Vec::new();
+    let mut tool_calls: Vec<ToolCall> = Vec::new();
+    let mut finish_reason = "stop";
+    let mut reasoning_chars = 0;
+
+    if let Some(content_array) = resp.get("content").and_then(|c| c.as_array()) {
+        for block in content_array {
+            if let Some(block_type) = block.get("type").and_then(|t| t.as_str()) {
+                match block_type {
+                    "text" => {
+                        if let Some(text) = block.get("text").and_then(|t| t.as_str()) {
+                            content_parts.push(json!({"type": "text", "text": text}));
+                        }
+                    }
+                    "thinking" => {
+                        if let Some(thinking) = block.get("thinking").and_then(|t| t.as_str()) {
+                            reasoning_chars += thinking.len();
+                        }
+                    }
+                    "image" => {
+                        let source = block.get("source");
+                        if let (Some(media_type), Some(data)) = (
+                            source
+                                .and_then(|s| s.get("media_type"))
+                                .and_then(|m| m.as_str()),
+                            source.and_then(|s| s.get("data")).and_then(|d| d.as_str()),
+                        ) {
+                            content_parts.push(json!({
+                                "type": "image_url",
+                                "image_url": {"url": format!("data:{};base64,{}", media_type, data)}
+                            }));
+                        }
+                    }
+                    "tool_use" => {
+                        if let (Some(name), Some(input)) = (
+                            block.get("name").and_then(|n| n.as_str()),
+                            block.get("input"),
+                        ) {
+                            tool_calls.push(ToolCall {
+                                id: tool_call_id(block.get("id"), tool_calls.len()),
+                                r#type: "function".to_string(),
+                                function: FunctionCall {
+                                    name: name.to_string(),
+                                    arguments: serde_json::to_string(input)
+                                        .unwrap_or_else(|_| "{}".to_string()),
+                                },
+                            });
+                            finish_reason = "tool_calls";
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if resp.get("stop_reason").and_then(|r| r.as_str()) == Some("max_tokens") {
+        finish_reason = "length";
+    }
+
+    let (prompt_tokens, cached_tokens) = prompt_usage(
+        resp.get("usage"),
+        "input_tokens",
+        "cache_read_input_tokens",
+        "cache_creation_input_tokens",
+    );
+
+    let completion_tokens = resp
+        .get("usage")
+        .and_then(|u| u.get("output_tokens"))
+        .and_then(|t| t.as_i64())
+        .unwrap_or(0) as i32;
+
+    let message = OpenAIMessage {
+        role: "assistant".to_string(),
+        content: if content_parts.is_empty() {
+            None
+        } else if state.response_content_parts {
+            Some(Value::Array(content_parts))
+        } else {
+            // Plain string mode can only carry text, images need content parts
+            let text: Vec<&str> = content_parts
+                .iter()
+                .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+                .collect();
+            if text.is_empty() {
+                None
+            } else {
+                Some(Value::String(text.concat()))
+            }
+        },
+        tool_calls: if tool_calls.is_empty() {
+            None
+        } else {
+            Some(tool_calls)
+        },
+        tool_call_id: None,
+        name: None,
+    };
+
+    let choice = OpenAIChoice {
+        index: 0,
+        message,
+        logprobs: None,
+        finish_reason: finish_reason.to_string(),
+        x_bedrock_stop_reason: None,
+    };
+
+    OpenAIResponse {
+        id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
+        object: CHAT_COMPLETION_OBJECT.to_string(),
+        created: state.clock.now(),
+        model: model.to_string(),
+        choices: vec![choice],
+        usage: OpenAIUsage::new(
+            prompt_tokens,
+            completion_tokens,
+            cached_tokens,
+            reasoning_chars,
+        ),
+    }
+}
+
+// OpenAI id for the tool call at `tool_index`: Bedrock's block id, or one derived from the
+// position when the block has none, so streamed chunks and the assembled response agree
+fn tool_call_id(bedrock_id: Option<&Value>, tool_index: usize) -> String {
+    bedrock_id
+        .and_then(|id| id.as_str())
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("call_{}", tool_index))
+}
+
+// OpenAI `tool_choice`, in any of its forms
+#[derive(Clone, Debug, PartialEq)]
+pub enum ToolChoice {
+    Auto,
+    None,
+    Required,
+    // {"type": "function", "function": {"name": ...}}
+    Function(String),
+}
+
+// Parse `tool_choice`; None when absent.
+// Err carries a client-facing message for a 400 response
+pub fn parse_tool_choice(req: &OpenAIRequest) -> Result<Option<ToolChoice>, String> {
+    let Some(choice) = &req.tool_choice else {
+        return Ok(None);
+    };
+
+    let parsed = match choice {
+        Value::String(mode) => match mode.as_str() {
+            "auto" => ToolChoice::Auto,
+            "none" => ToolChoice::None,
+            "required" => ToolChoice::Required,
+            other => {
+                return Err(format!(
+                    "Invalid tool_choice '{}'; expected auto, none, required or a function object",
+                    other
+                ))
+            }
+        },
+        Value::Object(object) => {
+            let name = object
+                .get("function")
+                .and_then(|f| f.get("name"))
+                .and_then(|n| n.as_str())
+                .filter(|_| object.get("type").and_then(|t| t.as_str()) == Some("function"));
+            match name {
+                Some(name) => ToolChoice::Function(name.to_string()),
+                None => {
+                    return Err(
+                        "tool_choice object must have type 'function' and a function.name"
+                            .to_string(),
+                    )
+                }
+            }
+        }
+        _ => return Err("tool_choice must be a string or an object".to_string()),
+    };
+
+    if let ToolChoice::Function(name) = &parsed {
+        let declared = req.tools.iter().flatten().any(|t| t.function.name == *name);
+        if !declared {
+            return Err(format!(
+                "tool_choice names function '{}', which is not in tools",
+                name
+            ));
+        }
+    }
+    Ok(Some(parsed))
+}
+
+// Whether `tool_choice` forces the model to call a tool ("required" or a named function)
+pub fn forces_tool_call(req: &OpenAIRequest) -> bool {
+    matches!(
+        parse_tool_choice(req),
+        Ok(Some(ToolChoice::Required | ToolChoice::Function(_)))
+    )
+}
+
+// Source of `created` timestamps. Fixed (FIXED_CREATED_TIMESTAMP) stamps every response
+// and chunk with the same time, so full responses can be compared against golden files
+#[derive(Clone, Copy, Default)]
+pub enum Clock {
+    #[default]
+    System,
+    Fixed(i64),
+}
+
+impl Clock {
+    // Unix seconds
+    pub fn now(self) -> i64 {
+        match self {
+            Clock::System => chrono::Utc::now().timestamp(),
+            Clock::Fixed(timestamp) => timestamp,
+        }
+    }
+}
+
+// Per-stream state carried across Bedrock streaming chunks
+#[derive(Default)]
+pub struct StreamState {
+    // `chatcmpl-` id repeated on every chunk of the response, usage chunk included
+    pub id: String,
+    // OpenAI choices[].index this stream's chunks are emitted under
+    pub choice_index: i32,
+    // Bedrock content block index → OpenAI tool_calls index
+    pub tool_call_indices: HashMap<u64, usize>,
+    pub stop_reason: Option<String>,
+    // Set by the first emitted chunk, which always carries `role`; later chunks never
+    // repeat it, even if Bedrock sends another message_start
+    pub role_sent: bool,
+    // Token counts reported by the stream, for the final usage chunk
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    // Prompt cache reads, when reported, and the length of streamed thinking text
+    pub cached_tokens: Option<i32>,
+    pub reasoning_chars: usize,
+    // tool_choice forces a tool call, so the stream opens with it rather than text
+    pub tool_call_forced: bool,
+    // Model name reported on every chunk
+    pub model: String,
+    // Upstream `Date` header time (CREATED_FROM_UPSTREAM_DATE); `clock` time when unset
+    pub upstream_created: Option<i64>,
+    pub clock: Clock,
+    // VALIDATE_TOOL_ARGUMENTS: check each tool call's arguments parse once its block ends
+    pub validate_tool_arguments: bool,
+    // Arguments streamed so far, by OpenAI tool_calls index (only while validating)
+    pub tool_arguments: HashMap<usize, String>,
+    // Set when a finished tool call's arguments weren't valid JSON; taken by the handler
+    pub invalid_tool_arguments: Option<String>,
+}
+
+impl StreamState {
+    pub fn created(&self) -> i64 {
+        self.upstream_created.unwrap_or_else(|| self.clock.now())
+    }
+
+    fn push_tool_arguments(&mut self, tool_index: usize, partial: &str) {
+        if self.validate_tool_arguments {
+            self.tool_arguments
+                .entry(tool_index)
+                .or_default()
+                .push_str(partial);
+        }
+    }
+
+    // The content block at `block_index` has ended: if it was a tool call, check its
+    // assembled arguments parse as JSON (no fragments at all counts as `{}`)
+    fn finish_tool_arguments(&mut self, block_index: u64) {
+        if !self.validate_tool_arguments {
+            return;
+        }
+        let Some(&tool_index) = self.tool_call_indices.get(&block_index) else {
+            return;
+        };
+
+        let arguments = self.tool_arguments.remove(&tool_index).unwrap_or_default();
+        if arguments.is_empty() {
+            return;
+        }
+        if let Err(e) = serde_json::from_str::<Value>(&arguments) {
+            tracing::warn!(
+                "⚠️ Streamed arguments for tool call {} are not valid JSON ({}): {}",
+                tool_index,
+                e,
+                arguments
+            );
+            self.invalid_tool_arguments = Some(format!(
+                "Arguments streamed for tool call {} are not valid JSON ({}); the upstream \
+                 response may have been truncated",
+                tool_index, e
+            ));
+        }
+    }
+}
+
+// --------------------------------------------------
+// Convert Bedrock streaming chunk → OpenAI streaming chunk
+// --------------------------------------------------
+pub fn bedrock_chunk_to_openai(
+    chunk: &Value,
+    stream_state: &mut StreamState,
+) -> Option<OpenAIStreamResponse> {
+    let mut delta = serde_json::Map::new();
+    let mut finish_reason = None;
+
+    match chunk.get("type").and_then(|t| t.as_str()) {
+        Some("message_start") => {
+            // Role-only opener, matching OpenAI's first chunk. With a forced tool
+            // call the role rides on the first tool_calls delta instead
+            if !stream_state.role_sent && !stream_state.tool_call_forced {
+                delta.insert("content".to_string(), Value::String(String::new()));
+            }
+            let usage = chunk.get("message").and_then(|m| m.get("usage"));
+            if usage.is_some_and(|u| u.get("input_tokens").is_some()) {
+                (stream_state.prompt_tokens, stream_state.cached_tokens) = prompt_usage(
+                    usage,
+                    "input_tokens",
+                    "cache_read_input_tokens",
+                    "cache_creation_input_tokens",
+                );
+            }
+        }
+        Some("content_block_start") => {
+            let block = chunk.get("content_block");
+            let block_index = chunk.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+            // A repeated start for a block already seen must not open a second tool call
+            let is_tool_use =
+                block.and_then(|b| b.get("type")).and_then(|t| t.as_str()) == Some("tool_use");
+            if is_tool_use && !stream_state.tool_call_indices.contains_key(&block_index) {
+                let tool_index = stream_state.tool_call_indices.len();
+                stream_state
+                    .tool_call_indices
+                    .insert(block_index, tool_index);
+
+                if !stream_state.role_sent {
+                    delta.insert("content".to_string(), Value::Null);
+                }
+                delta.insert(
+                    "tool_calls".to_string(),
+                    json!([{
+                        "index": tool_index,
+                        "id": tool_call_id(block.and_then(|b| b.get("id")), tool_index),
+                        "type": "function",
+                        "function": {
+                            "name": block.and_then(|b| b.get("name")).and_then(|v| v.as_str()).unwrap_or(""),
+                            "arguments": ""
+                        }
+                    }]),
+                );
+            }
+        }
+        Some("content_block_delta") => {
+            let block_delta = chunk.get("delta");
+            match block_delta
+                .and_then(|d| d.get("type"))
+                .and_then(|t| t.as_str())
+            {
+                Some("input_json_delta") => {
+                    let block_index = chunk.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+                    if let Some(&tool_index) = stream_state.tool_call_indices.get(&block_index) {
+                        let partial = block_delta
+                            .and_then(|d| d.get("partial_json"))
+                            .and_then(|p| p.as_str())
+                            .unwrap_or("");
+                        stream_state.push_tool_arguments(tool_index, partial);
+                        delta.insert(
+                            "tool_calls".to_string(),
+                            json!([{
+                                "index": tool_index,
+                                "function": { "arguments": partial }
+                            }]),
+                        );
+                    }
+                }
+                Some("thinking_delta") => {
+                    if let Some(thinking) = block_delta
+                        .and_then(|d| d.get("thinking"))
+                        .and_then(|t| t.as_str())
+                    {
+                        stream_state.reasoning_chars += thinking.len();
+                    }
+                }
+                _ => {
+                    if let Some(text) = block_delta
+                        .and_then(|d| d.get("text"))
+                        .and_then(|t| t.as_str())
+                    {
+                        delta.insert("content".to_string(), Value::String(text.to_string()));
+                    }
+                }
+            }
+        }
+        Some("content_block_stop") => {
+            let block_index = chunk.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+            stream_state.finish_tool_arguments(block_index);
+        }
+        Some("message_delta") => {
+            if let Some(reason) = chunk
+                .get("delta")
+                .and_then(|d| d.get("stop_reason"))
+                .and_then(|r| r.as_str())
+            {
+                stream_state.stop_reason = Some(reason.to_string());
+            }
+            if let Some(output_tokens) = chunk
+                .get("usage")
+                .and_then(|u| u.get("output_tokens"))
+                .and_then(|t| t.as_i64())
+            {
+                stream_state.completion_tokens = output_tokens as i32;
+            }
+        }
+        Some("message_stop") => {
+            let reason = match stream_state.stop_reason.as_deref() {
+                Some("tool_use") => "tool_calls",
+                Some("max_tokens") => "length",
+                _ => "stop",
+            };
+            finish_reason = Some(reason.to_string());
+        }
+        _ => {}
+    }
+
+    if delta.is_empty() && finish_reason.is_none() {
+        return None;
+    }
+
+    ensure_role(&mut delta, stream_state);
+
+    Some(stream_response(
+        delta,
+        stream_state.choice_index,
+        finish_reason,
+        stream_state,
+    ))
+}
+
+// OpenAI clients expect `role` on the first delta of a stream and never again
+fn ensure_role(delta: &mut serde_json::Map<String, Value>, stream_state: &mut StreamState) {
+    if !stream_state.role_sent {
+        delta.insert("role".to_string(), Value::String("assistant".to_string()));
+        stream_state.role_sent = true;
+    }
+}
+
+fn stream_response(
+    delta: serde_json::Map<String, Value>,
+    index: i32,
+    finish_reason: Option<String>,
+    stream_state: &StreamState,
+) -> OpenAIStreamResponse {
+    OpenAIStreamResponse {
+        id: stream_state.id.clone(),
+        object: CHAT_COMPLETION_CHUNK_OBJECT.to_string(),
+        created: stream_state.created(),
+        model: stream_state.model.clone(),
+        choices: vec![OpenAIStreamChoice {
+            delta: Value::Object(delta),
+            index,
+            finish_reason,
+        }],
+        usage: None,
+    }
+}
+
+// Final chunk carrying token usage (stream_options.include_usage)
+pub fn usage_chunk(stream_state: &StreamState, model: &str) -> OpenAIStreamResponse {
+    OpenAIStreamResponse {
+        id: stream_state.id.clone(),
+        object: CHAT_COMPLETION_CHUNK_OBJECT.to_string(),
+        created: stream_state.created(),
+        model: model.to_string(),
+        choices: vec![],
+        usage: Some(OpenAIUsage::new(
+            stream_state.prompt_tokens,
+            stream_state.completion_tokens,
+            stream_state.cached_tokens,
+            stream_state.reasoning_chars,
+        )),
+    }
+}
+
+// --------------------------------------------------
+// Flatten OpenAI message content (string or parts array) to plain text
+// --------------------------------------------------
+pub fn content_to_text(content: &Option<Value>) -> String {
+    match content {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Some(other) => other.to_string(),
+        None => String::new(),
+    }
+}
+
+// Empty strings are dropped: Anthropic rejects them with a 400
+fn non_empty_stops(stop: &Option<Value>) -> Vec<String> {
+    match stop {
+        Some(Value::String(s)) if !s.is_empty() => vec![s.clone()],
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(|s| s.as_str())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Stop sequences to send, cut to MAX_STOP_SEQUENCES. Only reached over the limit with
+// STOP_SEQUENCES_MODE=truncate; otherwise the handler has already rejected the request
+fn stop_sequences(stop: &Option<Value>, state: &AppState) -> Vec<String> {
+    let mut stops = non_empty_stops(stop);
+    if stops.len() > state.max_stop_sequences {
+        tracing::info!(
+            "✂️ Truncating {} stop sequences to MAX_STOP_SEQUENCES={}",
+            stops.len(),
+            state.max_stop_sequences
+        );
+        stops.truncate(state.max_stop_sequences);
+    }
+    stops
+}
+
+// Err carries a client-facing message for a 400 response
+pub fn validate_stop_sequences(req: &OpenAIRequest, state: &AppState) -> Result<(), String> {
+    let count = non_empty_stops(&req.stop).len();
+    if count > state.max_stop_sequences && !state.truncate_stop_sequences {
+        return Err(format!(
+            "'stop' has {} sequences, exceeding the limit of {} for this model",
+            count, state.max_stop_sequences
+        ));
+    }
+    Ok(())
+}
+
+// Titan completionReason → OpenAI finish_reason
+fn titan_finish_reason(reason: &str) -> &'static str {
+    match reason {
+        "LENGTH" => "length",
+        "CONTENT_FILTERED" => "content_filter",
+        _ => "stop",
+    }
+}
+
+// --------------------------------------------------
+// Convert OpenAIRequest → Titan Text JSON
+// --------------------------------------------------
+pub fn openai_to_titan(req: &OpenAIRequest, state: &AppState) -> Value {
+    let mut sections: Vec<String> = Vec::new();
+
+    for m in &req.messages {
+        let text = match &m.name {
+            Some(name) => format!("[{}]: {}", name, content_to_text(&m.content)),
+            None => content_to_text(&m.content),
+        };
+        match m.role.as_str() {
+            "system" | "developer" => sections.push(text),
+            "assistant" => sections.push(format!("Bot: {}", text)),
+            _ => sections.push(format!("User: {}", text)),
+        }
+    }
+    sections.push("Bot:".to_string());
+
+    let mut config = json!({
+        "maxTokenCount": effective_max_tokens(req, state),
+        "temperature": req.temperature.unwrap_or(DEFAULT_TEMPERATURE),
+    });
+
+    if let Some(top_p) = req.top_p {
+        config["topP"] = json!(top_p);
+    }
+
+    let stops = stop_sequences(&req.stop, state);
+    if !stops.is_empty() {
+        config["stopSequences"] = json!(stops);
+    }
+
+    json!({
+        "inputText": sections.join("\n"),
+        "textGenerationConfig": config,
+    })
+}
+
+// --------------------------------------------------
+// Convert Titan Text JSON → OpenAIResponse
+// --------------------------------------------------
+pub fn titan_to_openai(resp: &Value, model: &str, state: &AppState) -> OpenAIResponse {
+    let result = resp
+        .get("results")
+        .and_then(|r| r.as_array())
+        .and_then(|r| r.first());
+
+    let text = result
+        .and_then(|r| r.get("outputText"))
+        .and_then(|t| t.as_str())
+        .unwrap_or("")
+        .trim_start()
+        .to_string();
+
+    let finish_reason = result
+        .and_then(|r| r.get("completionReason"))
+        .and_then(|c| c.as_str())
+        .map(titan_finish_reason)
+        .unwrap_or("stop");
+
+    let prompt_tokens = resp
+        .get("inputTextTokenCount")
+        .and_then(|t| t.as_i64())
+        .unwrap_or(0) as i32;
+
+    let completion_tokens = result
+        .and_then(|r| r.get("tokenCount"))
+        .and_then(|t| t.as_i64())
+        .unwrap_or(0) as i32;
+
+    OpenAIResponse {
+        id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
+        object: CHAT_COMPLETION_OBJECT.to_string(),
+        created: state.clock.now(),
+        model: model.to_string(),
+        choices: vec![OpenAIChoice {
+            index: 0,
+            message: OpenAIMessage {
+                role: "assistant".to_string(),
+                content: Some(Value::String(text)),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            },
+            logprobs: None,
+            finish_reason: finish_reason.to_string(),
+            x_bedrock_stop_reason: None,
+        }],
+        usage: OpenAIUsage::new(prompt_tokens, completion_tokens, None, 0),
+    }
+}
+
+// --------------------------------------------------
+// Convert Titan streaming chunk → OpenAI streaming chunk
+// --------------------------------------------------
+pub fn titan_chunk_to_openai(
+    chunk: &Value,
+    stream_state: &mut StreamState,
+) -> Option<OpenAIStreamResponse> {
+    let mut delta = serde_json::Map::new();
+
+    // Token counts arrive on the final chunk
+    if let Some(metrics) = chunk.get("amazon-bedrock-invocationMetrics") {
+        if let Some(t) = metrics.get("inputTokenCount").and_then(|t| t.as_i64()) {
+            stream_state.prompt_tokens = t as i32;
+        }
+        if let Some(t) = metrics.get("outputTokenCount").and_then(|t| t.as_i64()) {
+            stream_state.completion_tokens = t as i32;
+        }
+    }
+
+    if let Some(text) = chunk.get("outputText").and_then(|t| t.as_str()) {
+        if !text.is_empty() {
+            delta.insert("content".to_string(), Value::String(text.to_string()));
+        }
+    }
+
+    let finish_reason = chunk
+        .get("completionReason")
+        .and_then(|c| c.as_str())
+        .map(|c| titan_finish_reason(c).to_string());
+
+    if delta.is_empty() && finish_reason.is_none() {
+        return None;
+    }
+
+    ensure_role(&mut delta, stream_state);
+
+    // Titan tags each streamed result with its own index
+    let index = chunk
+        .get("index")
+        .and_then(|i| i.as_i64())
+        .map(|i| i as i32)
+        .unwrap_or(stream_state.choice_index);
+
+    Some(stream_response(delta, index, finish_reason, stream_state))
+}
+
+// Cohere finish_reason → OpenAI finish_reason
+fn cohere_finish_reason(reason: &str) -> &'static str {
+    match reason {
+        "MAX_TOKENS" => "length",
+        "ERROR_TOXIC" => "content_filter",
+        _ => "stop",
+    }
+}
+
+// --------------------------------------------------
+// Convert OpenAIRequest → Cohere Command JSON
+// --------------------------------------------------
+pub fn openai_to_cohere(req: &OpenAIRequest, state: &AppState) -> Value {
+    let mut sections: Vec<String> = Vec::new();
+
+    for m in &req.messages {
+        let text = match &m.name {
+            Some(name) => format!("[{}]: {}", name, content_to_text(&m.content)),
+            None => content_to_text(&m.content),
+        };
+        match m.role.as_str() {
+            "system" | "developer" => sections.push(text),
+            "assistant" => sections.push(format!("CHATBOT: {}", text)),
+            _ => sections.push(format!("USER: {}", text)),
+        }
+    }
+    sections.push("CHATBOT:".to_string());
+
+    let mut payload = json!({
+        "prompt": sections.join("\n"),
+        "max_tokens": effective_max_tokens(req, state),
+        "temperature": req.temperature.unwrap_or(DEFAULT_TEMPERATURE),
+        // Cohere only streams when asked to in the body
+        "stream": req.stream == Some(true),
+    });
+
+    if let Some(top_p) = req.top_p {
+        payload["p"] = json!(top_p);
+    }
+
+    let stops = stop_sequences(&req.stop, state);
+    if !stops.is_empty() {
+        payload["stop_sequences"] = json!(stops);
+    }
+
+    if requests_logprobs(req) {
+        payload["return_likelihoods"] = json!("GENERATION");
+    }
+
+    payload
+}
+
+// Cohere `token_likelihoods` → OpenAI `logprobs`. Cohere reports no alternative tokens,
+// so every `top_logprobs` list is empty
+fn cohere_logprobs(generation: &Value) -> Option<Value> {
+    let tokens = generation.get("token_likelihoods")?.as_array()?;
+    let content: Vec<Value> = tokens
+        .iter()
+        .filter_map(|t| {
+            let token = t.get("token")?.as_str()?;
+            Some(json!({
+                "token": token,
+                "logprob": t.get("likelihood")?.as_f64()?,
+                "bytes": token.as_bytes(),
+                "top_logprobs": [],
+            }))
+        })
+        .collect();
+    Some(json!({ "content": content }))
+}
+
+// --------------------------------------------------
+// Convert Cohere Command JSON → OpenAIResponse
+// --------------------------------------------------
+pub fn cohere_to_openai(resp: &Value, model: &str, state: &AppState) -> OpenAIResponse {
+    let generation = resp
+        .get("generations")
+        .and_then(|g| g.as_array())
+        .and_then(|g| g.first())
+        .unwrap_or(&Value::Null);
+
+    let text = generation
+        .get("text")
+        .and_then(|t| t.as_str())
+        .unwrap_or("")
+        .trim_start()
+        .to_string();
+
+    let finish_reason = generation
+        .get("finish_reason")
+        .and_then(|r| r.as_str())
+        .map(cohere_finish_reason)
+        .unwrap_or("stop");
+
+    // Cohere reports no token counts; likelihoods, when requested, cover every output token
+    let completion_tokens = generation
+        .get("token_likelihoods")
+        .and_then(|t| t.as_array())
+        .map_or(0, |t| t.len()) as i32;
+
+    OpenAIResponse {
+        id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
+        object: CHAT_COMPLETION_OBJECT.to_string(),
+        created: state.clock.now(),
+        model: model.to_string(),
+        choices: vec![OpenAIChoice {
+            index: 0,
+            message: OpenAIMessage {
+                role: "assistant".to_string(),
+                content: Some(Value::String(text)),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            },
+            logprobs: cohere_logprobs(generation),
+            finish_reason: finish_reason.to_string(),
+            x_bedrock_stop_reason: None,
+        }],
+        usage: OpenAIUsage::new(0, completion_tokens, None, 0),
+    }
+}
+
+// --------------------------------------------------
+// Convert Cohere streaming chunk → OpenAI streaming chunk
+// --------------------------------------------------
+pub fn cohere_chunk_to_openai(
+    chunk: &Value,
+    stream_state: &mut StreamState,
+) -> Option<OpenAIStreamResponse> {
+    let mut delta = serde_json::Map::new();
+
+    // Token counts arrive on the final chunk
+    if let Some(metrics) = chunk.get("amazon-bedrock-invocationMetrics") {
+        if let Some(t) = metrics.get("inputTokenCount").and_then(|t| t.as_i64()) {
+            stream_state.prompt_tokens = t as i32;
+        }
+        if let Some(t) = metrics.get("outputTokenCount").and_then(|t| t.as_i64()) {
+            stream_state.completion_tokens = t as i32;
+        }
+    }
+
+    let finished = chunk.get("is_finished").and_then(|f| f.as_bool()) == Some(true);
+    // The final chunk repeats the whole response, so only earlier chunks carry text
+    if let Some(text) = chunk
+        .get("text")
+        .and_then(|t| t.as_str())
+        .filter(|_| !finished)
+    {
+        if !text.is_empty() {
+            delta.insert("content".to_string(), Value::String(text.to_string()));
+        }
+    }
+
+    let finish_reason = chunk
+        .get("finish_reason")
+        .and_then(|r| r.as_str())
+        .filter(|_| finished)
+        .map(|r| cohere_finish_reason(r).to_string());
+
+    if delta.is_empty() && finish_reason.is_none() {
+        return None;
+    }
+
+    ensure_role(&mut delta, stream_state);
+    Some(stream_response(
+        delta,
+        stream_state.choice_index,
+        finish_reason,
+        stream_state,
+    ))
+}
+
+// --------------------------------------------------
+// Converse API (USE_CONVERSE_API=true): one schema for every model family
+// --------------------------------------------------
+
+// OpenAI content (string or parts) → Converse content blocks
+fn converse_content_blocks(content: &Value) -> Vec<Value> {
+    match content {
+        Value::String(text) if text.is_empty() => Vec::new(),
+        Value::String(text) => vec![json!({"text": text})],
+        Value::Array(parts) => parts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, part)| converse_content_block(i, part))
+            .collect(),
+        Value::Null => Vec::new(),
+        other => vec![json!({"text": other.to_string()})],
+    }
+}
+
+// Converse block for the OpenAI content part at index `i`, None for parts it can't carry
+fn converse_content_block(i: usize, part: &Value) -> Option<Value> {
+    match part.get("type").and_then(|t| t.as_str()) {
+        Some("text") => Some(json!({"text": part.get("text")?.as_str()?})),
+        Some("image_url") => {
+            // Only inline data URLs can be forwarded: data:image/<format>;base64,<data>
+            let url = part.get("image_url")?.get("url")?.as_str()?;
+            let (meta, data) = url.strip_prefix("data:image/")?.split_once(',')?;
+            let format = meta.strip_suffix(";base64")?;
+            Some(json!({"image": {"format": format, "source": {"bytes": data}}}))
+        }
+        Some("file" | "document") => {
+            // Validated by the handler: a PDF, so the format is the media subtype
+            let doc = document_part(part).ok()??;
+            let format = doc.media_type.strip_prefix("application/")?;
+            Some(json!({"document": {
+                "format": format,
+                "name": converse_document_name(doc.title, i),
+                "source": {"bytes": doc.data},
+            }}))
+        }
+        _ => None,
+    }
+}
+
+// Converse requires a document name of letters, digits, single spaces, hyphens, parentheses
+// and square brackets: the filename with anything else (its extension dot included) turned
+// into spaces, or "document-<part index>" without one
+fn converse_document_name(title: Option<&str>, index: usize) -> String {
+    let name = title
+        .unwrap_or_default()
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || "-()[]".contains(c) => c,
+            _ => ' ',
+        })
+        .collect::<String>();
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if name.is_empty() {
+        format!("document-{}", index)
+    } else {
+        name
+    }
+}
+
+// Converse stopReason → OpenAI finish_reason
+fn converse_finish_reason(reason: &str) -> &'static str {
+    match reason {
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        "content_filtered" | "guardrail_intervened" => "content_filter",
+        _ => "stop",
+    }
+}
+
+// --------------------------------------------------
+// Convert OpenAIRequest → Converse JSON
+// --------------------------------------------------
+pub fn openai_to_converse(req: &OpenAIRequest, state: &AppState) -> Value {
+    let mut system: Vec<Value> = Vec::new();
+    let mut messages: Vec<Value> = Vec::new();
+
+    for m in &req.messages {
+        let (role, blocks) = match m.role.as_str() {
+            "system" | "developer" => {
+                system.extend(converse_content_blocks(
+                    m.content.as_ref().unwrap_or(&Value::Null),
+                ));
+                continue;
+            }
+            "tool" => (
+                "user",
+                vec![json!({
+                    "toolResult": {
+                        "toolUseId": m.tool_call_id.clone().unwrap_or_else(|| "tool_call_1".to_string()),
+                        "content": [{"text": content_to_text(&m.content)}],
+                    }
+                })],
+            ),
+            "assistant" => {
+                let mut blocks = converse_content_blocks(&content_with_name(&m.content, &m.name));
+                for tc in m.tool_calls.iter().flatten() {
+                    blocks.push(json!({
+                        "toolUse": {
+                            "toolUseId": tc.id,
+                            "name": tc.function.name,
+                            "input": serde_json::from_str::<Value>(&tc.function.arguments)
+                                .unwrap_or_else(|_| json!({})),
+                        }
+                    }));
+                }
+                ("assistant", blocks)
+            }
+            _ => (
+                "user",
+                converse_content_blocks(&content_with_name(&m.content, &m.name)),
+            ),
+        };
+
+        if blocks.is_empty() {
+            continue;
+        }
+
+        // Converse requires alternating roles, so consecutive turns
+        // from the same side (e.g. parallel tool results) are merged
+        match messages.last_mut() {
+            Some(last) if last["role"] == role => {
+                if let Some(content) = last["content"].as_array_mut() {
+                    content.extend(blocks);
+                }
+            }
+            _ => messages.push(json!({"role": role, "content": blocks})),
+        }
+    }
+
+    let mut inference_config = json!({
+        "maxTokens": effective_max_tokens(req, state),
+        "temperature": req.temperature.unwrap_or(DEFAULT_TEMPERATURE),
+    });
+    if let Some(top_p) = req.top_p {
+        inference_config["topP"] = json!(top_p);
+    }
+    let stops = stop_sequences(&req.stop, state);
+    if !stops.is_empty() {
+        inference_config["stopSequences"] = json!(stops);
+    }
+
+    let mut payload = json!({
+        "messages": messages,
+        "inferenceConfig": inference_config,
+    });
+
+    system.extend(json_mode_instruction(req).map(|text| json!({ "text": text })));
+    if !system.is_empty() {
+        payload["system"] = Value::Array(system);
+    }
+
+    // Converse has no "none", so tools are left out instead
+    let tool_choice = match parse_tool_choice(req).ok().flatten() {
+        Some(ToolChoice::None) => None,
+        Some(ToolChoice::Required) => Some(json!({"any": {}})),
+        Some(ToolChoice::Function(name)) => Some(json!({"tool": {"name": name}})),
+        Some(ToolChoice::Auto) | None => Some(json!({"auto": {}})),
+    };
+
+    if let (Some(tools), Some(tool_choice)) = (&req.tools, tool_choice) {
+        if !tools.is_empty() {
+            let tools: Vec<Value> = tools
+                .iter()
+                .map(|t| {
+                    let mut spec = json!({
+                        "name": t.function.name,
+                        "inputSchema": {
+                            "json": t.function.parameters.clone()
+                                .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+                        },
+                    });
+                    // Converse rejects an empty description, so omit it instead
+                    if let Some(description) =
+                        t.function.description.as_deref().filter(|d| !d.is_empty())
+                    {
+                        spec["description"] = json!(description);
+                    }
+                    json!({"toolSpec": spec})
+                })
+                .collect();
+            payload["toolConfig"] = json!({"tools": tools, "toolChoice": tool_choice});
+        }
+    }
+
+    payload
+}
+
+// --------------------------------------------------
+// Convert Converse JSON → OpenAIResponse
+// --------------------------------------------------
+pub fn converse_to_openai(resp: &Value, model: &str, state: &AppState) -> OpenAIResponse {
+    let mut content_parts: Vec<Value> = Vec::new();
+    let mut tool_calls: Vec<ToolCall> = Vec::new();
+    let mut reasoning_chars = 0;
+
+    let blocks = resp
+        .get("output")
+        .and_then(|o| o.get("message"))
+        .and_then(|m| m.get("content"))
+        .and_then(|c| c.as_array());
+
+    for block in blocks.into_iter().flatten() {
+        if let Some(text) = block.get("text").and_then(|t| t.as_str()) {
+            content_parts.push(json!({"type": "text", "text": text}));
+        } else if let Some(reasoning) = block
+            .get("reasoningContent")
+            .and_then(|r| r.get("reasoningText"))
+            .and_then(|r| r.get("text"))
+            .and_then(|t| t.as_str())
+        {
+            reasoning_chars += reasoning.len();
+        } else if let Some(tool_use) = block.get("toolUse") {
+            tool_calls.push(ToolCall {
+                id: tool_call_id(tool_use.get("toolUseId"), tool_calls.len()),
+                r#type: "function".to_string(),
+                function: FunctionCall {
+                    name: tool_use
+                        .get("name")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("unknown_tool")
+                        .to_string(),
+                    arguments: serde_json::to_string(tool_use.get("input").unwrap_or(&json!({})))
+                        .unwrap_or_else(|_| "{}".to_string()),
+                },
+            });
+        }
+    }
+
+    let finish_reason = resp
+        .get("stopReason")
+        .and_then(|r| r.as_str())
+        .map(converse_finish_reason)
+        .unwrap_or("stop");
+
+    let usage = resp.get("usage");
+    let (prompt_tokens, cached_tokens) = prompt_usage(
+        usage,
+        "inputTokens",
+        "cacheReadInputTokens",
+        "cacheWriteInputTokens",
+    );
+    let completion_tokens = usage
+        .and_then(|u| u.get("outputTokens"))
+        .and_then(|t| t.as_i64())
+        .unwrap_or(0) as i32;
+
+    let content = if content_parts.is_empty() {
+        None
+    } else if state.response_content_parts {
+        Some(Value::Array(content_parts))
+    } else {
+        let text: Vec<&str> = content_parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect();
+        Some(Value::String(text.concat()))
+    };
+
+    OpenAIResponse {
+        id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
+        object: CHAT_COMPLETION_OBJECT.to_string(),
+        created: state.clock.now(),
+        model: model.to_string(),
+        choices: vec![OpenAIChoice {
+            index: 0,
+            message: OpenAIMessage {
+                role: "assistant".to_string(),
+                content,
+                tool_calls: if tool_calls.is_empty() {
+                    None
+                } else {
+                    Some(tool_calls)
+                },
+                tool_call_id: None,
+                name: None,
+            },
+            logprobs: None,
+            finish_reason: finish_reason.to_string(),
+            x_bedrock_stop_reason: None,
         }],
-    })
+        usage: OpenAIUsage::new(
+            prompt_tokens,
+            completion_tokens,
+            cached_tokens,
+            reasoning_chars,
+        ),
+    }
+}
+
+// --------------------------------------------------
+// Convert Converse stream event → OpenAI streaming chunk
+//
+// `event` is keyed by its event type, e.g. {"contentBlockDelta": {...}}
+// --------------------------------------------------
+pub fn converse_chunk_to_openai(
+    event: &Value,
+    stream_state: &mut StreamState,
+) -> Option<OpenAIStreamResponse> {
+    let mut delta = serde_json::Map::new();
+    let mut finish_reason = None;
+
+    if event.get("messageStart").is_some() {
+        if !stream_state.role_sent && !stream_state.tool_call_forced {
+            delta.insert("content".to_string(), Value::String(String::new()));
+        }
+    } else if let Some(start) = event.get("contentBlockStart") {
+        let block_index = start
+            .get("contentBlockIndex")
+            .and_then(|i| i.as_u64())
+            .unwrap_or(0);
+        let tool_use = start
+            .get("start")
+            .and_then(|s| s.get("toolUse"))
+            .filter(|_| !stream_state.tool_call_indices.contains_key(&block_index));
+        if let Some(tool_use) = tool_use {
+            let tool_index = stream_state.tool_call_indices.len();
+            stream_state
+                .tool_call_indices
+                .insert(block_index, tool_index);
+
+            if !stream_state.role_sent {
+                delta.insert("content".to_string(), Value::Null);
+            }
+            delta.insert(
+                "tool_calls".to_string(),
+                json!([{
+                    "index": tool_index,
+                    "id": tool_call_id(tool_use.get("toolUseId"), tool_index),
+                    "type": "function",
+                    "function": {
+                        "name": tool_use.get("name").and_then(|v| v.as_str()).unwrap_or(""),
+                        "arguments": ""
+                    }
+                }]),
+            );
+        }
+    } else if let Some(block_delta) = event.get("contentBlockDelta") {
+        let block_index = block_delta
+            .get("contentBlockIndex")
+            .and_then(|i| i.as_u64())
+            .unwrap_or(0);
+        let inner = block_delta.get("delta");
+
+        if let Some(text) = inner.and_then(|d| d.get("text")).and_then(|t| t.as_str()) {
+            delta.insert("content".to_string(), Value::String(text.to_string()));
+        } else if let Some(reasoning) = inner
+            .and_then(|d| d.get("reasoningContent"))
+            .and_then(|r| r.get("text"))
+            .and_then(|t| t.as_str())
+        {
+            stream_state.reasoning_chars += reasoning.len();
+        } else if let Some(partial) = inner
+            .and_then(|d| d.get("toolUse"))
+            .and_then(|t| t.get("input"))
+            .and_then(|i| i.as_str())
+        {
+            if let Some(&tool_index) = stream_state.tool_call_indices.get(&block_index) {
+                stream_state.push_tool_arguments(tool_index, partial);
+                delta.insert(
+                    "tool_calls".to_string(),
+                    json!([{
+                        "index": tool_index,
+                        "function": { "arguments": partial }
+                    }]),
+                );
+            }
+        }
+    } else if let Some(stop) = event.get("contentBlockStop") {
+        let block_index = stop
+            .get("contentBlockIndex")
+            .and_then(|i| i.as_u64())
+            .unwrap_or(0);
+        stream_state.finish_tool_arguments(block_index);
+    } else if let Some(stop) = event.get("messageStop") {
+        let reason = stop
+            .get("stopReason")
+            .and_then(|r| r.as_str())
+            .unwrap_or("end_turn");
+        stream_state.stop_reason = Some(reason.to_string());
+        finish_reason = Some(converse_finish_reason(reason).to_string());
+    } else if let Some(usage) = event.get("metadata").and_then(|m| m.get("usage")) {
+        if usage.get("inputTokens").is_some() {
+            (stream_state.prompt_tokens, stream_state.cached_tokens) = prompt_usage(
+                Some(usage),
+                "inputTokens",
+                "cacheReadInputTokens",
+                "cacheWriteInputTokens",
+            );
+        }
+        if let Some(output_tokens) = usage.get("outputTokens").and_then(|t| t.as_i64()) {
+            stream_state.completion_tokens = output_tokens as i32;
+        }
+    }
+
+    if delta.is_empty() && finish_reason.is_none() {
+        return None;
+    }
+
+    ensure_role(&mut delta, stream_state);
+
+    Some(stream_response(
+        delta,
+        stream_state.choice_index,
+        finish_reason,
+        stream_state,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, text: &str) -> OpenAIMessage {
+        OpenAIMessage {
+            role: role.to_string(),
+            content: Some(json!(text)),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        }
+    }
+
+    fn roles(messages: &[OpenAIMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.role.as_str()).collect()
+    }
+
+    #[test]
+    fn truncate_history_keeps_system_and_latest_turn() {
+        let long = "x".repeat(400);
+        let mut messages = vec![
+            message("system", "be brief"),
+            message("user", &long),
+            message("assistant", &long),
+            message("user", &long),
+            message("assistant", &long),
+            message("user", "latest question"),
+        ];
+        let before = messages.len();
+
+        let dropped = truncate_history(&mut messages, 150);
+
+        assert_eq!(roles(&messages), ["system", "user"]);
+        assert_eq!(content_to_text(&messages[1].content), "latest question");
+        // The count reported in x-truncated-messages is the number of messages removed
+        assert_eq!(dropped, before - messages.len());
+    }
+
+    #[test]
+    fn truncate_history_drops_assistant_and_tool_tail_with_its_turn() {
+        let long = "x".repeat(400);
+        let mut messages = vec![
+            message("system", "be brief"),
+            message("user", "look it up"),
+            message("assistant", &long),
+            message("tool", &long),
+            message("assistant", &long),
+            message("user", "and now?"),
+            message("assistant", "short"),
+            message("user", "latest question"),
+        ];
+        let before = messages.len();
+
+        let dropped = truncate_history(&mut messages, 100);
+
+        // Removing the first user message takes its assistant/tool replies with it,
+        // so the kept history still starts on a user turn
+        assert_eq!(roles(&messages), ["system", "user", "assistant", "user"]);
+        assert_eq!(content_to_text(&messages[1].content), "and now?");
+        assert_eq!(dropped, 4);
+        assert_eq!(dropped, before - messages.len());
+    }
+
+    #[test]
+    fn truncate_history_leaves_history_within_budget() {
+        let mut messages = vec![message("user", "hi"), message("assistant", "hello")];
+        assert_eq!(truncate_history(&mut messages, 1000), 0);
+        assert_eq!(messages.len(), 2);
+    }
+
+    // Stream one Anthropic tool_use block with `fragments` as its argument deltas and
+    // return the invalid-arguments message it left, if any
+    fn stream_tool_call(fragments: &[&str]) -> Option<String> {
+        let mut stream_state = StreamState {
+            validate_tool_arguments: true,
+            ..Default::default()
+        };
+        let mut events = vec![json!({
+            "type": "content_block_start",
+            "index": 1,
+            "content_block": {"type": "tool_use", "id": "toolu_1", "name": "lookup", "input": {}}
+        })];
+        events.extend(fragments.iter().map(|fragment| {
+            json!({
+                "type": "content_block_delta",
+                "index": 1,
+                "delta": {"type": "input_json_delta", "partial_json": fragment}
+            })
+        }));
+        events.push(json!({"type": "content_block_stop", "index": 1}));
+
+        for event in &events {
+            bedrock_chunk_to_openai(event, &mut stream_state);
+        }
+        assert!(stream_state.tool_arguments.is_empty());
+        stream_state.invalid_tool_arguments
+    }
+
+    #[test]
+    fn accepts_tool_arguments_split_across_deltas() {
+        assert_eq!(
+            stream_tool_call(&[r#"{"city": "Par"#, r#"is", "days""#, ": 3}"]),
+            None
+        );
+    }
+
+    #[test]
+    fn flags_truncated_tool_arguments() {
+        let message = stream_tool_call(&[r#"{"city": "Par"#, r#"is", "days""#]).unwrap();
+        assert!(message.starts_with("Arguments streamed for tool call 0 are not valid JSON"));
+    }
+
+    #[test]
+    fn accepts_tool_call_without_argument_deltas() {
+        assert_eq!(stream_tool_call(&[]), None);
+    }
+
+    // Parse a chat request carrying `max_tokens` (omitted when `value` is None)
+    fn parse_max_tokens(value: Option<Value>) -> Result<Option<u32>, String> {
+        let mut body = json!({"messages": [{"role": "user", "content": "hi"}]});
+        if let Some(value) = value {
+            body["max_tokens"] = value;
+        }
+        serde_json::from_value::<OpenAIRequest>(body)
+            .map(|req| req.max_tokens)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn whole_number_accepts_integers_and_whole_floats() {
+        assert_eq!(parse_max_tokens(Some(json!(2))), Ok(Some(2)));
+        assert_eq!(parse_max_tokens(Some(json!(2.0))), Ok(Some(2)));
+        assert_eq!(parse_max_tokens(Some(json!(u32::MAX))), Ok(Some(u32::MAX)));
+    }
+
+    #[test]
+    fn whole_number_rejects_fractional_negative_and_oversized_values() {
+        for value in [
+            json!(2.5),
+            json!(-1),
+            json!(-2.0),
+            json!(u32::MAX as u64 + 1),
+        ] {
+            let error = parse_max_tokens(Some(value.clone())).unwrap_err();
+            assert!(
+                error.contains("expected a non-negative whole number"),
+                "{}: {}",
+                value,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn whole_number_treats_missing_and_null_as_unset() {
+        assert_eq!(parse_max_tokens(None), Ok(None));
+        assert_eq!(parse_max_tokens(Some(Value::Null)), Ok(None));
+    }
+
+    #[test]
+    fn detects_plain_openai_bodies() {
+        let payload = json!({
+            "model": "gpt-4",
+            "messages": [{"role": "user", "content": "hi"}],
+        });
+        let LegacyPayload::OpenAI(req) = transform_payload(payload).unwrap() else {
+            panic!("expected an OpenAI-style body");
+        };
+        assert_eq!(req.model.as_deref(), Some("gpt-4"));
+        assert_eq!(req.messages.len(), 1);
+    }
+
+    #[test]
+    fn keeps_anthropic_native_bodies() {
+        let payload = json!({
+            "max_tokens": 256,
+            "messages": [{"role": "user", "content": [{"type": "text", "text": "hi"}]}],
+        });
+        let LegacyPayload::Native(payload) = transform_payload(payload).unwrap() else {
+            panic!("expected an Anthropic-native body");
+        };
+        assert_eq!(payload["anthropic_version"], "bedrock-2023-05-31");
+        assert_eq!(payload["max_tokens"], 256);
+        assert_eq!(payload["messages"][0]["content"][0]["text"], "hi");
+    }
+
+    #[test]
+    fn converse_maps_document_parts_to_document_blocks() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "messages": [{"role": "user", "content": [
+                {"type": "text", "text": "Summarize"},
+                {"type": "file", "file": {
+                    "filename": "q3_report.pdf",
+                    "file_data": "data:application/pdf;base64,JVBERi0xLjQK",
+                }},
+                {"type": "document", "source": {
+                    "type": "base64",
+                    "media_type": "application/pdf",
+                    "data": "JVBERi0xLjQK",
+                }},
+            ]}]
+        }))
+        .unwrap();
+
+        let payload = openai_to_converse(&req, &crate::state::test_state());
+        let content = &payload["messages"][0]["content"];
+        assert_eq!(content[0], json!({"text": "Summarize"}));
+        assert_eq!(
+            content[1],
+            json!({"document": {
+                "format": "pdf",
+                "name": "q3 report pdf",
+                "source": {"bytes": "JVBERi0xLjQK"},
+            }})
+        );
+        assert_eq!(content[2]["document"]["name"], "document-2");
+    }
+
+    fn ceiling_request(max_tokens: u32, reasoning_effort: Option<&str>) -> OpenAIRequest {
+        serde_json::from_value(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "max_tokens": max_tokens,
+            "reasoning_effort": reasoning_effort,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn max_tokens_above_the_ceiling_enables_the_extended_output_beta() {
+        let mut state = crate::state::test_state();
+        state.enforce_max_tokens_ceiling = true;
+        state.extended_output_beta = true;
+        let model_id = "anthropic.claude-3-5-sonnet-20240620-v1:0";
+
+        let payload = openai_to_bedrock(&ceiling_request(4096, None), model_id, &state);
+        assert!(payload.get("anthropic_beta").is_none());
+
+        let payload = openai_to_bedrock(&ceiling_request(6000, None), model_id, &state);
+        assert_eq!(
+            payload["anthropic_beta"],
+            json!(["max-tokens-3-5-sonnet-2024-07-15"])
+        );
+        assert_eq!(payload["max_tokens"], 6000);
+
+        // Beyond the beta's own ceiling the request is clamped to it
+        let payload = openai_to_bedrock(&ceiling_request(10000, None), model_id, &state);
+        assert_eq!(payload["max_tokens"], 8192);
+
+        // Without the beta the ceiling is a clamp
+        state.extended_output_beta = false;
+        let payload = openai_to_bedrock(&ceiling_request(6000, None), model_id, &state);
+        assert!(payload.get("anthropic_beta").is_none());
+        assert_eq!(payload["max_tokens"], 4096);
+    }
+
+    #[test]
+    fn ceiling_clamp_keeps_the_thinking_budget_below_max_tokens() {
+        let mut state = crate::state::test_state();
+        state.enforce_max_tokens_ceiling = true;
+        state.reasoning_budget_high = 70000;
+        let model_id = "anthropic.claude-3-7-sonnet-20250219-v1:0";
+
+        // 70000 + 1000 is clamped to the 64000 ceiling, under the 70000 budget
+        let payload = openai_to_bedrock(&ceiling_request(1000, Some("high")), model_id, &state);
+        assert_eq!(payload["max_tokens"], 64000);
+        assert_eq!(payload["thinking"]["budget_tokens"], 32000);
+
+        // A budget already below the clamped max_tokens is kept
+        state.reasoning_budget_high = 16384;
+        let payload = openai_to_bedrock(&ceiling_request(100000, Some("high")), model_id, &state);
+        assert_eq!(payload["max_tokens"], 64000);
+        assert_eq!(payload["thinking"]["budget_tokens"], 16384);
+    }
+
+    #[test]
+    fn cohere_returns_token_logprobs_when_requested() {
+        let state = crate::state::test_state();
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "logprobs": true,
+        }))
+        .unwrap();
+        let payload = openai_to_cohere(&req, &state);
+        assert_eq!(payload["prompt"], "USER: hi\nCHATBOT:");
+        assert_eq!(payload["return_likelihoods"], "GENERATION");
+
+        let resp = json!({
+            "generations": [{
+                "finish_reason": "COMPLETE",
+                "text": " Hello!",
+                "token_likelihoods": [
+                    {"token": " Hello", "likelihood": -0.25},
+                    {"token": "!", "likelihood": -1.5},
+                ],
+            }],
+        });
+        let response = cohere_to_openai(&resp, "command", &state);
+        let choice = &response.choices[0];
+        assert_eq!(choice.message.content, Some(json!("Hello!")));
+        assert_eq!(choice.finish_reason, "stop");
+        assert_eq!(
+            choice.logprobs,
+            Some(json!({"content": [
+                {"token": " Hello", "logprob": -0.25, "bytes": [32, 72, 101, 108, 108, 111], "top_logprobs": []},
+                {"token": "!", "logprob": -1.5, "bytes": [33], "top_logprobs": []},
+            ]}))
+        );
+        assert_eq!(response.usage.completion_tokens, 2);
+
+        // Without likelihoods (logprobs not requested) the field stays null
+        let resp = json!({"generations": [{"finish_reason": "MAX_TOKENS", "text": "Hi"}]});
+        let response = cohere_to_openai(&resp, "command", &state);
+        assert_eq!(response.choices[0].logprobs, None);
+        assert_eq!(response.choices[0].finish_reason, "length");
+    }
+
+    #[test]
+    fn converse_stream_maps_text_tool_use_and_usage() {
+        // ConverseStream events as Bedrock sends them for a reply that then calls a tool
+        let events = [
+            json!({"messageStart": {"role": "assistant"}}),
+            json!({"contentBlockDelta": {"contentBlockIndex": 0, "delta": {"text": "Let me check."}}}),
+            json!({"contentBlockStop": {"contentBlockIndex": 0}}),
+            json!({"contentBlockStart": {"contentBlockIndex": 1, "start": {
+                "toolUse": {"toolUseId": "tooluse_abc", "name": "get_weather"}
+            }}}),
+            json!({"contentBlockDelta": {"contentBlockIndex": 1, "delta": {
+                "toolUse": {"input": "{\"city\":"}
+            }}}),
+            json!({"contentBlockDelta": {"contentBlockIndex": 1, "delta": {
+                "toolUse": {"input": "\"Paris\"}"}
+            }}}),
+            json!({"contentBlockStop": {"contentBlockIndex": 1}}),
+            json!({"messageStop": {"stopReason": "tool_use"}}),
+            json!({"metadata": {
+                "usage": {"inputTokens": 25, "outputTokens": 14, "totalTokens": 39},
+                "metrics": {"latencyMs": 512}
+            }}),
+        ];
+        let mut stream_state = StreamState {
+            id: "chatcmpl-converse".to_string(),
+            ..Default::default()
+        };
+        let chunks: Vec<Value> = events
+            .iter()
+            .filter_map(|event| converse_chunk_to_openai(event, &mut stream_state))
+            .map(|chunk| serde_json::to_value(chunk).unwrap())
+            .collect();
+
+        let deltas: Vec<&Value> = chunks.iter().map(|c| &c["choices"][0]["delta"]).collect();
+        assert_eq!(deltas[0]["role"], "assistant");
+        assert_eq!(deltas[0]["content"], "");
+        assert_eq!(deltas[1]["content"], "Let me check.");
+        assert!(deltas[1].get("role").is_none());
+
+        assert_eq!(deltas[2]["tool_calls"][0]["index"], 0);
+        assert_eq!(deltas[2]["tool_calls"][0]["id"], "tooluse_abc");
+        assert_eq!(
+            deltas[2]["tool_calls"][0]["function"]["name"],
+            "get_weather"
+        );
+        assert_eq!(
+            deltas[3]["tool_calls"][0]["function"]["arguments"],
+            "{\"city\":"
+        );
+        assert_eq!(
+            deltas[4]["tool_calls"][0]["function"]["arguments"],
+            "\"Paris\"}"
+        );
+
+        assert_eq!(chunks.len(), 6);
+        assert_eq!(chunks[5]["choices"][0]["finish_reason"], "tool_calls");
+        assert!(chunks.iter().all(|c| c["id"] == "chatcmpl-converse"));
+
+        // The metadata event yields no chunk of its own; its counts feed the usage chunk
+        assert_eq!(stream_state.prompt_tokens, 25);
+        assert_eq!(stream_state.completion_tokens, 14);
+        let usage = serde_json::to_value(usage_chunk(&stream_state, "model")).unwrap();
+        assert_eq!(usage["usage"]["prompt_tokens"], 25);
+        assert_eq!(usage["usage"]["completion_tokens"], 14);
+        assert_eq!(usage["usage"]["total_tokens"], 39);
+    }
+
+    fn request(body: Value) -> OpenAIRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn default_max_tokens_applies_only_when_the_client_omits_it() {
+        let model = "anthropic.claude-3-haiku-20240307-v1:0";
+        let hi = json!([{"role": "user", "content": "hi"}]);
+        let omitted = request(json!({"messages": hi}));
+        let explicit = request(json!({"messages": hi, "max_tokens": 100}));
+
+        let state = crate::state::test_state();
+        assert_eq!(state.default_max_tokens, 512);
+        assert_eq!(
+            openai_to_bedrock(&omitted, model, &state)["max_tokens"],
+            512
+        );
+
+        let state = crate::state::test_state_with(&[("DEFAULT_MAX_TOKENS", "4096")])
+            .ok()
+            .unwrap();
+        assert_eq!(
+            openai_to_bedrock(&omitted, model, &state)["max_tokens"],
+            4096
+        );
+        assert_eq!(
+            openai_to_bedrock(&explicit, model, &state)["max_tokens"],
+            100
+        );
+    }
+
+    #[test]
+    fn max_tokens_shrinks_to_fit_a_large_prompt_in_the_context_window() {
+        let mut state = crate::state::test_state();
+        state.model_context_window = Some(8000);
+        let large = request(json!({
+            "messages": [{"role": "user", "content": "lorem ipsum ".repeat(2000)}],
+            "max_tokens": 7900,
+        }));
+        let input: usize = large.messages.iter().map(estimate_message_tokens).sum();
+        assert!(input + 7900 > 8000);
+
+        let max_tokens = effective_max_tokens(&large, &state);
+        assert_eq!(max_tokens as usize, 8000 - input);
+
+        let small = request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "max_tokens": 7900,
+        }));
+        assert_eq!(effective_max_tokens(&small, &state), 7900);
+
+        // Without a window the requested value is sent as is
+        state.model_context_window = None;
+        assert_eq!(effective_max_tokens(&large, &state), 7900);
+    }
+
+    #[test]
+    fn response_content_parts_mode_returns_text_parts() {
+        let resp = json!({
+            "content": [
+                {"type": "text", "text": "First, "},
+                {"type": "tool_use", "id": "toolu_1", "name": "lookup", "input": {"q": "x"}},
+                {"type": "text", "text": "then more."},
+            ],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 3, "output_tokens": 4},
+        });
+
+        let state = crate::state::test_state();
+        let message = &bedrock_to_openai(&resp, "model", &state).choices[0].message;
+        assert_eq!(message.content, Some(json!("First, then more.")));
+
+        let state = crate::state::test_state_with(&[("RESPONSE_CONTENT_PARTS", "true")])
+            .ok()
+            .unwrap();
+        let message = &bedrock_to_openai(&resp, "model", &state).choices[0].message;
+        assert_eq!(
+            message.content,
+            Some(json!([
+                {"type": "text", "text": "First, "},
+                {"type": "text", "text": "then more."},
+            ]))
+        );
+        assert_eq!(message.tool_calls.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn stream_chunks_carry_the_choice_index() {
+        let anthropic = [
+            json!({"type": "message_start", "message": {"usage": {"input_tokens": 3}}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Hi"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}, "usage": {"output_tokens": 1}}),
+        ];
+        let converse = [
+            json!({"messageStart": {"role": "assistant"}}),
+            json!({"contentBlockDelta": {"contentBlockIndex": 0, "delta": {"text": "Hi"}}}),
+            json!({"messageStop": {"stopReason": "end_turn"}}),
+        ];
+
+        for choice_index in [0, 2] {
+            let mut indices = Vec::new();
+            let mut stream_state = StreamState {
+                choice_index,
+                ..Default::default()
+            };
+            indices.extend(
+                anthropic
+                    .iter()
+                    .filter_map(|event| bedrock_chunk_to_openai(event, &mut stream_state))
+                    .map(|chunk| chunk.choices[0].index),
+            );
+            let mut stream_state = StreamState {
+                choice_index,
+                ..Default::default()
+            };
+            indices.extend(
+                converse
+                    .iter()
+                    .filter_map(|event| converse_chunk_to_openai(event, &mut stream_state))
+                    .map(|chunk| chunk.choices[0].index),
+            );
+            assert_eq!(indices, [choice_index; 5]);
+        }
+    }
+
+    fn native_payload(body: Value) -> serde_json::Map<String, Value> {
+        match transform_payload(body) {
+            Ok(LegacyPayload::Native(payload)) => payload,
+            Ok(LegacyPayload::OpenAI(_)) => panic!("expected an Anthropic-native payload"),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn legacy_prompt_arrays_are_joined_into_one_user_turn() {
+        let payload = native_payload(json!({
+            "prompt": ["Summarize this.", "Keep it short."],
+            "max_tokens_to_sample": 300,
+        }));
+        assert_eq!(
+            payload["messages"],
+            json!([{"role": "user", "content": "Summarize this.\n\nKeep it short."}])
+        );
+        assert_eq!(payload["max_tokens"], 300);
+        assert_eq!(payload["anthropic_version"], "bedrock-2023-05-31");
+
+        for (body, error) in [
+            (
+                json!({"prompt": ["ok", 1]}),
+                "`prompt` array must contain only strings",
+            ),
+            (
+                json!({"prompt": 7}),
+                "`prompt` must be a string or an array of strings",
+            ),
+            (json!({"messages": "hi"}), "`messages` must be an array"),
+            (
+                json!(["not", "an", "object"]),
+                "Request body must be a JSON object",
+            ),
+        ] {
+            assert_eq!(transform_payload(body).err().unwrap(), error);
+        }
+    }
+
+    #[test]
+    fn legacy_structured_messages_pass_through() {
+        let messages = json!([
+            {"role": "user", "content": [
+                {"type": "text", "text": "What is in this image?"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}},
+            ]},
+            {"role": "assistant", "content": [{"type": "text", "text": "A cat."}]},
+            {"role": "user", "content": "Thanks"},
+        ]);
+        let payload = native_payload(json!({
+            "messages": messages,
+            "max_tokens_to_sample": 100,
+        }));
+        assert_eq!(payload["messages"], messages);
+        assert_eq!(payload["max_tokens"], 100);
+        assert!(!payload.contains_key("max_tokens_to_sample"));
+    }
+
+    #[test]
+    fn user_and_metadata_become_anthropic_metadata_user_id() {
+        let model = "anthropic.claude-3-haiku-20240307-v1:0";
+        let state = crate::state::test_state();
+        let hi = json!([{"role": "user", "content": "hi"}]);
+        let payload = |body: Value| {
+            openai_to_bedrock(&request(body), model, &state)
+                .get("metadata")
+                .cloned()
+        };
+
+        assert_eq!(payload(json!({"messages": hi})), None);
+        assert_eq!(
+            payload(json!({"messages": hi, "user": "user-123"})),
+            Some(json!({"user_id": "user-123"}))
+        );
+        assert_eq!(
+            payload(json!({
+                "messages": hi,
+                "user": "user-123",
+                "metadata": {"user_id": "tenant-9", "session": "s1"},
+            })),
+            Some(json!({"user_id": "tenant-9"}))
+        );
+    }
+
+    #[test]
+    fn only_the_first_stream_chunk_carries_the_role() {
+        let text = |text: &str| json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": text}});
+        // Bedrock's first event is a content delta, and a stray message_start arrives later
+        let events = [
+            text("Hel"),
+            json!({"type": "message_start", "message": {"usage": {"input_tokens": 3}}}),
+            text("lo"),
+            json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}, "usage": {"output_tokens": 2}}),
+        ];
+        let mut stream_state = StreamState::default();
+        let deltas: Vec<Value> = events
+            .iter()
+            .filter_map(|event| bedrock_chunk_to_openai(event, &mut stream_state))
+            .map(|chunk| chunk.choices[0].delta.clone())
+            .collect();
+
+        assert_eq!(deltas[0], json!({"role": "assistant", "content": "Hel"}));
+        assert_eq!(deltas[1], json!({"content": "lo"}));
+        assert!(deltas[1..].iter().all(|delta| delta.get("role").is_none()));
+
+        // Titan streams have no message_start at all
+        let mut stream_state = StreamState::default();
+        let chunk =
+            titan_chunk_to_openai(&json!({"outputText": "Hi", "index": 0}), &mut stream_state)
+                .unwrap();
+        assert_eq!(
+            chunk.choices[0].delta,
+            json!({"role": "assistant", "content": "Hi"})
+        );
+    }
+
+    #[test]
+    fn empty_stop_entries_are_not_sent_to_anthropic() {
+        let model = "anthropic.claude-3-haiku-20240307-v1:0";
+        let state = crate::state::test_state();
+        let hi = json!([{"role": "user", "content": "hi"}]);
+        let stops = |stop: Value| {
+            openai_to_bedrock(
+                &request(json!({"messages": hi, "stop": stop})),
+                model,
+                &state,
+            )
+            .get("stop_sequences")
+            .cloned()
+        };
+
+        assert_eq!(stops(json!([""])), None);
+        assert_eq!(stops(json!("")), None);
+        assert_eq!(stops(json!(["", "END", ""])), Some(json!(["END"])));
+        assert_eq!(stops(json!("END")), Some(json!(["END"])));
+
+        // Legacy /invoke bodies are cleaned the same way
+        let payload = native_payload(json!({
+            "messages": hi,
+            "max_tokens": 10,
+            "stop_sequences": [""],
+        }));
+        assert!(!payload.contains_key("stop_sequences"));
+    }
+
+    #[test]
+    fn message_names_become_content_prefixes() {
+        let req = request(json!({"messages": [
+            {"role": "user", "name": "alice", "content": "Is the build green?"},
+            {"role": "assistant", "name": "ci-bot", "content": "Yes."},
+            {"role": "user", "name": "bob", "content": [
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+            ]},
+            {"role": "assistant", "content": "Looks fine."},
+            {"role": "user", "name": "carol", "content": [{"type": "text", "text": "Ship it"}]},
+        ]}));
+        let payload = openai_to_bedrock(
+            &req,
+            "anthropic.claude-3-haiku-20240307-v1:0",
+            &crate::state::test_state(),
+        );
+        let messages = &payload["messages"];
+
+        assert_eq!(messages[0]["content"], "[alice]: Is the build green?");
+        assert_eq!(messages[1]["content"], "[ci-bot]: Yes.");
+        // A name on a message without text gets a text part of its own
+        assert_eq!(
+            messages[2]["content"][0],
+            json!({"type": "text", "text": "[bob]:"})
+        );
+        assert_eq!(messages[2]["content"].as_array().unwrap().len(), 2);
+        assert_eq!(messages[3]["content"], "Looks fine.");
+        assert_eq!(messages[4]["content"][0]["text"], "[carol]: Ship it");
+    }
+
+    #[test]
+    fn developer_messages_join_the_system_prompt() {
+        let req = request(json!({"messages": [
+            {"role": "system", "content": "You are terse."},
+            {"role": "developer", "content": "Answer in French."},
+            {"role": "user", "content": "hi"},
+        ]}));
+        let payload = openai_to_bedrock(
+            &req,
+            "anthropic.claude-3-haiku-20240307-v1:0",
+            &crate::state::test_state(),
+        );
+        assert_eq!(payload["system"], "You are terse.\n\nAnswer in French.");
+        assert_eq!(
+            payload["messages"],
+            json!([{"role": "user", "content": "hi"}])
+        );
+    }
+
+    #[test]
+    fn function_role_maps_to_tool_and_unknown_roles_are_refused() {
+        let mut req = request(json!({"messages": [
+            {"role": "user", "content": "weather?"},
+            {"role": "function", "name": "get_weather", "content": "sunny"},
+        ]}));
+        normalize_roles(&mut req.messages).unwrap();
+        assert_eq!(req.messages[1].role, "tool");
+        assert_eq!(req.messages[1].tool_call_id.as_deref(), Some("get_weather"));
+        let payload = openai_to_bedrock(
+            &req,
+            "anthropic.claude-3-haiku-20240307-v1:0",
+            &crate::state::test_state(),
+        );
+        assert_eq!(payload["messages"][1]["role"], "user");
+        assert_eq!(payload["messages"][1]["content"][0]["type"], "tool_result");
+        assert_eq!(
+            payload["messages"][1]["content"][0]["tool_use_id"],
+            "get_weather"
+        );
+
+        let mut req = request(json!({"messages": [
+            {"role": "user", "content": "hi"},
+            {"role": "narrator", "content": "meanwhile"},
+        ]}));
+        let error = normalize_roles(&mut req.messages).unwrap_err();
+        assert!(
+            error.starts_with("Unsupported role 'narrator' in messages[1]"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn reasoning_effort_levels_map_to_thinking_budgets() {
+        let sonnet = "us.anthropic.claude-sonnet-4-20250514-v1:0";
+        let haiku = "anthropic.claude-3-haiku-20240307-v1:0";
+        let state = crate::state::test_state_with(&[
+            ("REASONING_BUDGET_LOW", "2000"),
+            ("REASONING_BUDGET_MEDIUM", "5000"),
+        ])
+        .ok()
+        .unwrap();
+        let with_effort = |effort: &str| {
+            request(json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 1000,
+                "temperature": 0.5,
+                "reasoning_effort": effort,
+            }))
+        };
+
+        for (effort, budget) in [("low", 2000), ("medium", 5000), ("high", 16384)] {
+            let payload = openai_to_bedrock(&with_effort(effort), sonnet, &state);
+            assert_eq!(
+                payload["thinking"],
+                json!({"type": "enabled", "budget_tokens": budget})
+            );
+            // Extended thinking needs temperature 1 and room above the budget
+            assert_eq!(payload["temperature"], 1);
+            assert_eq!(payload["max_tokens"], budget + 1000);
+        }
+
+        // Unknown levels and models without extended thinking are ignored
+        for (effort, model) in [("extreme", sonnet), ("high", haiku)] {
+            let payload = openai_to_bedrock(&with_effort(effort), model, &state);
+            assert!(payload.get("thinking").is_none());
+            assert_eq!(payload["temperature"], 0.5);
+            assert_eq!(payload["max_tokens"], 1000);
+        }
+    }
+
+    #[test]
+    fn user_prompt_prefix_and_suffix_wrap_the_latest_user_message() {
+        let model = "anthropic.claude-3-haiku-20240307-v1:0";
+        let state = crate::state::test_state_with(&[
+            ("USER_PROMPT_PREFIX", "Answer concisely."),
+            ("USER_PROMPT_SUFFIX", "Reply in English."),
+        ])
+        .ok()
+        .unwrap();
+
+        let req = request(json!({"messages": [
+            {"role": "system", "content": "You are terse"},
+            {"role": "user", "content": "first"},
+            {"role": "assistant", "content": "ok"},
+            {"role": "user", "content": "second"},
+        ]}));
+        let payload = openai_to_bedrock(&req, model, &state);
+        assert_eq!(payload["system"], "You are terse");
+        assert_eq!(payload["messages"][0]["content"], "first");
+        assert_eq!(
+            payload["messages"][2]["content"],
+            "Answer concisely.\n\nsecond\n\nReply in English."
+        );
+
+        // Tool results are not user prompts
+        let req = request(json!({"messages": [
+            {"role": "user", "content": "weather?"},
+            {"role": "assistant", "content": null, "tool_calls": [{
+                "id": "call_1", "type": "function",
+                "function": {"name": "get_weather", "arguments": "{}"},
+            }]},
+            {"role": "tool", "tool_call_id": "call_1", "content": "sunny"},
+        ]}));
+        let payload = openai_to_bedrock(&req, model, &state);
+        assert_eq!(
+            payload["messages"][0]["content"],
+            "Answer concisely.\n\nweather?\n\nReply in English."
+        );
+        assert_eq!(payload["messages"][2]["content"][0]["content"], "sunny");
+
+        // Content arrays get separate text parts; the image part is left as it was
+        let image =
+            json!({"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}});
+        let req = request(json!({"messages": [
+            {"role": "user", "content": [{"type": "text", "text": "What is this?"}, image]},
+        ]}));
+        let payload = openai_to_bedrock(&req, model, &state);
+        assert_eq!(
+            payload["messages"][0]["content"],
+            json!([
+                {"type": "text", "text": "Answer concisely."},
+                {"type": "text", "text": "What is this?"},
+                image,
+                {"type": "text", "text": "Reply in English."},
+            ])
+        );
+    }
+
+    #[test]
+    fn text_response_format_is_accepted_as_a_no_op() {
+        let model = "anthropic.claude-3-haiku-20240307-v1:0";
+        let state = crate::state::test_state();
+        let hi = json!([{"role": "user", "content": "hi"}]);
+        let plain = request(json!({"messages": hi}));
+        let text = request(json!({"messages": hi, "response_format": {"type": "text"}}));
+
+        assert_eq!(validate_response_format(&text), Ok(()));
+        assert_eq!(
+            openai_to_bedrock(&text, model, &state),
+            openai_to_bedrock(&plain, model, &state)
+        );
+
+        let xml = request(json!({"messages": hi, "response_format": {"type": "xml"}}));
+        assert_eq!(
+            validate_response_format(&xml).unwrap_err(),
+            "Invalid response_format type 'xml'; expected one of text, json_object, json_schema"
+        );
+        let untyped = request(json!({"messages": hi, "response_format": "text"}));
+        assert!(validate_response_format(&untyped).is_err());
+    }
+
+    #[test]
+    fn assistant_text_and_tool_calls_become_text_then_tool_use_blocks() {
+        let model = "anthropic.claude-3-haiku-20240307-v1:0";
+        let state = crate::state::test_state();
+        let tool_call = |id: &str, city: &str| {
+            json!({
+                "id": id, "type": "function",
+                "function": {"name": "get_weather", "arguments": json!({"city": city}).to_string()},
+            })
+        };
+        let turn = |content: Value| {
+            request(json!({"messages": [
+                {"role": "user", "content": "Weather in Paris and Rome?"},
+                {"role": "assistant", "content": content, "tool_calls": [
+                    tool_call("call_1", "Paris"),
+                    tool_call("call_2", "Rome"),
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "sunny"},
+                {"role": "tool", "tool_call_id": "call_2", "content": "rainy"},
+            ]}))
+        };
+        let expected = json!([
+            {"type": "text", "text": "Let me check both."},
+            {"type": "tool_use", "id": "call_1", "name": "get_weather", "input": {"city": "Paris"}},
+            {"type": "tool_use", "id": "call_2", "name": "get_weather", "input": {"city": "Rome"}},
+        ]);
+
+        let payload = openai_to_bedrock(&turn(json!("Let me check both.")), model, &state);
+        assert_eq!(payload["messages"][1]["role"], "assistant");
+        assert_eq!(payload["messages"][1]["content"], expected);
+
+        let parts = json!([{"type": "text", "text": "Let me check both."}]);
+        let payload = openai_to_bedrock(&turn(parts), model, &state);
+        assert_eq!(payload["messages"][1]["content"], expected);
+
+        // Without text only the tool_use blocks are sent
+        let payload = openai_to_bedrock(&turn(Value::Null), model, &state);
+        assert_eq!(
+            payload["messages"][1]["content"],
+            json!(expected.as_array().unwrap()[1..])
+        );
+    }
+
+    #[test]
+    fn tool_choice_forms_map_to_anthropic_tool_choice() {
+        let model = "anthropic.claude-3-haiku-20240307-v1:0";
+        let state = crate::state::test_state();
+        let with_choice = |choice: Value| {
+            request(json!({
+                "messages": [{"role": "user", "content": "Weather in Paris?"}],
+                "tools": [{"type": "function", "function": {"name": "get_weather"}}],
+                "tool_choice": choice,
+            }))
+        };
+
+        let forced = json!({"type": "function", "function": {"name": "get_weather"}});
+        for (choice, parsed, anthropic) in [
+            (json!("auto"), ToolChoice::Auto, json!({"type": "auto"})),
+            (json!("none"), ToolChoice::None, json!({"type": "none"})),
+            (
+                json!("required"),
+                ToolChoice::Required,
+                json!({"type": "any"}),
+            ),
+            (
+                forced,
+                ToolChoice::Function("get_weather".to_string()),
+                json!({"type": "tool", "name": "get_weather"}),
+            ),
+        ] {
+            let req = with_choice(choice);
+            assert_eq!(parse_tool_choice(&req), Ok(Some(parsed)));
+            assert_eq!(
+                openai_to_bedrock(&req, model, &state)["tool_choice"],
+                anthropic
+            );
+        }
+
+        let omitted = request(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "tools": [{"type": "function", "function": {"name": "get_weather"}}],
+        }));
+        assert_eq!(parse_tool_choice(&omitted), Ok(None));
+        assert!(openai_to_bedrock(&omitted, model, &state)
+            .get("tool_choice")
+            .is_none());
+    }
+
+    #[test]
+    fn invalid_tool_choice_forms_are_rejected() {
+        let with_choice = |choice: Value| {
+            request(json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "tools": [{"type": "function", "function": {"name": "get_weather"}}],
+                "tool_choice": choice,
+            }))
+        };
+        for (choice, message) in [
+            (
+                json!("always"),
+                "Invalid tool_choice 'always'; expected auto, none, required or a function object",
+            ),
+            (
+                json!({"type": "function", "function": {"name": "get_time"}}),
+                "tool_choice names function 'get_time', which is not in tools",
+            ),
+            (
+                json!({"type": "tool", "name": "get_weather"}),
+                "tool_choice object must have type 'function' and a function.name",
+            ),
+            (json!(true), "tool_choice must be a string or an object"),
+        ] {
+            assert_eq!(
+                parse_tool_choice(&with_choice(choice)),
+                Err(message.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn too_many_stop_sequences_are_rejected_or_truncated() {
+        let model = "anthropic.claude-3-haiku-20240307-v1:0";
+        let with_stops = |stop: Value| {
+            request(json!({"messages": [{"role": "user", "content": "hi"}], "stop": stop}))
+        };
+
+        // Anthropic's own limit by default
+        let state = crate::state::test_state();
+        let stops: Vec<String> = (0..8192).map(|i| format!("STOP{}", i)).collect();
+        assert_eq!(
+            validate_stop_sequences(&with_stops(json!(stops)), &state).unwrap_err(),
+            "'stop' has 8192 sequences, exceeding the limit of 8191 for this model"
+        );
+        assert_eq!(
+            validate_stop_sequences(&with_stops(json!(stops[1..])), &state),
+            Ok(())
+        );
+
+        // Empty entries are never sent, so they don't count
+        let state = crate::state::test_state_with(&[("MAX_STOP_SEQUENCES", "2")])
+            .ok()
+            .unwrap();
+        assert_eq!(
+            validate_stop_sequences(&with_stops(json!(["a", "", "b"])), &state),
+            Ok(())
+        );
+        let over = with_stops(json!(["a", "", "b", "c"]));
+        assert_eq!(
+            validate_stop_sequences(&over, &state).unwrap_err(),
+            "'stop' has 3 sequences, exceeding the limit of 2 for this model"
+        );
+
+        let state = crate::state::test_state_with(&[
+            ("MAX_STOP_SEQUENCES", "2"),
+            ("STOP_SEQUENCES_MODE", "truncate"),
+        ])
+        .ok()
+        .unwrap();
+        assert_eq!(validate_stop_sequences(&over, &state), Ok(()));
+        assert_eq!(
+            openai_to_bedrock(&over, model, &state)["stop_sequences"],
+            json!(["a", "b"])
+        );
+    }
+
+    #[test]
+    fn cached_and_thinking_usage_fill_the_token_details() {
+        let state = crate::state::test_state();
+        let cached = json!({
+            "content": [
+                {"type": "thinking", "thinking": "The user greets me. ".repeat(4), "signature": "sig"},
+                {"type": "text", "text": "Hello!"},
+            ],
+            "stop_reason": "end_turn",
+            "usage": {
+                "input_tokens": 12,
+                "cache_read_input_tokens": 1800,
+                "cache_creation_input_tokens": 200,
+                "output_tokens": 40,
+            },
+        });
+        let response = bedrock_to_openai(&cached, "claude-sonnet-4-20250514", &state);
+        let usage = serde_json::to_value(&response.usage).unwrap();
+        // prompt_tokens counts cache reads and writes too, as OpenAI's does
+        assert_eq!(usage["prompt_tokens"], 2012);
+        assert_eq!(usage["completion_tokens"], 40);
+        assert_eq!(usage["total_tokens"], 2052);
+        assert_eq!(
+            usage["prompt_tokens_details"],
+            json!({"cached_tokens": 1800})
+        );
+        // 80 characters of thinking at ~4 per token
+        assert_eq!(
+            usage["completion_tokens_details"],
+            json!({"reasoning_tokens": 20})
+        );
+
+        let uncached = json!({
+            "content": [{"type": "text", "text": "Hello!"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 12, "output_tokens": 3},
+        });
+        let response = bedrock_to_openai(&uncached, "claude-sonnet-4-20250514", &state);
+        let usage = serde_json::to_value(&response.usage).unwrap();
+        assert!(usage.get("prompt_tokens_details").is_none());
+        assert!(usage.get("completion_tokens_details").is_none());
+    }
 }